@@ -1,64 +1,428 @@
-use std::fs;
-use std::io::{self, Read};
-use std::path::Path;
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use crate::cli::{Config, resolve_use_color};
-use crate::fs_walk::collect_files;
-use crate::regex::parse_regex;
-use crate::search::process_input;
+use crate::cache::MissCache;
+use crate::cli::{Config, StatsFormat, resolve_use_color};
+use crate::compare::compare_engines;
+use crate::fs_walk::{is_broken_link, walk_files};
+use crate::hex::process_bytes;
+use crate::index::{Index, Trigram, required_trigrams};
+use crate::output::display_path;
+use crate::pager::Pager;
+use crate::parallel::search_parallel;
+use crate::printer::{self, Printer};
+use crate::reader::read_bytes;
+use crate::regex::fuzzy::FuzzyPattern;
+use crate::regex::parser::parse_regex_with;
+use crate::regex::{DEFAULT_NESTING_LIMIT, ParseOptions, Regex, RegexSet, check_nesting};
+use crate::search;
+use crate::stats::Stats;
+use crate::watch::watch;
+
+// A path argument that stands for standard input, as in grep
+const STDIN_PATH: &str = "-";
 
 pub fn run(cfg: Config) -> i32 {
+    // Color is decided before stdout may be handed to the pager
     let use_color = resolve_use_color(&cfg.color);
+    let pager = Pager::start(&cfg);
+    let code = search(&cfg, use_color);
+    if let Some(pager) = pager {
+        pager.finish();
+    }
+    code
+}
 
-    let pattern_for_parser = if cfg.anchored {
-        &cfg.pattern[1..]
-    } else {
-        &cfg.pattern[..]
+fn search(cfg: &Config, use_color: bool) -> i32 {
+    let parse_opts = ParseOptions {
+        graphemes: cfg.graphemes,
+        multiline: cfg.multiline,
+        dotall: cfg.multiline_dotall,
+        ascii: cfg.no_unicode,
+        fuzzy: cfg.fuzzy,
+        whole_word: cfg.whole_word,
+        whole_line: cfg.whole_line,
+        word_chars: cfg.word_chars.clone(),
+        case_insensitive: cfg.ignore_case,
+        nesting_limit: cfg.max_nesting,
+    };
+    let nesting_limit = cfg.max_nesting.unwrap_or(DEFAULT_NESTING_LIMIT);
+    for p in cfg
+        .patterns
+        .iter()
+        .chain(&cfg.path_regexp)
+        .chain(&cfg.join_continuations)
+        .chain(&cfg.after_context_until)
+        .chain(&cfg.before_context_until)
+    {
+        if let Err(e) = check_nesting(p, nesting_limit) {
+            eprintln!("rust-grep: {e}; raise it with --max-nesting");
+            return 2;
+        }
+        if let Err(e) = parse_regex_with(p, &parse_opts) {
+            eprintln!("rust-grep: invalid pattern {p}: {e}");
+            return 2;
+        }
+    }
+    if let Some(k) = cfg.fuzzy
+        && let Some(p) = cfg
+            .patterns
+            .iter()
+            .find(|p| FuzzyPattern::new(p, k, &parse_opts).is_none())
+    {
+        eprintln!("rust-grep: --fuzzy supports only literals, classes and `.` (up to 64): {p}");
+        return 2;
+    }
+    let regexes = RegexSet::new(&cfg.patterns, &parse_opts).expect("patterns were checked");
+    // --compare-engines: the same patterns on the backtracking matcher alone
+    let reference = cfg.compare_engines.then(|| {
+        RegexSet::backtracking(&cfg.patterns, &parse_opts).expect("patterns were checked")
+    });
+    let mut disagreements = 0;
+    // -w, -x and -i are about the searched text, not paths
+    let path_opts = ParseOptions {
+        whole_word: false,
+        whole_line: false,
+        case_insensitive: false,
+        ..parse_opts.clone()
     };
-    let tokens = parse_regex(pattern_for_parser);
+    let path_filter = cfg
+        .path_regexp
+        .as_deref()
+        .map(|p| Regex::new(p, &path_opts).expect("patterns were checked"));
 
-    let mut global_matched = false;
+    let searcher = search::for_config(cfg);
+    let started = Instant::now();
+    let mut stats = Stats::new(regexes.len());
 
     if cfg.paths.is_empty() {
-        let mut buffer = String::new();
-        io::stdin().read_to_string(&mut buffer).unwrap();
-        process_input(
-            &buffer,
-            &tokens,
+        let mut printer = printer::for_config(cfg, &regexes, use_color, false);
+        if let Some(needle) = &cfg.hex {
+            let mut data = Vec::new();
+            if let Err(e) = io::stdin().lock().read_to_end(&mut data) {
+                report(Path::new("(standard input)"), &e);
+                return 2;
+            }
+            process_bytes(&data, needle, None, cfg, use_color, &mut stats, false);
+            return finish(cfg, printer.as_mut(), &stats, started);
+        }
+        // --threads: a -U match may cross chunk boundaries, so it's never
+        // split, and newlines can't be found before the encoding is known.
+        // A joined record or a stanza of context could also straddle two
+        // chunks, and -m counts across them.
+        if cfg.threads > 1
+            && !cfg.multiline
+            && !cfg.auto_encoding
+            && cfg.join_continuations.is_none()
+            && cfg.after_context.is_none()
+            && cfg.before_context.is_none()
+            && cfg.after_context_until.is_none()
+            && cfg.before_context_until.is_none()
+            && cfg.max_count.is_none()
+        {
+            let stdin = io::stdin().lock();
+            if let Err(e) = search_parallel(
+                stdin,
+                &regexes,
+                &searcher,
+                cfg.threads,
+                printer.as_mut(),
+                &mut stats,
+            ) {
+                report(Path::new("(standard input)"), &e);
+                return 2;
+            }
+            return finish(cfg, printer.as_mut(), &stats, started);
+        }
+        if let Some(reference) = &reference {
+            let buffer = match searcher.read(io::stdin().lock()) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    report(Path::new("(standard input)"), &e);
+                    return 2;
+                }
+            };
+            searcher.search(&buffer, &regexes, None, printer.as_mut(), &mut stats);
+            disagreements += compare_engines(&buffer, &regexes, reference, &searcher, None);
+        } else if let Err(e) = searcher.search_reader(
+            io::stdin().lock(),
+            &regexes,
             None,
-            cfg.use_o,
-            use_color,
-            &mut global_matched,
-            cfg.anchored,
-            false,
-        );
-        return if global_matched { 0 } else { 1 };
+            printer.as_mut(),
+            &mut stats,
+        ) {
+            report(Path::new("(standard input)"), &e);
+            return 2;
+        }
+        let code = finish(cfg, printer.as_mut(), &stats, started);
+        return if disagreements > 0 { 2 } else { code };
     }
 
     // expand input paths to concrete files
+    let required: Vec<_> = cfg
+        .patterns
+        .iter()
+        .map(|pattern| required_trigrams(pattern, &parse_opts))
+        .collect();
+    // Set once anything couldn't be searched, which makes the exit status 2
+    let failed = Cell::new(false);
+    let mut roots = cfg
+        .paths
+        .iter()
+        .map(|p| root_files(cfg, p, &required, &failed));
+    // -q prints nothing, so it searches each root as soon as it's walked and
+    // the first match spares walking the rest
     let mut files = Vec::new();
-    for p in &cfg.paths {
-        files.extend(collect_files(Path::new(p), cfg.recursive));
+    if !cfg.quiet {
+        for found in roots.by_ref() {
+            let Some(found) = found else {
+                return 2;
+            };
+            files.extend(found);
+        }
+        files.retain(|f| path_matches(path_filter.as_ref(), f));
     }
 
     // mimic your old behavior: recursive always shows prefix; otherwise only when multiple files
     let show_filename = cfg.recursive || files.len() > 1;
 
-    for path in files {
-        if let Ok(content) = fs::read_to_string(&path) {
-            let name = path.to_string_lossy();
-            process_input(
-                &content,
-                &tokens,
-                Some(name.as_ref()),
-                cfg.use_o,
-                use_color,
-                &mut global_matched,
-                cfg.anchored,
-                show_filename,
+    let mut printer = printer::for_config(cfg, &regexes, use_color, show_filename);
+
+    if cfg.watch {
+        watch(
+            cfg,
+            &regexes,
+            path_filter.as_ref(),
+            use_color,
+            show_filename,
+        );
+    }
+
+    // --cache: keyed by everything that decides whether a file matches
+    let mut cache = cfg
+        .cache
+        .then(|| {
+            let query = format!(
+                "{:?}",
+                (
+                    &cfg.patterns,
+                    &parse_opts,
+                    &cfg.hex,
+                    cfg.invert_match,
+                    cfg.text,
+                    cfg.skip_binary,
+                    cfg.auto_encoding,
+                )
             );
+            MissCache::load(&query)
+        })
+        .flatten();
+
+    let mut strict_failed = false;
+    let walked = roots
+        .map_while(|found| {
+            strict_failed |= found.is_none();
+            found
+        })
+        .flatten()
+        .filter(|f| path_matches(path_filter.as_ref(), f));
+    for path in files.into_iter().chain(walked) {
+        if cfg.cancel.is_cancelled()
+            || cfg
+                .max_files_with_matches
+                .is_some_and(|max| stats.files_matched >= max)
+        {
+            break;
         }
+        // `-` reads standard input in its place among the paths
+        let stdin = path == Path::new(STDIN_PATH);
+        let name = if stdin {
+            Path::new("(standard input)")
+        } else {
+            &path
+        };
+        // -L lists known misses, so it still has to visit them
+        if !stdin
+            && !cfg.files_without_match
+            && cache.as_ref().is_some_and(|c| c.is_known_miss(&path))
+        {
+            stats.files_skipped += 1;
+            continue;
+        }
+        let matched_before = stats.files_matched;
+        let searched = if let Some(needle) = &cfg.hex {
+            open_input(&path).and_then(read_bytes).map(|data| {
+                process_bytes(
+                    &data,
+                    needle,
+                    Some(name),
+                    cfg,
+                    use_color,
+                    &mut stats,
+                    show_filename,
+                )
+            })
+        } else if let Some(reference) = &reference {
+            open_input(&path)
+                .and_then(|input| searcher.read(input))
+                .map(|content| {
+                    searcher.search(&content, &regexes, Some(name), printer.as_mut(), &mut stats);
+                    disagreements +=
+                        compare_engines(&content, &regexes, reference, &searcher, Some(name));
+                })
+        } else if stdin {
+            let stdin = io::stdin().lock();
+            searcher.search_reader(stdin, &regexes, Some(name), printer.as_mut(), &mut stats)
+        } else {
+            searcher.search_path(&path, &regexes, printer.as_mut(), &mut stats)
+        };
+        if let Err(e) = &searched {
+            report(name, e);
+            // --strict: an unreadable or undecodable file ends the run
+            if cfg.strict {
+                return 2;
+            }
+            stats.files_skipped += 1;
+            failed.set(true);
+        }
+        // A cancelled search may have stopped short of a match
+        if let Some(cache) = &mut cache
+            && !stdin
+            && searched.is_ok()
+            && !cfg.cancel.is_cancelled()
+        {
+            cache.record(&path, stats.files_matched > matched_before);
+        }
+        // -q: one match anywhere settles the exit status
+        if cfg.quiet && stats.matched() {
+            break;
+        }
+    }
+    if strict_failed {
+        return 2;
+    }
+    if let Some(cache) = &cache {
+        let _ = cache.save();
     }
 
-    if global_matched { 0 } else { 1 }
+    let code = finish(cfg, printer.as_mut(), &stats, started);
+    // As in grep, an error makes the status 2 unless -q found a match anyway
+    if disagreements > 0 || failed.get() && !(cfg.quiet && stats.matched()) {
+        2
+    } else {
+        code
+    }
+}
+
+fn write_stats(
+    out: &mut dyn Write,
+    format: StatsFormat,
+    cfg: &Config,
+    stats: &Stats,
+    elapsed: Duration,
+) -> io::Result<()> {
+    match format {
+        StatsFormat::Text => stats.write_text(out, &cfg.labels, elapsed),
+        StatsFormat::Json => writeln!(out, "{}", stats.to_json(&cfg.labels, elapsed)),
+    }
+}
+
+// The files to search under `root`, after reporting what can't be read
+// (and setting `failed`); None when --strict ends the run
+fn root_files(
+    cfg: &Config,
+    root: &Path,
+    required: &[Vec<Trigram>],
+    failed: &Cell<bool>,
+) -> Option<Vec<PathBuf>> {
+    if root == Path::new(STDIN_PATH) {
+        return Some(vec![root.to_path_buf()]);
+    }
+    let walked = walk_files(
+        root,
+        cfg.recursive,
+        cfg.follow_links,
+        cfg.devices,
+        &cfg.globs,
+        &cfg.cancel,
+    );
+    for (path, e) in &walked.errors {
+        report(path, e);
+        failed.set(true);
+    }
+    if cfg.strict && !walked.errors.is_empty() {
+        return None;
+    }
+    let mut found = walked.files;
+    // --use-index: skip files the root's trigram index rules out. It holds
+    // trigrams of the raw bytes, which say nothing about a UTF-16 file's
+    // text, so --encoding=auto searches everything.
+    if cfg.use_index
+        && !cfg.auto_encoding
+        && let Some(index) = Index::load(root)
+    {
+        found.retain(|f| index.is_candidate(root, f, required));
+    }
+    Some(found)
+}
+
+// Says why `path` couldn't be searched, as grep does:
+// `rust-grep: foo.txt: Permission denied`
+pub fn report(path: &Path, e: &io::Error) {
+    let reason = if is_broken_link(path) {
+        "broken symbolic link".to_string()
+    } else if e.kind() == io::ErrorKind::InvalidData {
+        "not valid UTF-8 (search it with -a or --encoding=auto)".to_string()
+    } else {
+        // Without the ` (os error 13)` the message ends with
+        let message = e.to_string();
+        message
+            .split(" (os error")
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    };
+    eprintln!("rust-grep: {}: {reason}", display_path(path));
+}
+
+// The file at `path`, or standard input for `-`
+fn open_input(path: &Path) -> io::Result<Box<dyn Read>> {
+    if path == Path::new(STDIN_PATH) {
+        return Ok(Box::new(io::stdin().lock()));
+    }
+    Ok(Box::new(File::open(path)?))
+}
+
+// --path-regexp: the path as it would be printed, relative to the given root
+pub fn path_matches(path_filter: Option<&Regex>, path: &Path) -> bool {
+    path_filter.is_none_or(|re| re.is_match(&display_path(path)))
+}
+
+fn finish(cfg: &Config, printer: &mut dyn Printer, stats: &Stats, started: Instant) -> i32 {
+    printer.finish();
+    if let Some(format) = cfg.stats {
+        let elapsed = started.elapsed();
+        match &cfg.stats_file {
+            Some(file) => {
+                if let Err(e) = File::create(file)
+                    .and_then(|mut out| write_stats(&mut out, format, cfg, stats, elapsed))
+                {
+                    eprintln!("rust-grep: {}: {e}", file.display());
+                }
+            }
+            None => {
+                let _ = write_stats(&mut io::stderr(), format, cfg, stats, elapsed);
+            }
+        }
+    }
+    // -L succeeds when it lists a file
+    let success = if cfg.files_without_match {
+        stats.files_searched > stats.files_matched
+    } else {
+        stats.matched()
+    };
+    if success { 0 } else { 1 }
 }
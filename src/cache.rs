@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::index::mtime;
+
+type Stamp = ((u64, u32), u64); // (mtime, size)
+
+// --cache: remembers which files a query found nothing in, keyed by each
+// file's (path, size, mtime), so repeating the query over a mostly unchanged
+// tree skips them. Files that matched are searched again every time. There is
+// one cache file per query, named after a hash of everything that decides
+// whether a file matches.
+pub struct MissCache {
+    file: PathBuf,
+    misses: HashMap<String, Stamp>,
+    changed: bool,
+}
+
+impl MissCache {
+    // `query` is hashed into the file name; None when there's nowhere to
+    // keep the cache
+    pub fn load(query: &impl Hash) -> Option<MissCache> {
+        let mut hasher = DefaultHasher::new();
+        query.hash(&mut hasher);
+        let file = cache_dir()?.join(format!("{:016x}", hasher.finish()));
+        let text = fs::read_to_string(&file).unwrap_or_default();
+        Some(MissCache {
+            file,
+            misses: text.lines().filter_map(parse_entry).collect(),
+            changed: false,
+        })
+    }
+
+    // Whether `path` didn't match last time and hasn't changed since
+    pub fn is_known_miss(&self, path: &Path) -> bool {
+        let Some(key) = path.to_str() else {
+            return false;
+        };
+        self.misses
+            .get(key)
+            .is_some_and(|&old| stamp(path) == Some(old))
+    }
+
+    pub fn record(&mut self, path: &Path, matched: bool) {
+        let Some(key) = path.to_str() else {
+            return;
+        };
+        let stamp = stamp(path).filter(|_| !matched);
+        let old = match stamp {
+            Some(stamp) => self.misses.insert(key.to_string(), stamp),
+            None => self.misses.remove(key),
+        };
+        self.changed |= old != stamp;
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        let mut out = String::new();
+        for (path, ((secs, nanos), len)) in &self.misses {
+            out.push_str(&format!("{secs}.{nanos} {len} {path}\n"));
+        }
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.file, out)
+    }
+}
+
+// `RUST_GREP_CACHE_DIR`, else `rust-grep` under the user's cache directory
+fn cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("RUST_GREP_CACHE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(base.join("rust-grep"))
+}
+
+fn stamp(path: &Path) -> Option<Stamp> {
+    let meta = fs::metadata(path).ok()?;
+    Some((mtime(&meta), meta.len()))
+}
+
+// `SECS.NANOS LEN PATH`
+fn parse_entry(line: &str) -> Option<(String, Stamp)> {
+    let mut parts = line.splitn(3, ' ');
+    let (secs, nanos) = parts.next()?.split_once('.')?;
+    let len = parts.next()?.parse().ok()?;
+    let path = parts.next()?.to_string();
+    Some((path, ((secs.parse().ok()?, nanos.parse().ok()?), len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MissCache;
+    use std::collections::HashMap;
+    use std::fs;
+
+    #[test]
+    fn skips_unchanged_misses_only() {
+        let dir = std::env::temp_dir().join(format!("rust-grep-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (hit, miss) = (dir.join("hit.txt"), dir.join("miss.txt"));
+        fs::write(&hit, "needle\n").unwrap();
+        fs::write(&miss, "hay\n").unwrap();
+
+        let mut cache = MissCache {
+            file: dir.join("cache"),
+            misses: HashMap::new(),
+            changed: false,
+        };
+        cache.record(&hit, true);
+        cache.record(&miss, false);
+        cache.save().unwrap();
+        let text = fs::read_to_string(dir.join("cache")).unwrap();
+        let cache = MissCache {
+            misses: text.lines().filter_map(super::parse_entry).collect(),
+            ..cache
+        };
+        assert!(cache.is_known_miss(&miss));
+        assert!(!cache.is_known_miss(&hit));
+
+        fs::write(&miss, "hay and more hay\n").unwrap();
+        assert!(!cache.is_known_miss(&miss));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,48 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Lets an embedding application abort a search from another thread, e.g. when
+// the user types a new query. Clones share the flag. Searching and walking
+// check it between lines and directory entries and stop early; results
+// gathered up to that point are kept.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CancelToken;
+    use crate::cli::Config;
+    use crate::regex::{ParseOptions, RegexSet};
+    use crate::search::for_config;
+
+    #[test]
+    fn cancelled_search_stops_early() {
+        let patterns = vec!["a".to_string()];
+        let regexes = RegexSet::new(&patterns, &ParseOptions::default()).unwrap();
+        let cfg = Config {
+            patterns,
+            cancel: CancelToken::new(),
+            ..Default::default()
+        };
+        let searcher = for_config(&cfg);
+        assert_eq!(searcher.find_matches("a\na\n", &regexes).len(), 2);
+
+        cfg.cancel.clone().cancel();
+        assert!(cfg.cancel.is_cancelled());
+        assert!(searcher.find_matches("a\na\n", &regexes).is_empty());
+    }
+}
@@ -0,0 +1,130 @@
+// Curated patterns for --pattern-name. Written against the syntax this engine
+// supports, so classes spell out their members instead of using ranges.
+
+macro_rules! hex {
+    () => {
+        "[0123456789abcdefABCDEF]"
+    };
+}
+macro_rules! alnum {
+    () => {
+        "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789"
+    };
+}
+
+pub struct NamedPattern {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub pattern: &'static str,
+}
+
+#[rustfmt::skip]
+pub const CATALOG: &[NamedPattern] = &[
+    NamedPattern {
+        name: "email",
+        description: "email address",
+        pattern: concat!(
+            "[", alnum!(), "._%+-]+@[", alnum!(), "-]+",
+            "(\\.[", alnum!(), "-]+)*\\.[", alnum!(), "]{2,}"
+        ),
+    },
+    NamedPattern {
+        name: "ipv4",
+        description: "dotted-quad IPv4 address",
+        pattern: concat!(
+            "(25[012345]|2[01234]\\d|1\\d\\d|[123456789]\\d|\\d)",
+            "(\\.(25[012345]|2[01234]\\d|1\\d\\d|[123456789]\\d|\\d)){3}"
+        ),
+    },
+    NamedPattern {
+        name: "ipv6",
+        description: "IPv6 address, full or :: compressed",
+        pattern: concat!(
+            "((", hex!(), "{1,4}:){7}", hex!(), "{1,4}",
+            "|(", hex!(), "{1,4}:)*", hex!(), "{1,4}::(", hex!(), "{1,4}:)*", hex!(), "{1,4}",
+            "|::(", hex!(), "{1,4}:)*", hex!(), "{1,4})"
+        ),
+    },
+    NamedPattern {
+        name: "uuid",
+        description: "8-4-4-4-12 hex UUID",
+        pattern: concat!(
+            hex!(), "{8}-", hex!(), "{4}-", hex!(), "{4}-", hex!(), "{4}-", hex!(), "{12}"
+        ),
+    },
+    NamedPattern {
+        name: "url",
+        description: "http, https or ftp URL",
+        pattern: "(https?|ftp)://[^ \t\"'<>()]+",
+    },
+    NamedPattern {
+        name: "iso-date",
+        description: "ISO 8601 calendar date (YYYY-MM-DD)",
+        pattern: "\\d{4}-(0[123456789]|1[012])-(0[123456789]|[12]\\d|3[01])",
+    },
+];
+
+pub fn lookup(name: &str) -> Option<&'static NamedPattern> {
+    CATALOG.iter().find(|p| p.name == name)
+}
+
+pub fn print_catalog() {
+    println!("Available pattern names:");
+    for p in CATALOG {
+        println!("  {:<10} {}", p.name, p.description);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lookup;
+    use crate::regex::ParseOptions;
+    use crate::regex::pattern::Regex;
+
+    // Whole-string match, so partial hits don't count as passing
+    fn matches(name: &str, text: &str) -> bool {
+        let re = Regex::new(lookup(name).unwrap().pattern, &ParseOptions::default()).unwrap();
+        re.captures_at(text, 0)
+            .is_some_and(|(m, _)| m.len() == text.len())
+    }
+
+    #[test]
+    fn email() {
+        assert!(matches("email", "jane.doe+tag@mail.example.com"));
+        assert!(!matches("email", "jane@localhost"));
+    }
+
+    #[test]
+    fn ipv4() {
+        assert!(matches("ipv4", "192.168.0.255"));
+        assert!(!matches("ipv4", "256.1.1.1"));
+        assert!(!matches("ipv4", "1.2.3"));
+    }
+
+    #[test]
+    fn ipv6() {
+        assert!(matches("ipv6", "2001:0db8:85a3:0000:0000:8a2e:0370:7334"));
+        assert!(matches("ipv6", "fe80::1"));
+        assert!(matches("ipv6", "::1"));
+        assert!(!matches("ipv6", "12345::1"));
+    }
+
+    #[test]
+    fn uuid() {
+        assert!(matches("uuid", "123e4567-e89b-12d3-a456-426614174000"));
+        assert!(!matches("uuid", "123e4567-e89b-12d3-a456-42661417400"));
+    }
+
+    #[test]
+    fn url() {
+        assert!(matches("url", "https://example.com/a?b=c"));
+        assert!(!matches("url", "mailto:someone@example.com"));
+    }
+
+    #[test]
+    fn iso_date() {
+        assert!(matches("iso-date", "2024-02-29"));
+        assert!(!matches("iso-date", "2024-13-01"));
+        assert!(!matches("iso-date", "2024-00-10"));
+    }
+}
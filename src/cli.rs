@@ -1,53 +1,1049 @@
+use std::ffi::{OsStr, OsString};
+use std::fs;
 use std::io;
 use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::process;
 
-#[derive(Debug, Clone)]
+use crate::cancel::CancelToken;
+use crate::catalog::{lookup, print_catalog};
+use crate::file_types;
+use crate::glob::{FileGlobs, expand};
+use crate::hex::parse_hex;
+use crate::regex::dialect::{ALTERNATIVES, POSSESSIVE, perl_syntax};
+use crate::regex::escape;
+
+#[derive(Debug, Clone, Default)]
 pub enum ColorWhen {
     Always,
+    #[default]
     Never,
     Auto,
 }
 
-#[derive(Debug, Clone)]
+// -D: what to do with FIFOs, sockets and device nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceAction {
+    Read,
+    Skip,
+}
+
+// --format: how results are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+    Sarif,
+    Github,
+}
+
+// --column-unit: what a column counts on lines with multibyte chars
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnUnit {
+    Bytes,
+    Chars,
+}
+
+// --stats: a human-readable summary or one JSON object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    Text,
+    Json,
+}
+
+// --offsets: report match spans relative to the line or to the whole input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetBase {
+    Line,
+    File,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Config {
-    pub pattern: String,
-    pub anchored: bool,
+    pub patterns: Vec<String>,
+    pub labels: Vec<String>, // one per pattern: its index, catalog name or a user name
+    pub pattern_label: bool,
+    pub stats: Option<StatsFormat>,
+    pub stats_file: Option<PathBuf>, // --stats-file: written there instead of stderr
     pub use_o: bool,
+    pub no_pager: bool,
+    pub files_with_matches: bool,
+    pub files_without_match: bool,             // -L
+    pub max_files_with_matches: Option<usize>, // stop once this many files matched
+    pub quiet: bool,
+    pub invert_match: bool,
+    pub count: bool,
+    pub group_by: Option<usize>, // capture group whose values are counted; 0 is the whole match
+    pub trim: bool,
+    pub replace: Option<String>, // --replace: the template printed in place of each match
+    pub text: bool,
+    pub skip_binary: bool,   // -I: files that look binary aren't searched
+    pub auto_encoding: bool, // --encoding=auto
+    pub no_mmap: bool,       // read large files onto the heap instead of mapping them
+    pub hexdump: bool,
+    pub hex: Option<Vec<u8>>, // raw bytes to search for instead of patterns (--hex)
+    pub max_matches_per_line: Option<usize>,
+    pub max_count: Option<usize>, // -m: selected lines per input
+    pub fuzzy: Option<usize>,
+    pub max_nesting: Option<usize>, // how deep groups may nest; see DEFAULT_NESTING_LIMIT
+    pub threads: usize,             // workers for a piped stdin; 0 or 1 searches it on this thread
+    pub whole_word: bool,
+    pub whole_line: bool,
+    pub ignore_case: bool,
+    pub line_number: bool, // -n
+    pub byte_offset: bool, // -b
+    pub heading: bool,     // file names on a line above their matches, not on each line
+    pub word_chars: Option<String>,
+    pub offsets: Option<OffsetBase>,
+    pub column: Option<ColumnUnit>,
+    pub format: OutputFormat,
+    pub sort_by_count: bool,        // --sort=count
+    pub dir_summary: Option<usize>, // --summary=dir, with the depth to roll up to
     pub recursive: bool,
+    pub follow_links: bool, // -R: follow symlinks met while recursing
+    pub use_index: bool,
+    pub cache: bool,
+    pub compare_engines: bool,
+    pub strict: bool, // exit 2 on the first file that can't be read
+    pub graphemes: bool,
+    pub no_unicode: bool,
+    pub watch: bool,
+    pub multiline: bool,
+    pub multiline_dotall: bool,
+    pub devices: Option<DeviceAction>, // None: read explicit args, skip while recursing
     pub color: ColorWhen,
-    pub paths: Vec<String>,
+    pub paths: Vec<PathBuf>,
+    pub path_regexp: Option<String>,
+    pub globs: FileGlobs,                     // --include / --exclude / --type
+    pub join_continuations: Option<String>,   // lines matching it are merged into one record
+    pub after_context: Option<usize>,         // -A
+    pub before_context: Option<usize>,        // -B
+    pub after_context_until: Option<String>,  // context after a match runs to a line matching it
+    pub before_context_until: Option<String>, // context before a match reaches back to one
+    pub cancel: CancelToken,                  // for library callers; the CLI never cancels
 }
 
-pub fn parse_args(args: Vec<String>) -> Config {
-    let use_o = args.iter().any(|a| a == "-o");
-    let recursive = args.iter().any(|a| a == "-r");
+pub fn parse_args(args: Vec<OsString>) -> Config {
+    let args = Args::parse(args.get(1..).unwrap_or_default());
+    if args.flag(&["--help"]) {
+        print!("{USAGE}\n\n{HELP}");
+        process::exit(0);
+    }
+    if args.flag(&["-V", "--version"]) {
+        println!("rust-grep {}", env!("CARGO_PKG_VERSION"));
+        process::exit(0);
+    }
+    let use_o = args.flag(&["-o"]);
+    let no_pager = args.flag(&["--no-pager"]);
+    let files_with_matches = args.flag(&["-l"]);
+    let files_without_match = args.flag(&["-L", "--files-without-match"]);
+    let quiet = args.flag(&["-q"]);
+    let invert_match = args.flag(&["-v", "--invert-match"]);
+    let count = args.flag(&["-c", "--count"]);
+    let trim = args.flag(&["--trim"]);
+    let replace = args
+        .value(&["--replace"])
+        .map(|v| v.to_string_lossy().into_owned());
+    let no_mmap = args.flag(&["--no-mmap"]);
+    let hexdump = args.flag(&["--hexdump"]);
+    let follow_links = args.flag(&["-R", "--dereference-recursive"]);
+    let recursive = follow_links || args.flag(&["-r"]);
+    let use_index = args.flag(&["--use-index"]);
+    let cache = args.flag(&["--cache"]);
+    let strict = args.flag(&["--strict"]);
+    let compare_engines = args.flag(&["--compare-engines"]);
+    let graphemes = args.flag(&["--graphemes"]);
+    let no_unicode = args.flag(&["--no-unicode"]);
+    let whole_word = args.flag(&["-w"]);
+    let whole_line = args.flag(&["-x", "--line-regexp"]);
+    let ignore_case = args.flag(&["-i", "--ignore-case"]);
+    let line_number = args.flag(&["-n", "--line-number"]);
+    let byte_offset = args.flag(&["-b", "--byte-offset"]);
+    // Grouped under headings by default on a terminal, as in ripgrep; decided
+    // before stdout may be handed to the pager
+    let heading = args.toggle("--heading", "--no-heading", io::stdout().is_terminal());
+    let watch = args.flag(&["--watch"]);
+    let multiline = args.flag(&["-U", "--multiline"]);
+    let multiline_dotall = args.flag(&["--multiline-dotall"]);
+
+    let offsets = match args.last(&["--offsets"]).map(|v| v.map(OsStr::to_str)) {
+        None => None,
+        Some(None | Some(Some("line"))) => Some(OffsetBase::Line),
+        Some(Some(Some("file"))) => Some(OffsetBase::File),
+        Some(Some(_)) => {
+            eprintln!("rust-grep: --offsets must be line or file");
+            process::exit(2);
+        }
+    };
+
+    // --column prefixes output with the match's 1-based column, in bytes by
+    // default like vim; --column-unit=chars counts characters instead
+    let column_unit = match args.value(&["--column-unit"]).map(|v| v.to_str()) {
+        None | Some(Some("bytes")) => ColumnUnit::Bytes,
+        Some(Some("chars")) => ColumnUnit::Chars,
+        Some(_) => {
+            eprintln!("rust-grep: --column-unit must be bytes or chars");
+            process::exit(2);
+        }
+    };
+    let column = args.flag(&["--column"]).then_some(column_unit);
 
-    let color = if args.iter().any(|a| a == "--color=always") {
-        ColorWhen::Always
-    } else if args.iter().any(|a| a == "--color=never") {
-        ColorWhen::Never
-    } else {
-        // default grep-ish behavior: never unless asked; you can keep Auto if you want
-        if args.iter().any(|a| a == "--color=auto") {
-            ColorWhen::Auto
-        } else {
-            ColorWhen::Never
+    // --binary-files=TYPE: `binary`, the default, says that a file with a NUL
+    // byte near its start matches without printing its lines; `text` is -a
+    // and `without-match` is -I
+    let binary_files = match args.value(&["--binary-files"]).map(|v| v.to_str()) {
+        None | Some(Some("binary")) => None,
+        Some(Some(kind @ ("text" | "without-match"))) => Some(kind),
+        Some(_) => {
+            eprintln!("rust-grep: --binary-files must be binary, text or without-match");
+            process::exit(2);
         }
     };
+    let text = binary_files == Some("text") || args.flag(&["-a", "--text"]);
+    let skip_binary = !text && (binary_files == Some("without-match") || args.flag(&["-I"]));
+
+    let max_matches_per_line = args.value(&["--max-matches-per-line"]).map(|v| {
+        let n = v.to_str().and_then(|n| n.parse().ok()).filter(|&n| n > 0);
+        n.unwrap_or_else(|| {
+            eprintln!("rust-grep: invalid --max-matches-per-line value");
+            process::exit(2);
+        })
+    });
 
-    let pattern_idx = args.iter().position(|r| r == "-E").expect("Missing -E") + 1;
-    let pattern = args[pattern_idx].clone();
-    let anchored = pattern.starts_with('^');
+    let max_count = args.value(&["-m", "--max-count"]).map(|v| {
+        v.to_str().and_then(|n| n.parse().ok()).unwrap_or_else(|| {
+            eprintln!("rust-grep: invalid --max-count value");
+            process::exit(2);
+        })
+    });
 
-    let paths = args[pattern_idx + 1..].to_vec();
+    let fuzzy = args.value(&["--fuzzy"]).map(|v| {
+        v.to_str().and_then(|n| n.parse().ok()).unwrap_or_else(|| {
+            eprintln!("rust-grep: invalid --fuzzy value");
+            process::exit(2);
+        })
+    });
+
+    let threads = args.value(&["--threads"]).map_or(0, |v| {
+        v.to_str().and_then(|n| n.parse().ok()).unwrap_or_else(|| {
+            eprintln!("rust-grep: invalid --threads value");
+            process::exit(2);
+        })
+    });
+
+    let max_files_with_matches = args.value(&["--max-files-with-matches"]).map(|v| {
+        v.to_str().and_then(|n| n.parse().ok()).unwrap_or_else(|| {
+            eprintln!("rust-grep: invalid --max-files-with-matches value");
+            process::exit(2);
+        })
+    });
+
+    let max_nesting = args.value(&["--max-nesting"]).map(|v| {
+        v.to_str().and_then(|n| n.parse().ok()).unwrap_or_else(|| {
+            eprintln!("rust-grep: invalid --max-nesting value");
+            process::exit(2);
+        })
+    });
+
+    let group_by = args.value(&["--group-by"]).map(|v| {
+        v.to_str().and_then(|n| n.parse().ok()).unwrap_or_else(|| {
+            eprintln!("rust-grep: invalid --group-by value");
+            process::exit(2);
+        })
+    });
+
+    // --word-chars: non-alphanumerics that are part of a word for \b and -w
+    let word_chars = args.value(&["--word-chars"]).map(|v| {
+        v.to_str().map(str::to_string).unwrap_or_else(|| {
+            eprintln!("rust-grep: --word-chars is not valid UTF-8");
+            process::exit(2);
+        })
+    });
+
+    let format = match args.value(&["--format"]).map(|v| v.to_str()) {
+        None | Some(Some("text")) => OutputFormat::Text,
+        Some(Some("json")) => OutputFormat::Json,
+        Some(Some("sarif")) => OutputFormat::Sarif,
+        Some(Some("github")) => OutputFormat::Github,
+        Some(_) => {
+            eprintln!("rust-grep: --format must be text, json, sarif or github");
+            process::exit(2);
+        }
+    };
+    // SARIF and annotations describe matches, so there's nothing to put a
+    // count or a file list in
+    if matches!(format, OutputFormat::Sarif | OutputFormat::Github)
+        && (count || files_with_matches || files_without_match)
+    {
+        eprintln!("rust-grep: --format sarif and github can't be combined with -c, -l or -L");
+        process::exit(2);
+    }
+
+    let sort_by_count = match args.value(&["--sort"]).map(|v| v.to_str()) {
+        None => false,
+        Some(Some("count")) => true,
+        Some(_) => {
+            eprintln!("rust-grep: --sort must be count");
+            process::exit(2);
+        }
+    };
+
+    let dir_summary = match args.value(&["--summary"]).map(|v| v.to_str()) {
+        None => None,
+        Some(Some("dir")) => Some(args.value(&["--summary-depth"]).map_or(2, |v| {
+            v.to_str().and_then(|n| n.parse().ok()).unwrap_or_else(|| {
+                eprintln!("rust-grep: invalid --summary-depth value");
+                process::exit(2);
+            })
+        })),
+        Some(_) => {
+            eprintln!("rust-grep: --summary must be dir");
+            process::exit(2);
+        }
+    };
+
+    let auto_encoding = match args.value(&["--encoding"]).map(|v| v.to_str()) {
+        None | Some(Some("utf-8" | "utf8")) => false,
+        Some(Some("auto")) => true,
+        Some(_) => {
+            eprintln!("rust-grep: --encoding must be auto or utf-8");
+            process::exit(2);
+        }
+    };
+
+    let devices = args.value(&["-D", "--devices"]).map(|v| match v.to_str() {
+        Some("read") => DeviceAction::Read,
+        Some("skip") => DeviceAction::Skip,
+        _ => {
+            eprintln!("rust-grep: unknown devices method");
+            process::exit(2);
+        }
+    });
+
+    // Defaults from RUST_GREP_OPTIONS or the config file come first, so the
+    // last --color wins
+    let color = match args.value(&["--color"]).map(|v| v.to_str()) {
+        // default grep-ish behavior: never unless asked
+        None | Some(Some("never")) => ColorWhen::Never,
+        Some(Some("always")) => ColorWhen::Always,
+        Some(Some("auto")) => ColorWhen::Auto,
+        Some(_) => {
+            eprintln!("rust-grep: --color must be never, always or auto");
+            process::exit(2);
+        }
+    };
+
+    // --hex searches raw bytes instead of patterns
+    let hex = args.value(&["--hex"]).map(|v| {
+        v.to_str().and_then(parse_hex).unwrap_or_else(|| {
+            eprintln!("rust-grep: --hex needs pairs of hex digits, e.g. 'DE AD BE EF'");
+            process::exit(2);
+        })
+    });
+
+    // --type-list shows the types --type and --type-not accept, and needs no
+    // pattern
+    if args.flag(&["--type-list"]) {
+        file_types::print_types();
+        process::exit(0);
+    }
+
+    // Patterns come from -e/--regexp, -f and --pattern-name, in the order
+    // given. Without any of them (or --hex) the first positional argument is
+    // the pattern; the rest are paths.
+    let mut patterns = Vec::new();
+    let mut labels = Vec::new();
+    let mut pattern_given = hex.is_some();
+    for (name, value) in &args.options {
+        let value = value.as_deref().unwrap_or_default();
+        match *name {
+            "-e" | "--regexp" => {
+                patterns.push(pattern_text(value));
+                labels.push(patterns.len().to_string());
+            }
+            "--pattern-name" => {
+                let name = value.to_string_lossy();
+                patterns.push(named_pattern(&name));
+                labels.push(name.into_owned());
+            }
+            "-f" | "--file" => patterns_from_file(value, &mut patterns, &mut labels),
+            _ => continue,
+        }
+        pattern_given = true;
+    }
+    let mut positionals = args.positionals.iter();
+    if !pattern_given {
+        let Some(pattern) = positionals.next() else {
+            eprintln!("rust-grep: no pattern given\n{USAGE}");
+            eprintln!("Try 'rust-grep --help' for more information.");
+            process::exit(2);
+        };
+        patterns.push(pattern_text(pattern));
+        labels.push(patterns.len().to_string());
+    }
+
+    // --stats-file on its own implies JSON, the format meant for files
+    let stats_file = args.value(&["--stats-file"]).map(PathBuf::from);
+    let stats = match args.last(&["--stats"]).map(|v| v.map(OsStr::to_str)) {
+        None => stats_file.as_ref().map(|_| StatsFormat::Json),
+        Some(None | Some(Some("text"))) => Some(StatsFormat::Text),
+        Some(Some(Some("json"))) => Some(StatsFormat::Json),
+        Some(Some(_)) => {
+            eprintln!("rust-grep: --stats must be text or json");
+            process::exit(2);
+        }
+    };
+    // --pattern-label=NAME,NAME,... names the patterns in the order given
+    let pattern_label = args.flag(&["--pattern-label"]);
+    if let Some(names) = args.value(&["--pattern-label"]) {
+        for (label, name) in labels.iter_mut().zip(names.to_string_lossy().split(',')) {
+            *label = name.to_string();
+        }
+    }
+
+    // -P allows the Perl-only syntax this engine has, which is lookbehind
+    let perl = args.flag(&["-P", "--perl-regexp"]);
+    let fixed = args.flag(&["-F", "--fixed-strings"]);
+    if !fixed {
+        patterns.iter().for_each(|p| check_dialect(p, perl));
+    }
+
+    // -F: every pattern is literal text, and one with newlines is a literal
+    // per line. Each piece keeps its pattern's label.
+    if fixed {
+        (patterns, labels) = patterns
+            .iter()
+            .zip(&labels)
+            .flat_map(|(pattern, label)| {
+                pattern
+                    .split('\n')
+                    .map(move |piece| (escape(piece), label.clone()))
+            })
+            .unzip();
+    }
+
+    // --path-regexp filters which files are searched by their path
+    let path_regexp = args.value(&["--path-regexp"]).map(pattern_text);
+
+    // --include, --exclude, --type and --type-not may each be given several
+    // times
+    let mut globs = FileGlobs {
+        include: args.values(&["--include"]),
+        exclude: args.values(&["--exclude"]),
+        types: type_globs(&args, "--type"),
+    };
+    globs.exclude.extend(type_globs(&args, "--type-not"));
+
+    // --join-continuations merges continuation lines into their record
+    let join_continuations = args.value(&["--join-continuations"]).map(pattern_text);
+
+    // -A, -B and -C (both, unless overridden by -A or -B): lines of context
+    let context_lines = |short: &str, long: &str| {
+        args.value(&[short, long]).map(|v| {
+            v.to_str().and_then(|n| n.parse().ok()).unwrap_or_else(|| {
+                eprintln!("rust-grep: invalid {short} value");
+                process::exit(2);
+            })
+        })
+    };
+    let context = context_lines("-C", "--context");
+    let after_context = context_lines("-A", "--after-context").or(context);
+    let before_context = context_lines("-B", "--before-context").or(context);
+
+    // --after-context-until / --before-context-until: context bounded by a
+    // delimiter line instead of a line count
+    let context_until = |long: &str| args.value(&[long]).map(pattern_text);
+    let after_context_until = context_until("--after-context-until");
+    let before_context_until = context_until("--before-context-until");
+    for p in path_regexp
+        .iter()
+        .chain(&join_continuations)
+        .chain(&after_context_until)
+        .chain(&before_context_until)
+    {
+        check_dialect(p, perl);
+    }
+
+    // Paths stay as raw OS strings so non-UTF-8 file names can be addressed.
+    // cmd.exe passes wildcards through, so Windows always expands them.
+    let expand_globs = cfg!(windows) || args.flag(&["--expand-globs"]);
+    let paths = positionals
+        .map(PathBuf::from)
+        .flat_map(|p| if expand_globs { expand(&p) } else { vec![p] })
+        .collect();
 
     Config {
-        pattern,
-        anchored,
+        patterns,
+        labels,
+        pattern_label,
+        stats,
+        stats_file,
         use_o,
+        no_pager,
+        heading,
+        files_with_matches,
+        files_without_match,
+        max_files_with_matches,
+        quiet,
+        invert_match,
+        count,
+        group_by,
+        trim,
+        replace,
+        text,
+        skip_binary,
+        auto_encoding,
+        no_mmap,
+        hexdump,
+        hex,
+        max_matches_per_line,
+        max_count,
+        fuzzy,
+        max_nesting,
+        threads,
+        whole_word,
+        whole_line,
+        ignore_case,
+        line_number,
+        byte_offset,
+        word_chars,
+        offsets,
+        column,
+        format,
+        sort_by_count,
+        dir_summary,
         recursive,
+        follow_links,
+        use_index,
+        cache,
+        strict,
+        compare_engines,
+        graphemes,
+        no_unicode,
+        watch,
+        multiline,
+        multiline_dotall,
+        devices,
         color,
         paths,
+        path_regexp,
+        globs,
+        join_continuations,
+        after_context,
+        before_context,
+        after_context_until,
+        before_context_until,
+        cancel: CancelToken::new(),
+    }
+}
+
+const USAGE: &str = "usage: rust-grep [OPTION]... PATTERN [PATH]...
+       rust-grep [OPTION]... -e PATTERN... [PATH]...";
+
+// --help. Every option in OPTIONS is listed; a test keeps it that way.
+const HELP: &str = "\
+Search PATHs (standard input without any, or for `-`) for lines matching
+PATTERN, a regular expression.
+
+Patterns:
+  -e, --regexp PATTERN       search for PATTERN; repeat for several
+  -E, --extended-regexp      patterns are extended regular expressions (the default)
+  -f, --file FILE            read patterns from FILE, one per line
+  --pattern-name NAME        a built-in pattern (email, ipv4...); `help` lists them
+  -F, --fixed-strings        patterns are literal text
+  -P, --perl-regexp          allow Perl-only syntax (lookbehind)
+  -i, --ignore-case          ignore case
+  -w                         match whole words only
+  -x, --line-regexp          match whole lines only
+  --word-chars CHARS         extra characters that count as word characters
+  -U, --multiline            let matches span lines
+  --multiline-dotall         with -U, `.` matches newlines too
+  --fuzzy N                  allow up to N edits per match
+  --hex BYTES                search for raw bytes such as 'DE AD' instead
+  --graphemes                `.` matches whole grapheme clusters
+  --no-unicode               ASCII-only classes and case folding
+  --max-nesting N            reject groups nested deeper than N (default 200)
+
+Output:
+  -o                         print only the matched parts
+  -v, --invert-match         select lines that don't match
+  -c, --count                print the number of matching lines per input
+  -l                         print only the names of files with a match
+  -L, --files-without-match  print only the names of files without one
+  -q                         print nothing; the exit status tells
+  -n, --line-number          prefix lines with their line number
+  -b, --byte-offset          prefix lines (with -o, matches) with their byte offset
+  --column                   prefix lines with the column of the match
+  --column-unit UNIT         count columns in bytes or chars
+  --offsets[=line|file]      print the byte span of each match
+  -m, --max-count N          stop each input after N selected lines
+  --max-matches-per-line N   report at most N matches per line
+  --max-files-with-matches N stop after N files have matched
+  -A, --after-context N      print N lines after each match
+  -B, --before-context N     print N lines before each match
+  -C, --context N            print N lines on both sides
+  --after-context-until PAT  print lines after a match up to one matching PAT
+  --before-context-until PAT print lines before a match back to one matching PAT
+  --join-continuations PAT   join lines starting with PAT to the one before
+  --color WHEN               never, always or auto
+  --format FORMAT            text, json, sarif or github
+  --pattern-label[=NAMES]    prefix output with the pattern that matched
+  --group-by N               count matches per text of capture group N
+  --sort count               print inputs with the most matches first
+  --summary dir              print match counts per directory
+  --summary-depth N          roll --summary up to N levels (default 2)
+  --trim                     strip leading whitespace from printed lines
+  --replace TEMPLATE         print TEMPLATE in place of matches ($1, ${name})
+  --hexdump                  print matched lines as hexdump rows
+  --stats[=text|json]        print statistics to stderr
+  --stats-file PATH          write the statistics to PATH
+  --heading                  print file names above their lines (default on a terminal)
+  --no-heading               print file names on every line
+  --no-pager                 don't page output on a terminal
+
+Files:
+  -r                         search directories recursively
+  -R, --dereference-recursive  recurse, following symlinks
+  --include GLOB             only search files whose name matches GLOB
+  --exclude GLOB             skip files whose name matches GLOB
+  --type TYPE                only search files of a built-in type
+  --type-not TYPE            skip files of a built-in type
+  --type-list                list the built-in file types
+  --path-regexp PAT          only search files whose path matches PAT
+  -a, --text                 search binary files as text
+  -I                         skip binary files
+  --binary-files TYPE        binary, text or without-match
+  --encoding ENC             utf-8, or auto to detect each file's encoding
+  -D, --devices ACTION       read or skip FIFOs, sockets and devices
+  --expand-globs             expand wildcards in PATHs
+  --no-mmap                  read files instead of mapping them
+  --use-index                skip files a trigram index rules out
+  --cache                    skip files that didn't match last time
+  --strict                   stop at the first unreadable path
+  --watch                    keep reporting matches in appended data
+  --threads N                match standard input on N threads
+
+Other:
+  --compare-engines          check the fast engines against the backtracker
+  --profile NAME             add the options of a config file profile
+  --help                     print this help and exit
+  -V, --version              print the version and exit
+
+Short flags can be bundled (-rn) and values attached (-A3, --color=auto).
+`--` ends the options. Exit status: 0 if something matched, 1 if nothing
+did, 2 on an error.
+";
+
+// A pattern given as an argument; it has to be text to be matched
+fn pattern_text(value: &OsStr) -> String {
+    value.to_str().map(str::to_string).unwrap_or_else(|| {
+        eprintln!("rust-grep: pattern is not valid UTF-8");
+        process::exit(2);
+    })
+}
+
+// Perl-only syntax is an error without -P, and with it too where this engine
+// doesn't have it; then the closest construct it has is suggested
+fn check_dialect(pattern: &str, perl: bool) {
+    if let Some(e) = dialect_error(pattern, perl) {
+        eprintln!("rust-grep: {e}");
+        process::exit(2);
+    }
+}
+
+fn dialect_error(pattern: &str, perl: bool) -> Option<String> {
+    let (found, span) = perl_syntax(pattern)?;
+    if found.supported && perl {
+        return None;
+    }
+    let text = &pattern[span];
+    let what = format!("pattern {pattern} uses {} `{text}`", found.name);
+    if found.supported {
+        return Some(format!("{what}, which needs -P (--perl-regexp)"));
+    }
+    // `a*+` is `a*` that never gives back what it matched
+    let suggestion = match text.strip_suffix('+') {
+        Some(greedy) if found == POSSESSIVE => format!("greedy quantifier `{greedy}`"),
+        _ => {
+            let (name, syntax) = ALTERNATIVES
+                .iter()
+                .min_by_key(|(_, syntax)| edit_distance(text, syntax))
+                .expect("there are alternatives");
+            format!("{name} `{syntax}`")
+        }
+    };
+    Some(format!(
+        "{what}, which isn't supported; did you mean {suggestion}?"
+    ))
+}
+
+// -f FILE: one pattern per line, labelled by position like -e patterns
+fn patterns_from_file(file: &OsStr, patterns: &mut Vec<String>, labels: &mut Vec<String>) {
+    let text = fs::read_to_string(file).unwrap_or_else(|e| {
+        eprintln!("rust-grep: {}: {e}", file.to_string_lossy());
+        process::exit(2);
+    });
+    for line in text.lines() {
+        patterns.push(line.to_string());
+        labels.push(patterns.len().to_string());
+    }
+}
+
+// Every option parse_args understands. Those in VALUE_OPTIONS need a value,
+// attached or as the next argument; those in OPTIONAL_VALUE_OPTIONS only take
+// one attached, as `--name=VALUE`.
+const OPTIONS: &[&str] = &[
+    "-E",
+    "--extended-regexp",
+    "-e",
+    "--regexp",
+    "-f",
+    "--file",
+    "--pattern-name",
+    "--pattern-label",
+    "--stats",
+    "--stats-file",
+    "-o",
+    "-l",
+    "-L",
+    "--files-without-match",
+    "-q",
+    "-v",
+    "--invert-match",
+    "-c",
+    "--count",
+    "-w",
+    "-x",
+    "--line-regexp",
+    "-F",
+    "--fixed-strings",
+    "-P",
+    "--perl-regexp",
+    "-i",
+    "--ignore-case",
+    "-n",
+    "--line-number",
+    "-b",
+    "--byte-offset",
+    "--word-chars",
+    "--offsets",
+    "--column",
+    "--column-unit",
+    "--max-matches-per-line",
+    "-m",
+    "--max-count",
+    "-a",
+    "--text",
+    "-I",
+    "--binary-files",
+    "--hexdump",
+    "--hex",
+    "--fuzzy",
+    "--threads",
+    "--group-by",
+    "--max-files-with-matches",
+    "--max-nesting",
+    "--heading",
+    "--no-heading",
+    "--no-pager",
+    "--no-mmap",
+    "--format",
+    "--sort",
+    "--summary",
+    "--summary-depth",
+    "--encoding",
+    "--trim",
+    "--replace",
+    "-r",
+    "-R",
+    "--dereference-recursive",
+    "--expand-globs",
+    "--use-index",
+    "--cache",
+    "--strict",
+    "--compare-engines",
+    "--path-regexp",
+    "--include",
+    "--exclude",
+    "--type",
+    "--type-not",
+    "--type-list",
+    "--join-continuations",
+    "-A",
+    "--after-context",
+    "-B",
+    "--before-context",
+    "-C",
+    "--context",
+    "--after-context-until",
+    "--before-context-until",
+    "--color",
+    "-D",
+    "--devices",
+    "-U",
+    "--multiline",
+    "--multiline-dotall",
+    "--watch",
+    "--no-unicode",
+    "--graphemes",
+    "--help",
+    "-V",
+    "--version",
+];
+const VALUE_OPTIONS: &[&str] = &[
+    "-e",
+    "--regexp",
+    "-f",
+    "--file",
+    "--pattern-name",
+    "--hex",
+    "--fuzzy",
+    "--group-by",
+    "--max-files-with-matches",
+    "-m",
+    "--max-count",
+    "--max-nesting",
+    "--path-regexp",
+    "--include",
+    "--exclude",
+    "--type",
+    "--type-not",
+    "--join-continuations",
+    "-A",
+    "--after-context",
+    "-B",
+    "--before-context",
+    "-C",
+    "--context",
+    "--after-context-until",
+    "--before-context-until",
+    "-D",
+    "--devices",
+    "--column-unit",
+    "--binary-files",
+    "--max-matches-per-line",
+    "--threads",
+    "--word-chars",
+    "--replace",
+    "--format",
+    "--sort",
+    "--summary",
+    "--summary-depth",
+    "--encoding",
+    "--color",
+    "--stats-file",
+];
+const OPTIONAL_VALUE_OPTIONS: &[&str] = &["--stats", "--offsets", "--pattern-label"];
+
+// The command line after the program name, read the way grep reads it: short
+// flags may be bundled (`-rn`), a value may be attached (`-A3`,
+// `--color=auto`) or be the next argument (`-A 3`, `--color auto`), options
+// may follow positional arguments, and `--` ends the options
+struct Args {
+    options: Vec<(&'static str, Option<OsString>)>, // name as in OPTIONS, value
+    positionals: Vec<OsString>,                     // PATTERN unless -e or the like, then paths
+}
+
+impl Args {
+    // Exits with status 2 on an unknown option or a missing value, suggesting
+    // the closest known option for typos like `--no-uncode`
+    fn parse(args: &[OsString]) -> Args {
+        let mut options = Vec::new();
+        let mut positionals = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let text = arg.to_string_lossy();
+            if text == "--" {
+                positionals.extend(iter.cloned());
+                break;
+            }
+            if let Some(long) = text.strip_prefix("--") {
+                let (name, attached) = match long.split_once('=') {
+                    Some((name, _)) => (name, true),
+                    None => (long, false),
+                };
+                let name = known_option(&text[..name.len() + 2]);
+                let attached = attached.then(|| os_tail(arg, name.len() + 1));
+                let value = match attached {
+                    Some(_)
+                        if !VALUE_OPTIONS.contains(&name)
+                            && !OPTIONAL_VALUE_OPTIONS.contains(&name) =>
+                    {
+                        eprintln!("rust-grep: option '{name}' doesn't take a value");
+                        process::exit(2);
+                    }
+                    None if VALUE_OPTIONS.contains(&name) => Some(next_value(name, &mut iter)),
+                    value => value,
+                };
+                options.push((name, value));
+            } else if text.len() > 1 && text.starts_with('-') {
+                // An option taking a value takes the rest of the argument, or
+                // else the next one
+                for (i, c) in text.char_indices().skip(1) {
+                    let name = known_option(&format!("-{c}"));
+                    if !VALUE_OPTIONS.contains(&name) {
+                        options.push((name, None));
+                        continue;
+                    }
+                    let rest = i + c.len_utf8();
+                    let value = if rest == text.len() {
+                        next_value(name, &mut iter)
+                    } else {
+                        os_tail(arg, rest)
+                    };
+                    options.push((name, Some(value)));
+                    break;
+                }
+            } else {
+                positionals.push(arg.clone());
+            }
+        }
+        Args {
+            options,
+            positionals,
+        }
+    }
+
+    fn flag(&self, names: &[&str]) -> bool {
+        self.options.iter().any(|(name, _)| names.contains(name))
+    }
+
+    // The last of `names` given, so command-line flags override injected
+    // defaults, with its value if it has one
+    fn last(&self, names: &[&str]) -> Option<Option<&OsStr>> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(name, _)| names.contains(name))
+            .map(|(_, value)| value.as_deref())
+    }
+
+    // Whether `on` was given after the last `off`, or `default` if neither was
+    fn toggle(&self, on: &str, off: &str, default: bool) -> bool {
+        self.options
+            .iter()
+            .rev()
+            .find(|(name, _)| [on, off].contains(name))
+            .map_or(default, |(name, _)| *name == on)
+    }
+
+    fn value(&self, names: &[&str]) -> Option<&OsStr> {
+        self.last(names).flatten()
+    }
+
+    // Every value of a repeatable option
+    fn values(&self, names: &[&str]) -> Vec<String> {
+        self.options
+            .iter()
+            .filter(|(name, _)| names.contains(name))
+            .filter_map(|(_, value)| value.as_deref())
+            .map(|value| value.to_string_lossy().into_owned())
+            .collect()
+    }
+}
+
+// Whether the argument after `arg` is its value, as `-e` or `--color` take
+// one, so that argument isn't an option even if it looks like one
+pub fn takes_next_value(arg: &OsStr) -> bool {
+    let text = arg.to_string_lossy();
+    if let Some(long) = text.strip_prefix("--") {
+        return !long.contains('=') && VALUE_OPTIONS.contains(&&text[..]);
+    }
+    let Some(short) = text.strip_prefix('-') else {
+        return false;
+    };
+    // The first option in a bundle that takes a value takes the rest of it
+    let mut chars = short.chars();
+    while let Some(c) = chars.next() {
+        if VALUE_OPTIONS.contains(&&format!("-{c}")[..]) {
+            return chars.as_str().is_empty();
+        }
+    }
+    false
+}
+
+// `arg` from byte `start` on, as the same bytes, so a file name that isn't
+// valid UTF-8 still names the same file. Callers split just past a known
+// option and its `=`, which are ASCII.
+fn os_tail(arg: &OsStr, start: usize) -> OsString {
+    let tail = &arg.as_encoded_bytes()[start..];
+    // SAFETY: the split follows a valid UTF-8 prefix, as from_encoded_bytes
+    // requires
+    unsafe { OsStr::from_encoded_bytes_unchecked(tail) }.to_os_string()
+}
+
+// `name` as listed in OPTIONS; anything else ends the run
+fn known_option(name: &str) -> &'static str {
+    if let Some(known) = OPTIONS.iter().find(|&&known| known == name) {
+        return known;
+    }
+    match closest_option(name) {
+        Some(known) => eprintln!("rust-grep: unknown option '{name}'; did you mean '{known}'?"),
+        None => eprintln!("rust-grep: unknown option '{name}'"),
+    }
+    process::exit(2);
+}
+
+// The argument after an option that needs a value
+fn next_value<'a>(name: &str, iter: &mut impl Iterator<Item = &'a OsString>) -> OsString {
+    iter.next().cloned().unwrap_or_else(|| {
+        eprintln!("rust-grep: option '{name}' needs a value");
+        process::exit(2);
+    })
+}
+
+// The known option within two edits of `name`, if any
+fn closest_option(name: &str) -> Option<&'static str> {
+    OPTIONS
+        .iter()
+        .map(|&known| (edit_distance(name, known), known))
+        .filter(|&(distance, _)| distance <= 2)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, known)| known)
+}
+
+// Levenshtein distance, one row at a time
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// The globs of every file type named with `option`
+fn type_globs(args: &Args, option: &str) -> Vec<String> {
+    let mut globs = Vec::new();
+    for name in args.values(&[option]) {
+        let Some(file_type) = file_types::lookup(&name) else {
+            eprintln!("rust-grep: unknown file type '{name}' (see --type-list)");
+            process::exit(2);
+        };
+        globs.extend(file_type.globs.iter().map(|g| g.to_string()));
+    }
+    globs
+}
+
+// --pattern-name: a pattern from the built-in catalog, or the list for `help`
+fn named_pattern(name: &str) -> String {
+    if name == "help" {
+        print_catalog();
+        process::exit(0);
+    }
+    match lookup(name) {
+        Some(p) => p.pattern.to_string(),
+        None => {
+            eprintln!("rust-grep: unknown pattern name '{name}' (try --pattern-name help)");
+            process::exit(2);
+        }
     }
 }
 
@@ -58,3 +1054,131 @@ pub fn resolve_use_color(color: &ColorWhen) -> bool {
         ColorWhen::Auto => io::stdout().is_terminal(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Args, HELP, OPTIONS, closest_option, dialect_error, edit_distance, parse_args};
+
+    #[test]
+    fn help_lists_every_option() {
+        for option in OPTIONS {
+            let listed = HELP.lines().any(|line| {
+                line.trim_start()
+                    .split([',', ' ', '['])
+                    .filter(|word| !word.is_empty())
+                    .take_while(|word| word.starts_with('-'))
+                    .any(|word| word == *option)
+            });
+            assert!(listed, "{option} is missing from --help");
+        }
+    }
+
+    #[test]
+    fn suggests_options_close_to_a_typo() {
+        assert_eq!(edit_distance("--stast", "--stats"), 2);
+        assert_eq!(closest_option("--no-uncode"), Some("--no-unicode"));
+        assert_eq!(closest_option("--multilne"), Some("--multiline"));
+        assert_eq!(closest_option("--ignore-case"), Some("--ignore-case"));
+        assert_eq!(closest_option("--frobnicate"), None);
+    }
+
+    #[test]
+    fn collects_patterns_from_every_pattern_option() {
+        let args = [
+            "rust-grep",
+            "-e",
+            "foo",
+            "--regexp",
+            "bar",
+            "--regexp=baz",
+            "a.txt",
+        ];
+        let cfg = parse_args(args.iter().map(Into::into).collect());
+        assert_eq!(cfg.patterns, ["foo", "bar", "baz"]);
+        assert_eq!(cfg.paths, [std::path::PathBuf::from("a.txt")]);
+    }
+
+    #[test]
+    fn reads_bundled_flags_values_and_a_positional_pattern() {
+        let args = [
+            "rust-grep",
+            "-rnA3",
+            "foo",
+            "src",
+            "--color",
+            "always",
+            "-m2",
+            "--",
+            "-x",
+        ];
+        let cfg = parse_args(args.iter().map(Into::into).collect());
+        assert_eq!(cfg.patterns, ["foo"]);
+        assert_eq!(cfg.paths, ["src", "-x"].map(std::path::PathBuf::from));
+        assert!(cfg.recursive && cfg.line_number && !cfg.whole_line);
+        assert_eq!((cfg.after_context, cfg.max_count), (Some(3), Some(2)));
+        assert!(matches!(cfg.color, super::ColorWhen::Always));
+
+        // With -e the first positional argument is a path, and a value may
+        // look like an option
+        let args = ["rust-grep", "a.txt", "-e", "-r", "--regexp=-n"];
+        let cfg = parse_args(args.iter().map(Into::into).collect());
+        assert_eq!(cfg.patterns, ["-r", "-n"]);
+        assert_eq!(cfg.paths, [std::path::PathBuf::from("a.txt")]);
+        assert!(!cfg.recursive && !cfg.line_number);
+
+        // -E is only a flag, since extended syntax is the only dialect, so it
+        // bundles like one
+        let args = ["rust-grep", "-Ei", "foo", "a.txt"];
+        let cfg = parse_args(args.iter().map(Into::into).collect());
+        assert_eq!(cfg.patterns, ["foo"]);
+        assert_eq!(cfg.paths, [std::path::PathBuf::from("a.txt")]);
+        assert!(cfg.ignore_case);
+    }
+
+    #[test]
+    fn perl_only_syntax_needs_dash_p_or_gets_a_suggestion() {
+        assert_eq!(
+            dialect_error("(?<=a)x", false).unwrap(),
+            "pattern (?<=a)x uses lookbehind `(?<=`, which needs -P (--perl-regexp)"
+        );
+        assert_eq!(dialect_error("(?<=a)x", true), None);
+        assert_eq!(dialect_error("(?:a)x", false), None);
+        for (pattern, suggestion) in [
+            ("a++", "did you mean greedy quantifier `+`?"),
+            ("a{2,}+", "did you mean greedy quantifier `{2,}`?"),
+            ("(?=a)", "did you mean lookbehind `(?<=`?"),
+            ("(?!a)", "did you mean negative lookbehind `(?<!`?"),
+            ("(?>a)", "did you mean non-capturing group `(?:`?"),
+        ] {
+            for perl in [false, true] {
+                let e = dialect_error(pattern, perl).unwrap();
+                assert!(
+                    e.ends_with(&format!("isn't supported; {suggestion}")),
+                    "{e}"
+                );
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn attached_values_keep_bytes_that_arent_utf8() {
+        use std::ffi::{OsStr, OsString};
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = OsStr::from_bytes(b"pats\xff.txt");
+        let args: Vec<OsString> = [&b"-if"[..], b"--include=", b"--file="]
+            .iter()
+            .map(|prefix| {
+                let mut arg = OsStr::from_bytes(prefix).to_os_string();
+                arg.push(name);
+                arg
+            })
+            .collect();
+        let args = Args::parse(&args);
+        assert!(args.flag(&["-i"]));
+        assert_eq!(args.value(&["-f"]), Some(name));
+        assert_eq!(args.value(&["--include"]), Some(name));
+        assert_eq!(args.value(&["--file"]), Some(name));
+    }
+}
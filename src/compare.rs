@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use crate::output::display_path;
+use crate::regex::RegexSet;
+use crate::search::{LineMatch, Searcher};
+
+// --compare-engines: searches `content` with both `regexes` and `reference`
+// (the same patterns on the backtracking matcher alone) and reports every
+// line where they found different matches. Returns how many lines differed.
+pub fn compare_engines(
+    content: &str,
+    regexes: &RegexSet,
+    reference: &RegexSet,
+    searcher: &Searcher,
+    path: Option<&Path>,
+) -> usize {
+    let found = by_line(searcher.find_matches(content, regexes));
+    let expected = by_line(searcher.find_matches(content, reference));
+    let name = path.map_or("(standard input)".into(), display_path);
+    let mut differing = 0;
+    let (mut a, mut b) = (found.iter().peekable(), expected.iter().peekable());
+    loop {
+        let line = match (a.peek(), b.peek()) {
+            (None, None) => return differing,
+            (Some(x), Some(y)) => x.0.min(y.0),
+            (Some(x), None) => x.0,
+            (None, Some(y)) => y.0,
+        };
+        let got = a
+            .next_if(|x| x.0 == line)
+            .map(|x| x.1.as_str())
+            .unwrap_or("");
+        let want = b
+            .next_if(|y| y.0 == line)
+            .map(|y| y.1.as_str())
+            .unwrap_or("");
+        if got != want {
+            eprintln!(
+                "rust-grep: engines disagree at {name}:{line}: [{got}] (default) vs [{want}] (backtracking)"
+            );
+            differing += 1;
+        }
+    }
+}
+
+// (line number, `start-end#pattern` for each match), one entry per line
+fn by_line(matches: Vec<LineMatch>) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for m in matches {
+        let span = format!("{}-{}#{}", m.span.start, m.span.end, m.pattern + 1);
+        match lines.last_mut() {
+            Some((line, spans)) if *line == m.line_number => {
+                spans.push(' ');
+                spans.push_str(&span);
+            }
+            _ => lines.push((m.line_number, span)),
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compare_engines;
+    use crate::regex::{ParseOptions, RegexSet};
+    use crate::search::SearcherBuilder;
+
+    #[test]
+    fn engines_agree_on_literals_and_regexes() {
+        let patterns = ["foo", "fo+", "bar", "foo"].map(String::from);
+        let opts = ParseOptions::default();
+        let regexes = RegexSet::new(&patterns, &opts).unwrap();
+        let reference = RegexSet::backtracking(&patterns, &opts).unwrap();
+        let searcher = SearcherBuilder::new().build();
+        let text = "foo bar\nfoooo\nnothing\nbarfoo\n";
+        assert_eq!(
+            compare_engines(text, &regexes, &reference, &searcher, None),
+            0
+        );
+
+        let other = RegexSet::backtracking(&["bar".to_string()], &opts).unwrap();
+        assert_eq!(compare_engines(text, &regexes, &other, &searcher, None), 3);
+    }
+}
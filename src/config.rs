@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::process;
+
+use crate::cli::takes_next_value;
+
+const CONFIG_PATH_VAR: &str = "RUST_GREP_CONFIG_PATH";
+const OPTIONS_VAR: &str = "RUST_GREP_OPTIONS";
+
+#[derive(Debug, Default)]
+struct ConfigFile {
+    args: Vec<String>,
+    profiles: HashMap<String, Vec<String>>,
+}
+
+// Splices default arguments in front of the real ones and expands
+// `--profile NAME`. Later arguments win, so the order is: config file, then
+// RUST_GREP_OPTIONS, then the command line.
+pub fn with_defaults(args: Vec<OsString>) -> Vec<OsString> {
+    let config = load_config_file();
+
+    let mut iter = args.into_iter();
+    let mut out: Vec<OsString> = iter.next().into_iter().collect();
+
+    out.extend(config.args.into_iter().map(OsString::from));
+    if let Ok(options) = env::var(OPTIONS_VAR) {
+        out.extend(split_words(&options).into_iter().map(OsString::from));
+    }
+    out.extend(iter);
+    expand_profiles(out, &config.profiles)
+}
+
+// A profile is replaced in place by its arguments, so flags given after
+// `--profile` still override it. Option values (`-e --profile`) and
+// everything after `--` are left as they are.
+fn expand_profiles(args: Vec<OsString>, profiles: &HashMap<String, Vec<String>>) -> Vec<OsString> {
+    let mut out = Vec::with_capacity(args.len());
+    let mut iter = args.into_iter();
+    // The program name
+    out.extend(iter.next());
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            out.push(arg);
+            out.extend(iter);
+            break;
+        }
+        if takes_next_value(&arg) {
+            out.push(arg);
+            out.extend(iter.next());
+            continue;
+        }
+        let name = if arg == "--profile" {
+            iter.next()
+        } else if let Some(name) = arg.to_str().and_then(|a| a.strip_prefix("--profile=")) {
+            Some(OsString::from(name))
+        } else {
+            out.push(arg);
+            continue;
+        };
+
+        let name = name.unwrap_or_default().to_string_lossy().into_owned();
+        let Some(profile) = profiles.get(&name) else {
+            eprintln!("rust-grep: unknown profile '{name}'");
+            process::exit(2);
+        };
+        out.extend(profile.iter().map(OsString::from));
+    }
+    out
+}
+
+// One argument per line; blank lines and lines starting with '#' are ignored.
+// Lines of the form `name = ["-i", "--color=auto"]` define profiles instead.
+fn load_config_file() -> ConfigFile {
+    let Some(path) = env::var_os(CONFIG_PATH_VAR) else {
+        return ConfigFile::default();
+    };
+    let Ok(content) = fs::read_to_string(&path) else {
+        eprintln!(
+            "rust-grep: {}: cannot read config file",
+            path.to_string_lossy()
+        );
+        return ConfigFile::default();
+    };
+    parse_config(&content)
+}
+
+fn parse_config(content: &str) -> ConfigFile {
+    let mut config = ConfigFile::default();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some((name, args)) = parse_profile(line) {
+            config.profiles.insert(name, args);
+        } else {
+            config.args.push(line.to_string());
+        }
+    }
+    config
+}
+
+fn parse_profile(line: &str) -> Option<(String, Vec<String>)> {
+    let (name, value) = line.split_once('=')?;
+    let name = name.trim();
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if name.is_empty() || name.starts_with('-') || !name.chars().all(is_name) {
+        return None;
+    }
+
+    let list = value.trim().strip_prefix('[')?.strip_suffix(']')?;
+    let mut args = Vec::new();
+    let mut chars = list.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let mut arg = String::new();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => arg.push(chars.next()?),
+                        other => arg.push(other),
+                    }
+                }
+                args.push(arg);
+            }
+            ',' => {}
+            c if c.is_whitespace() => {}
+            _ => return None,
+        }
+    }
+    Some((name.to_string(), args))
+}
+
+// Whitespace-separated words with minimal shell-style quoting, so values
+// like `--include='*.log'` survive
+fn split_words(s: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+
+    for c in s.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.push(c),
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                in_word = true;
+            }
+            None if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            None => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(current);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::ffi::OsString;
+
+    use super::{expand_profiles, parse_config, split_words};
+
+    #[test]
+    fn splits_on_whitespace_and_honors_quotes() {
+        assert_eq!(
+            split_words("  --color=auto   -n "),
+            vec!["--color=auto", "-n"]
+        );
+        assert_eq!(
+            split_words(r#"--include='*.log' -e "a b" ''"#),
+            vec!["--include=*.log", "-e", "a b", ""]
+        );
+    }
+
+    #[test]
+    fn parses_profiles_next_to_plain_arguments() {
+        let config = parse_config(
+            "# defaults\n--color=auto\n--include=*.rs\nlogs = [\"-i\", \"--include=*.log\", \"-C2\"]\n",
+        );
+        assert_eq!(config.args, vec!["--color=auto", "--include=*.rs"]);
+        assert_eq!(
+            config.profiles["logs"],
+            vec!["-i", "--include=*.log", "-C2"]
+        );
+    }
+
+    #[test]
+    fn profiles_expand_only_where_an_option_can_be() {
+        let profiles = HashMap::from([("logs".to_string(), vec!["-i".to_string()])]);
+        let expand = |args: &[&str]| -> Vec<OsString> {
+            let args = args.iter().map(OsString::from).collect();
+            expand_profiles(args, &profiles)
+        };
+        assert_eq!(expand(&["rg", "--profile", "logs", "x"]), ["rg", "-i", "x"]);
+        assert_eq!(expand(&["rg", "--profile=logs", "x"]), ["rg", "-i", "x"]);
+        for args in [
+            ["rg", "--", "--profile", "p.txt"],
+            ["rg", "-e", "--profile", "p.txt"],
+            ["rg", "-ve", "--profile", "p.txt"],
+            ["rg", "--regexp", "--profile", "p.txt"],
+        ] {
+            assert_eq!(expand(&args), args);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+// --encoding=auto: how an input's bytes are turned into text
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1, // stands in for legacy single-byte charsets
+}
+
+// Bytes looked at when there's no BOM
+const SNIFF_LEN: usize = 4096;
+
+// The input's encoding and the length of its byte order mark. Without a BOM,
+// mostly-ASCII UTF-16 shows up as a NUL in every other byte; anything else
+// that isn't valid UTF-8 is read as Latin-1.
+pub fn detect(data: &[u8]) -> (Encoding, usize) {
+    match data {
+        [0xEF, 0xBB, 0xBF, ..] => return (Encoding::Utf8, 3),
+        [0xFF, 0xFE, ..] => return (Encoding::Utf16Le, 2),
+        [0xFE, 0xFF, ..] => return (Encoding::Utf16Be, 2),
+        _ => {}
+    }
+    let sample = &data[..data.len().min(SNIFF_LEN)];
+    let pairs = sample.len() / 2;
+    let even_nuls = sample.iter().step_by(2).filter(|&&b| b == 0).count();
+    let odd_nuls = sample
+        .iter()
+        .skip(1)
+        .step_by(2)
+        .filter(|&&b| b == 0)
+        .count();
+    if pairs > 0 && odd_nuls * 4 >= pairs && even_nuls * 4 < odd_nuls {
+        return (Encoding::Utf16Le, 0);
+    }
+    if pairs > 0 && even_nuls * 4 >= pairs && odd_nuls * 4 < even_nuls {
+        return (Encoding::Utf16Be, 0);
+    }
+    if std::str::from_utf8(data).is_ok() {
+        (Encoding::Utf8, 0)
+    } else {
+        (Encoding::Latin1, 0)
+    }
+}
+
+// `data` as text in the encoding `detect` picks, BOM removed. Never fails:
+// unpaired surrogates and a trailing odd byte become U+FFFD.
+pub fn decode_auto(data: &[u8]) -> String {
+    let (encoding, bom) = detect(data);
+    let data = &data[bom..];
+    match encoding {
+        Encoding::Utf8 => String::from_utf8_lossy(data).into_owned(),
+        Encoding::Latin1 => data.iter().map(|&b| b as char).collect(),
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let units: Vec<u16> = data
+                .chunks(2)
+                .map(|pair| match (pair, encoding) {
+                    ([lo, hi], Encoding::Utf16Le) => u16::from_le_bytes([*lo, *hi]),
+                    ([hi, lo], _) => u16::from_be_bytes([*hi, *lo]),
+                    _ => 0xFFFD,
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Encoding, decode_auto, detect};
+
+    fn utf16(text: &str, le: bool) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|u| if le { u.to_le_bytes() } else { u.to_be_bytes() })
+            .collect()
+    }
+
+    #[test]
+    fn detects_boms_and_bomless_utf16() {
+        let mut with_bom = vec![0xFF, 0xFE];
+        with_bom.extend(utf16("héllo\n", true));
+        assert_eq!(detect(&with_bom), (Encoding::Utf16Le, 2));
+        assert_eq!(decode_auto(&with_bom), "héllo\n");
+
+        assert_eq!(decode_auto(&utf16("error: disk\n", false)), "error: disk\n");
+        assert_eq!(decode_auto(&utf16("error: disk\n", true)), "error: disk\n");
+        assert_eq!(decode_auto(b"\xEF\xBB\xBFplain"), "plain");
+    }
+
+    #[test]
+    fn falls_back_to_latin1_for_invalid_utf8() {
+        assert_eq!(detect("café".as_bytes()), (Encoding::Utf8, 0));
+        assert_eq!(detect(b"caf\xE9"), (Encoding::Latin1, 0));
+        assert_eq!(decode_auto(b"caf\xE9"), "café");
+    }
+}
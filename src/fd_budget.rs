@@ -0,0 +1,98 @@
+use std::sync::{Condvar, Mutex};
+
+// Descriptors left for stdio, the pager, sockets and directory walking
+const RESERVED_FDS: usize = 32;
+
+// Used where the limit can't be read
+const DEFAULT_FD_LIMIT: usize = 512;
+
+// Caps how many files are open at once across threads. Anything past the
+// budget waits for a descriptor to be released instead of failing with
+// "too many open files" halfway through a search.
+#[derive(Debug)]
+pub struct FdBudget {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+// One open file's share of the budget, returned on drop
+pub struct FdPermit<'a> {
+    budget: &'a FdBudget,
+}
+
+impl FdBudget {
+    pub fn new(limit: usize) -> FdBudget {
+        FdBudget {
+            available: Mutex::new(limit.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    // Sized from the process's soft RLIMIT_NOFILE, less a reserve
+    pub fn from_rlimit() -> FdBudget {
+        FdBudget::new(fd_limit().saturating_sub(RESERVED_FDS))
+    }
+
+    // Blocks until a descriptor is free
+    pub fn acquire(&self) -> FdPermit<'_> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+        FdPermit { budget: self }
+    }
+}
+
+impl Drop for FdPermit<'_> {
+    fn drop(&mut self) {
+        *self.budget.available.lock().unwrap() += 1;
+        self.budget.released.notify_one();
+    }
+}
+
+#[cfg(unix)]
+fn fd_limit() -> usize {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit only writes to the struct it's given
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return DEFAULT_FD_LIMIT;
+    }
+    if limit.rlim_cur == libc::RLIM_INFINITY {
+        return usize::MAX;
+    }
+    usize::try_from(limit.rlim_cur).unwrap_or(usize::MAX)
+}
+
+#[cfg(not(unix))]
+fn fd_limit() -> usize {
+    DEFAULT_FD_LIMIT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FdBudget;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn waits_for_a_released_descriptor() {
+        let budget = FdBudget::new(1);
+        let acquired = AtomicBool::new(false);
+        thread::scope(|scope| {
+            let first = budget.acquire();
+            scope.spawn(|| {
+                let _second = budget.acquire();
+                acquired.store(true, Ordering::SeqCst);
+            });
+            thread::sleep(Duration::from_millis(50));
+            assert!(!acquired.load(Ordering::SeqCst));
+            drop(first);
+        });
+        assert!(acquired.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,70 @@
+// Named groups of file name globs for --type and --type-not, so common
+// selections don't need a list of --include globs each time
+
+pub struct FileType {
+    pub name: &'static str,
+    pub globs: &'static [&'static str],
+}
+
+// Sorted by name, as --type-list prints them
+#[rustfmt::skip]
+pub const FILE_TYPES: &[FileType] = &[
+    FileType { name: "c", globs: &["*.c", "*.h"] },
+    FileType { name: "config", globs: &["*.cfg", "*.conf", "*.ini", "*.properties"] },
+    FileType { name: "cpp", globs: &["*.cc", "*.cpp", "*.cxx", "*.hh", "*.hpp", "*.hxx"] },
+    FileType { name: "csharp", globs: &["*.cs"] },
+    FileType { name: "css", globs: &["*.css", "*.scss", "*.less"] },
+    FileType { name: "csv", globs: &["*.csv", "*.tsv"] },
+    FileType { name: "go", globs: &["*.go"] },
+    FileType { name: "html", globs: &["*.htm", "*.html"] },
+    FileType { name: "java", globs: &["*.java"] },
+    FileType { name: "js", globs: &["*.js", "*.jsx", "*.mjs", "*.cjs"] },
+    FileType { name: "json", globs: &["*.json", "*.jsonl", "*.ndjson"] },
+    FileType { name: "kotlin", globs: &["*.kt", "*.kts"] },
+    FileType { name: "log", globs: &["*.log", "*.log.[0123456789]", "*.out"] },
+    FileType { name: "markdown", globs: &["*.md", "*.markdown"] },
+    FileType { name: "py", globs: &["*.py", "*.pyi"] },
+    FileType { name: "ruby", globs: &["*.rb", "Gemfile", "Rakefile"] },
+    FileType { name: "rust", globs: &["*.rs"] },
+    FileType { name: "sh", globs: &["*.sh", "*.bash", "*.zsh"] },
+    FileType { name: "sql", globs: &["*.sql"] },
+    FileType { name: "test", globs: &[
+        "*_test.go", "*_test.rs", "test_*.py", "*_test.py", "*Test.java",
+        "*.test.js", "*.spec.js", "*.test.ts", "*.spec.ts",
+    ] },
+    FileType { name: "toml", globs: &["*.toml", "Cargo.lock"] },
+    FileType { name: "ts", globs: &["*.ts", "*.tsx", "*.mts", "*.cts"] },
+    FileType { name: "txt", globs: &["*.txt"] },
+    FileType { name: "xml", globs: &["*.xml", "*.xsd", "*.xsl", "*.svg"] },
+    FileType { name: "yaml", globs: &["*.yaml", "*.yml"] },
+];
+
+pub fn lookup(name: &str) -> Option<&'static FileType> {
+    FILE_TYPES.iter().find(|t| t.name == name)
+}
+
+pub fn print_types() {
+    for t in FILE_TYPES {
+        println!("{}: {}", t.name, t.globs.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FILE_TYPES, lookup};
+    use crate::glob::glob_match;
+
+    #[test]
+    fn types_are_sorted_and_match_their_files() {
+        assert!(FILE_TYPES.windows(2).all(|w| w[0].name < w[1].name));
+        let matches = |name: &str, file: &str| {
+            let globs = lookup(name).unwrap().globs;
+            globs.iter().any(|g| glob_match(g, file))
+        };
+        assert!(matches("rust", "main.rs"));
+        assert!(matches("log", "app.log.1"));
+        assert!(matches("test", "parser_test.go"));
+        assert!(!matches("test", "parser.go"));
+        assert!(lookup("cobol").is_none());
+    }
+}
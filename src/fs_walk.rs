@@ -1,28 +1,220 @@
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 
-pub fn collect_files(root: &Path, recursive: bool) -> Vec<PathBuf> {
-    if recursive && root.is_dir() {
-        let mut out = Vec::new();
-        collect_recursive(root, &mut out);
-        out
-    } else if root.is_file() {
-        vec![root.to_path_buf()]
-    } else {
-        Vec::new()
-    }
+use crate::cancel::CancelToken;
+use crate::cli::DeviceAction;
+use crate::glob::FileGlobs;
+
+// What walk_files found under a root: the files to search, and the paths it
+// couldn't read, like a missing root or a directory without permission
+#[derive(Debug, Default)]
+pub struct Walked {
+    pub files: Vec<PathBuf>,
+    pub errors: Vec<(PathBuf, io::Error)>,
+}
+
+// walk_files for callers that skip what can't be read
+pub fn collect_files(
+    root: &Path,
+    recursive: bool,
+    follow_links: bool,
+    devices: Option<DeviceAction>,
+    globs: &FileGlobs,
+    cancel: &CancelToken,
+) -> Vec<PathBuf> {
+    walk_files(root, recursive, follow_links, devices, globs, cancel).files
 }
 
-fn collect_recursive(dir: &Path, out: &mut Vec<PathBuf>) {
-    let Ok(entries) = fs::read_dir(dir) else {
-        return;
+// Paths named on the command line are followed if they're symlinks. Links
+// met while recursing are skipped, unless `follow_links` (-R) is set; then a
+// link back to a directory the walk is already inside is skipped instead,
+// which keeps cycles out of the walk. Files named on the command line go
+// through `globs` too.
+pub fn walk_files(
+    root: &Path,
+    recursive: bool,
+    follow_links: bool,
+    devices: Option<DeviceAction>,
+    globs: &FileGlobs,
+    cancel: &CancelToken,
+) -> Walked {
+    let mut out = Walked::default();
+    let meta = match fs::metadata(root) {
+        Ok(meta) => meta,
+        Err(e) => {
+            out.errors.push((root.to_path_buf(), e));
+            return out;
+        }
     };
-    for entry in entries.flatten() {
-        let path = entry.path();
-        if path.is_dir() {
-            collect_recursive(&path, out);
-        } else if path.is_file() {
-            out.push(path);
+    let file_type = meta.file_type();
+
+    if recursive && file_type.is_dir() {
+        let walk = Walk {
+            follow_links,
+            devices,
+            globs,
+            cancel,
+        };
+        let mut ancestors: Vec<DirId> = dir_id(root).into_iter().collect();
+        walk.collect(root, &mut ancestors, &mut out);
+    } else if globs.allows(root)
+        && (file_type.is_file() || !file_type.is_dir() && devices != Some(DeviceAction::Skip))
+    {
+        // FIFOs, sockets and devices named explicitly are read unless -D skip
+        out.files.push(root.to_path_buf());
+    }
+    out
+}
+
+struct Walk<'a> {
+    follow_links: bool,
+    devices: Option<DeviceAction>,
+    globs: &'a FileGlobs,
+    cancel: &'a CancelToken,
+}
+
+impl Walk<'_> {
+    // `ancestors` are the directories from the root down to `dir`
+    fn collect(&self, dir: &Path, ancestors: &mut Vec<DirId>, out: &mut Walked) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                out.errors.push((dir.to_path_buf(), e));
+                return;
+            }
+        };
+        for entry in entries.flatten() {
+            if self.cancel.is_cancelled() {
+                return;
+            }
+            let path = entry.path();
+            let Ok(mut file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_symlink() {
+                // Broken links have nothing to search
+                match fs::metadata(&path) {
+                    Ok(meta) if self.follow_links => file_type = meta.file_type(),
+                    _ => continue,
+                }
+            }
+            if file_type.is_dir() {
+                if !self.follow_links {
+                    self.collect(&path, ancestors, out);
+                    continue;
+                }
+                let Some(id) = dir_id(&path) else {
+                    continue;
+                };
+                if ancestors.contains(&id) {
+                    continue;
+                }
+                ancestors.push(id);
+                self.collect(&path, ancestors, out);
+                ancestors.pop();
+            } else if !self.globs.allows(&path) {
+                continue;
+            } else if file_type.is_file() {
+                out.files.push(path);
+            } else if self.devices == Some(DeviceAction::Read) {
+                // Special files found while recursing can block forever, so
+                // only read them when explicitly asked to with -D read
+                out.files.push(path);
+            }
         }
     }
 }
+
+// What tells directories apart however they're reached: the device and
+// inode, or where there are none, the path with every link resolved
+#[cfg(unix)]
+type DirId = (u64, u64);
+#[cfg(not(unix))]
+type DirId = PathBuf;
+
+#[cfg(unix)]
+fn dir_id(dir: &Path) -> Option<DirId> {
+    use std::os::unix::fs::MetadataExt;
+
+    let meta = fs::metadata(dir).ok()?;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn dir_id(dir: &Path) -> Option<DirId> {
+    fs::canonicalize(dir).ok()
+}
+
+// A symlink whose target doesn't exist
+pub fn is_broken_link(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
+        && fs::metadata(path).is_err()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{collect_files, is_broken_link, walk_files};
+    use crate::cancel::CancelToken;
+    use crate::glob::FileGlobs;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn follows_only_links_named_explicitly() {
+        let dir = std::env::temp_dir().join(format!("rust-grep-walk-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("tree")).unwrap();
+        std::fs::write(dir.join("target.txt"), "x\n").unwrap();
+        symlink(dir.join("target.txt"), dir.join("tree/link.txt")).unwrap();
+        symlink(dir.join("missing.txt"), dir.join("dangling.txt")).unwrap();
+        let cancel = CancelToken::new();
+        let all = FileGlobs::default();
+
+        assert!(collect_files(&dir.join("tree"), true, false, None, &all, &cancel).is_empty());
+        let link = dir.join("tree/link.txt");
+        assert_eq!(
+            collect_files(&link, true, false, None, &all, &cancel),
+            vec![link.clone()]
+        );
+        assert_eq!(
+            collect_files(&dir.join("tree"), true, true, None, &all, &cancel),
+            vec![link]
+        );
+        assert!(is_broken_link(&dir.join("dangling.txt")));
+        assert!(!is_broken_link(&dir.join("target.txt")));
+
+        // A root that can't be read is reported rather than skipped
+        let walked = walk_files(&dir.join("dangling.txt"), true, false, None, &all, &cancel);
+        assert!(walked.files.is_empty());
+        assert_eq!(walked.errors[0].0, dir.join("dangling.txt"));
+        assert_eq!(walked.errors[0].1.kind(), std::io::ErrorKind::NotFound);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn following_links_stops_at_cycles() {
+        let dir = std::env::temp_dir().join(format!("rust-grep-cycle-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.txt"), "x\n").unwrap();
+        std::fs::write(dir.join("sub/b.txt"), "x\n").unwrap();
+        symlink(&dir, dir.join("sub/loop")).unwrap();
+        symlink(dir.join("sub"), dir.join("other")).unwrap();
+
+        let mut found = collect_files(
+            &dir,
+            true,
+            true,
+            None,
+            &FileGlobs::default(),
+            &CancelToken::new(),
+        );
+        found.sort();
+        // `other` isn't a directory the walk is inside, so it's searched too
+        let expected = [
+            dir.join("a.txt"),
+            dir.join("other/b.txt"),
+            dir.join("sub/b.txt"),
+        ];
+        assert_eq!(found, expected);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,211 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+// Shell-style wildcards: `*` matches any run of characters, `?` one
+// character, and `[abc]`, `[a-z]` or `[!a]` one character from a set. File
+// names are compared ignoring ASCII case on Windows.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text)
+}
+
+// --include / --exclude: which files to search, by file name. A file is
+// searched if no exclude glob matches it and, when there are include globs,
+// one of them does. --type adds the globs of a file type to `types`, which
+// must match as well, and --type-not adds them to `exclude`.
+#[derive(Debug, Clone, Default)]
+pub struct FileGlobs {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub types: Vec<String>,
+}
+
+impl FileGlobs {
+    pub fn allows(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name() else {
+            return true;
+        };
+        let name = name.to_string_lossy();
+        let any = |globs: &[String]| globs.iter().any(|g| glob_match(g, &name));
+        (self.include.is_empty() || any(&self.include))
+            && (self.types.is_empty() || any(&self.types))
+            && !any(&self.exclude)
+    }
+}
+
+pub fn has_wildcards(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+fn match_from(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') => {
+            let rest = &pattern[1..];
+            if rest.first() == Some(&'*') {
+                return match_from(rest, text);
+            }
+            (0..=text.len()).any(|i| match_from(rest, &text[i..]))
+        }
+        Some('?') => !text.is_empty() && match_from(&pattern[1..], &text[1..]),
+        Some('[') if class_end(pattern).is_some() => {
+            let end = class_end(pattern).unwrap();
+            text.first()
+                .is_some_and(|&c| class_contains(&pattern[1..end], c))
+                && match_from(&pattern[end + 1..], &text[1..])
+        }
+        Some(&c) => {
+            text.first().is_some_and(|&t| chars_eq(c, t)) && match_from(&pattern[1..], &text[1..])
+        }
+    }
+}
+
+// Index of the `]` closing the class `pattern` starts with. A `]` right
+// after `[` or `[!` is a member, not the end.
+fn class_end(pattern: &[char]) -> Option<usize> {
+    let mut i = 1;
+    if matches!(pattern.get(i), Some('!' | '^')) {
+        i += 1;
+    }
+    if pattern.get(i) == Some(&']') {
+        i += 1;
+    }
+    pattern[i..].iter().position(|&c| c == ']').map(|p| i + p)
+}
+
+fn class_contains(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('!' | '^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= (class[i]..=class[i + 2]).any(|m| chars_eq(m, c));
+            i += 3;
+        } else {
+            found |= chars_eq(class[i], c);
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+fn chars_eq(a: char, b: char) -> bool {
+    if cfg!(windows) {
+        a.eq_ignore_ascii_case(&b)
+    } else {
+        a == b
+    }
+}
+
+// Expands wildcards in any component of `path` against the file system, in
+// sorted order. Hidden files only match a component starting with `.`. A
+// pattern that matches nothing is kept as-is so the usual "no such file"
+// handling applies to it.
+pub fn expand(path: &Path) -> Vec<PathBuf> {
+    if !has_wildcards(&path.to_string_lossy()) {
+        return vec![path.to_path_buf()];
+    }
+    let mut found = vec![PathBuf::new()];
+    for component in path.components() {
+        let Component::Normal(name) = component else {
+            for base in &mut found {
+                base.push(component);
+            }
+            continue;
+        };
+        let name = name.to_string_lossy();
+        if !has_wildcards(&name) {
+            for base in &mut found {
+                base.push(name.as_ref());
+            }
+            continue;
+        }
+        let mut next = Vec::new();
+        for base in &found {
+            let dir = if base.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                base.as_path()
+            };
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let entry_name = entry.file_name();
+                let entry_name = entry_name.to_string_lossy();
+                if entry_name.starts_with('.') && !name.starts_with('.') {
+                    continue;
+                }
+                if glob_match(&name, &entry_name) {
+                    next.push(base.join(entry.file_name()));
+                }
+            }
+        }
+        next.sort();
+        found = next;
+    }
+    if found.is_empty() {
+        return vec![path.to_path_buf()];
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileGlobs, expand, glob_match};
+    use std::path::Path;
+
+    #[test]
+    fn matches_shell_wildcards() {
+        assert!(glob_match("*.log", "app.log"));
+        assert!(!glob_match("*.log", "app.log.1"));
+        assert!(glob_match("app-??.txt", "app-01.txt"));
+        assert!(glob_match("[a-c]*[!0-9]", "build.rs"));
+        assert!(!glob_match("[a-c]*[!0-9]", "build1"));
+        assert!(glob_match("[]x]", "]"));
+        assert!(glob_match("a[b", "a[b"));
+        assert!(glob_match("**z", "xyz"));
+    }
+
+    #[test]
+    fn file_globs_match_file_names() {
+        let globs = FileGlobs {
+            include: vec!["*.rs".into(), "*.js".into()],
+            exclude: vec!["*.min.js".into()],
+            ..FileGlobs::default()
+        };
+        assert!(globs.allows(Path::new("src/main.rs")));
+        assert!(globs.allows(Path::new("web/app.js")));
+        assert!(!globs.allows(Path::new("web/app.min.js")));
+        assert!(!globs.allows(Path::new("README.md")));
+        assert!(FileGlobs::default().allows(Path::new("README.md")));
+
+        // A file must be of one of the --type types too
+        let typed = FileGlobs {
+            types: vec!["*.rs".into(), "*.md".into()],
+            ..globs
+        };
+        assert!(typed.allows(Path::new("src/main.rs")));
+        assert!(!typed.allows(Path::new("web/app.js")));
+    }
+
+    #[test]
+    fn expands_each_component() {
+        let dir = std::env::temp_dir().join(format!("rust-grep-glob-{}", std::process::id()));
+        for sub in ["logs1", "logs2", "other"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+            std::fs::write(dir.join(sub).join("a.log"), "").unwrap();
+        }
+        std::fs::write(dir.join("logs1").join(".hidden.log"), "").unwrap();
+
+        let found = expand(&dir.join("logs*").join("*.log"));
+        assert_eq!(found, [dir.join("logs1/a.log"), dir.join("logs2/a.log")]);
+        let missing = dir.join("*.none");
+        assert_eq!(expand(&missing), vec![missing]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
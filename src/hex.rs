@@ -0,0 +1,114 @@
+use std::path::Path;
+
+use crate::cli::Config;
+use crate::output::{display_path, hexdump};
+use crate::stats::Stats;
+
+// --hex: bytes written as pairs of hex digits, e.g. `DE AD BE EF` or
+// `deadbeef`. Whitespace between pairs is optional.
+pub fn parse_hex(spec: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = spec
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(
+        digits
+            .chunks(2)
+            .map(|pair| pair[0] << 4 | pair[1])
+            .collect(),
+    )
+}
+
+// Start offsets of the non-overlapping occurrences of `needle` in `data`
+pub fn find_all(data: &[u8], needle: &[u8]) -> Vec<usize> {
+    let mut hits = Vec::new();
+    let mut pos = 0;
+    while let Some(i) = data
+        .get(pos..)
+        .and_then(|rest| rest.windows(needle.len()).position(|w| w == needle))
+    {
+        hits.push(pos + i);
+        pos += i + needle.len();
+    }
+    hits
+}
+
+// Searches raw bytes for the --hex sequence. Each hit is printed as its
+// `START-END` byte offsets in the input, or as hexdump rows with --hexdump.
+pub fn process_bytes(
+    data: &[u8],
+    needle: &[u8],
+    filename: Option<&Path>,
+    cfg: &Config,
+    use_color: bool,
+    stats: &mut Stats,
+    show_filename: bool,
+) {
+    stats.files_searched += 1;
+    stats.bytes_searched += data.len();
+    let mut hits = find_all(data, needle);
+    // -m: each hit stands in for a line
+    if let Some(max) = cfg.max_count {
+        hits.truncate(max);
+    }
+    let name = filename.map_or_else(|| "(standard input)".into(), display_path);
+    if hits.is_empty() {
+        if cfg.files_without_match && !cfg.quiet {
+            println!("{name}");
+        }
+        return;
+    }
+    stats.files_matched += 1;
+    stats.matches += hits.len();
+    stats.matched_lines += hits.len();
+
+    if cfg.quiet || cfg.files_without_match {
+        return;
+    }
+    if cfg.files_with_matches {
+        println!("{name}");
+        return;
+    }
+    let prefix = if show_filename {
+        format!("{name}:")
+    } else {
+        String::new()
+    };
+    if cfg.hexdump {
+        let spans: Vec<_> = hits
+            .iter()
+            .map(|&start| start..start + needle.len())
+            .collect();
+        for row in hexdump(data, 0, &spans, use_color) {
+            println!("{prefix}{row}");
+        }
+        return;
+    }
+    for start in hits {
+        println!("{prefix}{start}-{}", start + needle.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_all, parse_hex};
+
+    #[test]
+    fn parses_hex_pairs() {
+        assert_eq!(parse_hex("DE AD be ef"), Some(vec![0xde, 0xad, 0xbe, 0xef]));
+        assert_eq!(parse_hex("7f454c46"), Some(b"\x7fELF".to_vec()));
+        assert_eq!(parse_hex("ABC"), None);
+        assert_eq!(parse_hex("zz"), None);
+        assert_eq!(parse_hex(" "), None);
+    }
+
+    #[test]
+    fn finds_non_overlapping_hits() {
+        assert_eq!(find_all(b"\x00\xaa\xaa\xaa\xaa\x01", &[0xaa, 0xaa]), [1, 3]);
+        assert_eq!(find_all(b"ab", b"abc"), Vec::<usize>::new());
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::UNIX_EPOCH;
+
+use crate::cancel::CancelToken;
+use crate::fs_walk::collect_files;
+use crate::glob::FileGlobs;
+use crate::regex::ParseOptions;
+use crate::regex::ast::Token;
+use crate::regex::parser::parse_regex_with;
+
+// Written at the root of the indexed tree
+pub const INDEX_FILE: &str = ".rust-grep-index";
+const MAGIC: &[u8] = b"rust-grep-index 1\n";
+
+pub type Trigram = [u8; 3];
+
+// `rust-grep index build [ROOT]` writes the index; `rust-grep index search
+// ARGS...` is a normal recursive search that consults it. Returns the
+// arguments to run the search with, or None once a build has finished.
+pub fn subcommand(args: Vec<OsString>) -> Option<Vec<OsString>> {
+    let mut iter = args.into_iter();
+    let prog = iter.next().unwrap_or_default();
+    iter.next(); // "index"
+    match iter.next().as_ref().and_then(|a| a.to_str()) {
+        Some("build") => {
+            let root = iter
+                .next()
+                .map_or_else(|| PathBuf::from("."), PathBuf::from);
+            if let Err(e) = build(&root) {
+                eprintln!("rust-grep: cannot build index in {}: {e}", root.display());
+                process::exit(2);
+            }
+            None
+        }
+        Some("search") => {
+            let mut out = vec![prog, "-r".into(), "--use-index".into()];
+            out.extend(iter);
+            Some(out)
+        }
+        _ => {
+            eprintln!(
+                "rust-grep: usage: index build [ROOT] | index search [OPTIONS] PATTERN ROOT..."
+            );
+            process::exit(2);
+        }
+    }
+}
+
+struct FileEntry {
+    path: String, // relative to the index root
+    mtime: (u64, u32),
+    len: u64,
+}
+
+pub struct Index {
+    files: Vec<FileEntry>,
+    ids: HashMap<String, u32>,
+    postings: HashMap<Trigram, Vec<u32>>, // sorted file ids
+}
+
+pub fn build(root: &Path) -> io::Result<()> {
+    let mut index = Index {
+        files: Vec::new(),
+        ids: HashMap::new(),
+        postings: HashMap::new(),
+    };
+    for path in collect_files(
+        root,
+        true,
+        false,
+        None,
+        &FileGlobs::default(),
+        &CancelToken::new(),
+    ) {
+        let Some(rel) = relative_path(root, &path) else {
+            continue;
+        };
+        if rel == INDEX_FILE {
+            continue;
+        }
+        let (Ok(meta), Ok(data)) = (fs::metadata(&path), fs::read(&path)) else {
+            continue;
+        };
+        let id = index.files.len() as u32;
+        let trigrams: HashSet<Trigram> = data.windows(3).map(|w| [w[0], w[1], w[2]]).collect();
+        for t in trigrams {
+            index.postings.entry(t).or_default().push(id);
+        }
+        index.files.push(FileEntry {
+            path: rel,
+            mtime: mtime(&meta),
+            len: meta.len(),
+        });
+    }
+    fs::write(root.join(INDEX_FILE), index.encode())
+}
+
+impl Index {
+    pub fn load(root: &Path) -> Option<Index> {
+        Index::decode(&fs::read(root.join(INDEX_FILE)).ok()?)
+    }
+
+    // Whether `path` (found under `root`) may match one of `patterns`. Files
+    // the index doesn't know, or that changed since it was built, always may.
+    pub fn is_candidate(&self, root: &Path, path: &Path, patterns: &[Vec<Trigram>]) -> bool {
+        let Some(rel) = relative_path(root, path) else {
+            return true;
+        };
+        if rel == INDEX_FILE {
+            return false;
+        }
+        let Some(&id) = self.ids.get(&rel) else {
+            return true;
+        };
+        let entry = &self.files[id as usize];
+        let fresh =
+            fs::metadata(path).is_ok_and(|m| mtime(&m) == entry.mtime && m.len() == entry.len);
+        if !fresh {
+            return true;
+        }
+        patterns.iter().any(|required| {
+            required.iter().all(|t| {
+                self.postings
+                    .get(t)
+                    .is_some_and(|ids| ids.binary_search(&id).is_ok())
+            })
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend((self.files.len() as u32).to_le_bytes());
+        for f in &self.files {
+            out.extend(f.mtime.0.to_le_bytes());
+            out.extend(f.mtime.1.to_le_bytes());
+            out.extend(f.len.to_le_bytes());
+            out.extend((f.path.len() as u32).to_le_bytes());
+            out.extend(f.path.as_bytes());
+        }
+        out.extend((self.postings.len() as u32).to_le_bytes());
+        for (t, ids) in &self.postings {
+            out.extend(t);
+            out.extend((ids.len() as u32).to_le_bytes());
+            for id in ids {
+                out.extend(id.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    // None for a corrupt or foreign file, which is then ignored
+    fn decode(data: &[u8]) -> Option<Index> {
+        let mut r = Bytes(data.strip_prefix(MAGIC)?);
+        let mut files = Vec::new();
+        for _ in 0..r.u32()? {
+            let mtime = (r.u64()?, r.u32()?);
+            let len = r.u64()?;
+            let path_len = r.u32()? as usize;
+            let path = String::from_utf8(r.take(path_len)?.to_vec()).ok()?;
+            files.push(FileEntry { path, mtime, len });
+        }
+        let mut postings = HashMap::new();
+        for _ in 0..r.u32()? {
+            let t = r.take(3)?;
+            let t = [t[0], t[1], t[2]];
+            let ids = (0..r.u32()?).map(|_| r.u32()).collect::<Option<Vec<_>>>()?;
+            postings.insert(t, ids);
+        }
+        let ids = (0..)
+            .zip(&files)
+            .map(|(id, f)| (f.path.clone(), id))
+            .collect();
+        Some(Index {
+            files,
+            ids,
+            postings,
+        })
+    }
+}
+
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+// Trigrams a line must contain for `pattern` to match it, taken from runs of
+// literals that every match has to include. Empty when nothing is required,
+// e.g. for `\d+`, a case-insensitive pattern, or with --fuzzy, where any
+// literal may be one of the edits.
+pub fn required_trigrams(pattern: &str, opts: &ParseOptions) -> Vec<Trigram> {
+    if opts.case_insensitive || opts.fuzzy.is_some() {
+        return Vec::new();
+    }
+    let mut runs = Vec::new();
+    let mut run = String::new();
+    // A malformed pattern requires nothing; it's reported where it's compiled
+    for token in parse_regex_with(pattern, opts).unwrap_or_default() {
+        match token {
+            Token::Literal(c) => run.push(c),
+            Token::Quantifier(inner, min, _, _) => match *inner {
+                // `x+` contributes an `x` on both sides of however many repeats
+                Token::Literal(c) if min > 0 => {
+                    run.push(c);
+                    runs.push(std::mem::take(&mut run));
+                    run.push(c);
+                }
+                _ => runs.push(std::mem::take(&mut run)),
+            },
+            _ => runs.push(std::mem::take(&mut run)),
+        }
+    }
+    runs.push(run);
+
+    let mut trigrams: Vec<Trigram> = runs
+        .iter()
+        .flat_map(|r| r.as_bytes().windows(3).map(|w| [w[0], w[1], w[2]]))
+        .collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    path.strip_prefix(root).ok()?.to_str().map(str::to_string)
+}
+
+pub fn mtime(meta: &fs::Metadata) -> (u64, u32) {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or((0, 0), |d| (d.as_secs(), d.subsec_nanos()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Index, build, required_trigrams};
+    use crate::regex::ParseOptions;
+    use std::fs;
+
+    #[test]
+    fn trigrams_from_required_literals() {
+        let opts = ParseOptions::default();
+        assert_eq!(required_trigrams("abcd", &opts), [*b"abc", *b"bcd"]);
+        // `(x|y)` and `\d` break runs; `c+` still requires a `c`
+        assert_eq!(
+            required_trigrams(r"ab(x|y)ab\dab", &opts),
+            Vec::<[u8; 3]>::new()
+        );
+        assert_eq!(required_trigrams("abc+de", &opts), [*b"abc", *b"cde"]);
+        assert!(required_trigrams("ab?c", &opts).is_empty());
+        // `hallo` is within one edit of `hello` without sharing `ell`
+        let fuzzy = ParseOptions {
+            fuzzy: Some(1),
+            ..ParseOptions::default()
+        };
+        assert!(required_trigrams("hello", &fuzzy).is_empty());
+    }
+
+    #[test]
+    fn index_narrows_candidates_until_a_file_changes() {
+        let root = std::env::temp_dir().join(format!("rust-grep-index-{}", std::process::id()));
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), "hello world\n").unwrap();
+        fs::write(root.join("sub/b.txt"), "goodbye\n").unwrap();
+        build(&root).unwrap();
+
+        let index = Index::load(&root).unwrap();
+        let query = [required_trigrams("world", &ParseOptions::default())];
+        assert!(index.is_candidate(&root, &root.join("a.txt"), &query));
+        assert!(!index.is_candidate(&root, &root.join("sub/b.txt"), &query));
+
+        // A file whose size changed is searched regardless of the index
+        fs::write(root.join("sub/b.txt"), "goodbye, world\n").unwrap();
+        assert!(index.is_candidate(&root, &root.join("sub/b.txt"), &query));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,28 @@
+pub mod app;
+pub mod cache;
+pub mod cancel;
+pub mod catalog;
+pub mod cli;
+pub mod compare;
+pub mod config;
+pub mod encoding;
+pub mod fd_budget;
+pub mod file_types;
+pub mod fs_walk;
+pub mod glob;
+pub mod hex;
+pub mod index;
+pub mod mmap;
+pub mod output;
+pub mod pager;
+pub mod parallel;
+pub mod pool;
+pub mod printer;
+pub mod reader;
+pub mod regex;
+pub mod replace;
+pub mod report;
+pub mod search;
+pub mod serve;
+pub mod stats;
+pub mod watch;
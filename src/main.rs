@@ -1,14 +1,35 @@
-mod app;
-mod cli;
-mod fs_walk;
-mod output;
-mod regex;
-mod search;
-
 use std::env;
+use std::ffi::OsString;
 use std::process;
 
+use codecrafters_grep::{app, cli, config, index, serve};
+
 fn main() {
-    let cfg = cli::parse_args(env::args().collect());
+    let mut args: Vec<OsString> = env::args_os().collect();
+    if args.get(1).is_some_and(|a| a == "serve") {
+        serve::subcommand(args);
+    }
+    reset_sigpipe();
+    if args.get(1).is_some_and(|a| a == "index") {
+        match index::subcommand(args) {
+            Some(search_args) => args = search_args,
+            None => process::exit(0),
+        }
+    }
+    let cfg = cli::parse_args(config::with_defaults(args));
     process::exit(app::run(cfg));
 }
+
+// Rust ignores SIGPIPE, so once a reader hangs up (`| head`, quitting the
+// pager) every println! would panic. Searching ends quietly instead, as
+// other Unix tools do. Not for serve, which outlives clients that hang up.
+#[cfg(unix)]
+fn reset_sigpipe() {
+    // SAFETY: restoring a default disposition before any output is written
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+}
+
+#[cfg(not(unix))]
+fn reset_sigpipe() {}
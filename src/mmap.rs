@@ -0,0 +1,99 @@
+use std::fs::File;
+use std::ops::Deref;
+
+// Files smaller than this are cheaper to read than to map
+const MIN_MAP_LEN: u64 = 1024 * 1024;
+
+// A regular file mapped read-only into memory, so it can be searched without
+// copying it onto the heap. As with any mapping, a file truncated by another
+// process while it's being searched may crash the search.
+pub struct Mmap {
+    ptr: *const u8,
+    len: usize,
+}
+
+// SAFETY: the mapping is read-only and owned by this value
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    // None for pipes, special files and small files, or when mapping fails;
+    // those are read as usual
+    pub fn map(file: &File) -> Option<Mmap> {
+        let meta = file.metadata().ok()?;
+        if !meta.is_file() || meta.len() < MIN_MAP_LEN {
+            return None;
+        }
+        map_file(file, usize::try_from(meta.len()).ok()?)
+    }
+}
+
+#[cfg(unix)]
+fn map_file(file: &File, len: usize) -> Option<Mmap> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: a fresh private read-only mapping of an open descriptor; the
+    // result is checked before use
+    let ptr = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ,
+            libc::MAP_PRIVATE,
+            file.as_raw_fd(),
+            0,
+        )
+    };
+    (ptr != libc::MAP_FAILED).then(|| Mmap {
+        ptr: ptr as *const u8,
+        len,
+    })
+}
+
+#[cfg(not(unix))]
+fn map_file(_file: &File, _len: usize) -> Option<Mmap> {
+    None
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `len` mapped bytes until drop
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: unmaps exactly the range mapped in map_file
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MIN_MAP_LEN, Mmap};
+    use std::fs::{self, File};
+
+    #[cfg(unix)]
+    #[test]
+    fn maps_large_regular_files_only() {
+        let dir = std::env::temp_dir().join(format!("rust-grep-mmap-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut data = "line\n".repeat(MIN_MAP_LEN as usize / 5 + 1);
+        data.push_str("last");
+        fs::write(dir.join("big.txt"), &data).unwrap();
+        fs::write(dir.join("small.txt"), "line\n").unwrap();
+
+        let map = Mmap::map(&File::open(dir.join("big.txt")).unwrap()).unwrap();
+        assert_eq!(&map[..], data.as_bytes());
+        assert!(Mmap::map(&File::open(dir.join("small.txt")).unwrap()).is_none());
+        assert!(Mmap::map(&File::open(&dir).unwrap()).is_none());
+        drop(map);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,72 @@
+use std::borrow::Cow;
+use std::env;
+use std::ops::Range;
+use std::path::Path;
+
 const COLOR_START: &str = "\x1b[01;31m";
 const COLOR_RESET: &str = "\x1b[m";
 
+// Styles for capture groups 1, 2, 3, ... (cycled) inside a colored match
+const GROUP_COLORS: &[&str] = &[
+    "\x1b[01;32m",
+    "\x1b[01;34m",
+    "\x1b[01;33m",
+    "\x1b[01;35m",
+    "\x1b[01;36m",
+];
+
+// Styles from GREP_COLORS for what surrounds the matches, e.g.
+// `GREP_COLORS='cx=2:se=36'`: `cx` for context lines, `ln` for line numbers,
+// `bn` for -b byte offsets and `se` for the `:` after file names, labels and
+// columns. Other keys are ignored and an empty value turns a style off.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Styles {
+    pub context: String,
+    pub line_number: String,
+    pub byte_offset: String,
+    pub separator: String,
+}
+
+impl Default for Styles {
+    fn default() -> Styles {
+        Styles {
+            context: "2".to_string(),
+            line_number: "32".to_string(),
+            byte_offset: "32".to_string(),
+            separator: "36".to_string(),
+        }
+    }
+}
+
+impl Styles {
+    pub fn from_env() -> Styles {
+        env::var("GREP_COLORS").map_or_else(|_| Styles::default(), |spec| Styles::parse(&spec))
+    }
+
+    pub fn parse(spec: &str) -> Styles {
+        let mut styles = Styles::default();
+        for (key, value) in spec.split(':').filter_map(|kv| kv.split_once('=')) {
+            match key {
+                "cx" => styles.context = value.to_string(),
+                "ln" => styles.line_number = value.to_string(),
+                "bn" => styles.byte_offset = value.to_string(),
+                "se" => styles.separator = value.to_string(),
+                _ => {}
+            }
+        }
+        styles
+    }
+}
+
+// `s` wrapped in the SGR `style`, or as-is without color or a style
+pub fn paint(s: &str, style: &str, use_color: bool) -> String {
+    if use_color && !style.is_empty() {
+        format!("\x1b[{style}m{s}{COLOR_RESET}")
+    } else {
+        s.to_string()
+    }
+}
+
 pub fn maybe_colorize(s: &str, use_color: bool) -> String {
     if use_color {
         format!("{COLOR_START}{s}{COLOR_RESET}")
@@ -8,3 +74,136 @@ pub fn maybe_colorize(s: &str, use_color: bool) -> String {
         s.to_string()
     }
 }
+
+// Colors a match, giving each capture group its own style. `groups` holds
+// spans relative to the start of `s`. Nested groups have higher indices than
+// their parents, so painting in index order leaves the innermost on top.
+pub fn colorize_match(s: &str, groups: &[Option<Range<usize>>], use_color: bool) -> String {
+    if !use_color || groups.iter().all(Option::is_none) {
+        return maybe_colorize(s, use_color);
+    }
+
+    let mut styles = vec![COLOR_START; s.len()];
+    for (i, span) in groups.iter().enumerate() {
+        if let Some(span) = span {
+            let style = GROUP_COLORS[i % GROUP_COLORS.len()];
+            styles[span.clone()].fill(style);
+        }
+    }
+
+    let mut out = String::new();
+    let mut run_start = 0;
+    for (idx, _) in s.char_indices().skip(1).chain([(s.len(), ' ')]) {
+        if idx == s.len() || styles[idx] != styles[run_start] {
+            out.push_str(styles[run_start]);
+            out.push_str(&s[run_start..idx]);
+            out.push_str(COLOR_RESET);
+            run_start = idx;
+        }
+    }
+    out
+}
+
+// Paths are kept as raw OS strings everywhere else; only lossy-convert for printing
+pub fn display_path(path: &Path) -> Cow<'_, str> {
+    path.to_string_lossy()
+}
+
+const HEX_ROW: usize = 16;
+const HEX_CONTEXT_ROWS: usize = 1;
+
+// --hexdump: `hexdump -C` style rows (offset, hex bytes, ASCII gutter) for the
+// rows holding a match plus one row either side. `base` is the offset of
+// `data` in the input and `spans` are relative to `data`. Rows are aligned to
+// the input, and runs of rows that aren't adjacent are separated by `--`.
+pub fn hexdump(data: &[u8], base: usize, spans: &[Range<usize>], use_color: bool) -> Vec<String> {
+    let first_row = base / HEX_ROW;
+    let last_row = (base + data.len().max(1) - 1) / HEX_ROW;
+
+    let mut rows: Vec<usize> = Vec::new();
+    for span in spans {
+        let from = (base + span.start) / HEX_ROW;
+        let to = (base + span.end.max(span.start + 1) - 1) / HEX_ROW;
+        let from = from.saturating_sub(HEX_CONTEXT_ROWS).max(first_row);
+        let to = (to + HEX_CONTEXT_ROWS).min(last_row);
+        rows.extend(from..=to);
+    }
+    rows.sort_unstable();
+    rows.dedup();
+
+    let mut out = Vec::new();
+    for (i, &row) in rows.iter().enumerate() {
+        if i > 0 && rows[i - 1] + 1 != row {
+            out.push("--".to_string());
+        }
+
+        let mut hex = String::new();
+        let mut ascii = String::new();
+        for col in 0..HEX_ROW {
+            if col > 0 {
+                hex.push(' ');
+            }
+            if col == HEX_ROW / 2 {
+                hex.push(' ');
+            }
+            let Some(idx) = (row * HEX_ROW + col).checked_sub(base) else {
+                hex.push_str("  ");
+                ascii.push(' ');
+                continue;
+            };
+            let Some(&byte) = data.get(idx) else {
+                hex.push_str("  ");
+                ascii.push(' ');
+                continue;
+            };
+            let shown = if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            };
+            let matched = spans.iter().any(|s| s.contains(&idx));
+            hex.push_str(&maybe_colorize(
+                &format!("{byte:02x}"),
+                use_color && matched,
+            ));
+            ascii.push_str(&maybe_colorize(&shown.to_string(), use_color && matched));
+        }
+        out.push(format!("{:08x}  {hex}  |{ascii}|", row * HEX_ROW));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Styles, hexdump, paint};
+
+    #[test]
+    fn grep_colors_overrides_context_and_separator_styles() {
+        let styles = Styles::parse("ms=01;32:cx=:se=35:ln=33");
+        assert_eq!(styles.context, "");
+        assert_eq!(styles.line_number, "33");
+        assert_eq!(styles.separator, "35");
+        assert_eq!(paint(":", &styles.separator, true), "\x1b[35m:\x1b[m");
+        assert_eq!(paint("ctx", &styles.context, true), "ctx");
+        assert_eq!(Styles::parse("garbage"), Styles::default());
+    }
+
+    #[test]
+    fn hexdump_shows_match_rows_with_context() {
+        let data: Vec<u8> = (0..80).map(|i| if i == 40 { 0x1b } else { b'a' }).collect();
+        let span = 40..41;
+        let rows = hexdump(&data, 0, std::slice::from_ref(&span), false);
+        assert_eq!(rows.len(), 3);
+        assert!(rows[0].starts_with("00000010  61 61"));
+        assert!(rows[1].starts_with("00000020  61 61 61 61 61 61 61 61  1b 61"));
+        assert!(rows[1].ends_with("|aaaaaaaa.aaaaaaa|"));
+
+        // Rows are aligned to the input, not to the start of `data`
+        let span = 0..1;
+        let rows = hexdump(b"xyz", 20, std::slice::from_ref(&span), false);
+        assert_eq!(
+            rows,
+            ["00000010              78 79 7a                              |    xyz         |"]
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Child, Command, Stdio};
+
+use crate::cli::Config;
+
+// Output goes through $PAGER (default `less`) when stdout is a terminal, like
+// git. Unless LESS is already set it gets `FRX`: quit if everything fits on
+// one screen, pass colors through, and leave the output on screen.
+pub struct Pager {
+    child: Child,
+}
+
+impl Pager {
+    // None when paging doesn't apply or the pager can't be started, in which
+    // case output goes straight to stdout as usual
+    pub fn start(cfg: &Config) -> Option<Pager> {
+        if cfg.no_pager || cfg.quiet || cfg.watch || !io::stdout().is_terminal() {
+            return None;
+        }
+        let command = env::var("PAGER").unwrap_or_else(|_| "less".to_string());
+        if command.is_empty() || command == "cat" {
+            return None;
+        }
+
+        let mut pager = Command::new("sh");
+        pager.arg("-c").arg(&command).stdin(Stdio::piped());
+        if env::var_os("LESS").is_none() {
+            pager.env("LESS", "FRX");
+        }
+        let child = pager.spawn().ok()?;
+        redirect_stdout(&child)?;
+        Some(Pager { child })
+    }
+
+    // Closes our end of the pipe and waits for the user to quit the pager
+    pub fn finish(mut self) {
+        let _ = io::stdout().flush();
+        close_stdout();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(unix)]
+fn redirect_stdout(child: &Child) -> Option<()> {
+    use std::os::fd::AsRawFd;
+
+    let stdin = child.stdin.as_ref()?;
+    // SAFETY: both descriptors are open; dup2 atomically replaces fd 1
+    let rc = unsafe { libc::dup2(stdin.as_raw_fd(), libc::STDOUT_FILENO) };
+    (rc != -1).then_some(())
+}
+
+#[cfg(unix)]
+fn close_stdout() {
+    // SAFETY: nothing writes to stdout after this; the pager sees EOF
+    unsafe {
+        libc::close(libc::STDOUT_FILENO);
+    }
+}
+
+#[cfg(not(unix))]
+fn redirect_stdout(_child: &Child) -> Option<()> {
+    None
+}
+
+#[cfg(not(unix))]
+fn close_stdout() {}
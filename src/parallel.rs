@@ -0,0 +1,230 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Read};
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::printer::{Collector, MatchedLines, OwnedMatch, Printer};
+use crate::regex::RegexSet;
+use crate::search::{Input, Searcher};
+use crate::stats::Stats;
+
+// Input is cut into pieces of about this size, extended to the next newline
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+// What a worker found in one chunk
+struct ChunkResult {
+    matches: Vec<OwnedMatch>,
+    stats: Stats,
+    lines: usize, // newlines in the chunk
+    len: usize,
+}
+
+// --threads with a pipe on stdin: line-aligned chunks are matched on
+// `threads` workers while this thread reads ahead, and results are handed to
+// `printer` in input order. Only a few chunks are in flight at once, so memory
+// stays bounded however long the stream is. Not for -U, where a match may
+// cross a chunk boundary.
+pub fn search_parallel<R: BufRead>(
+    mut reader: R,
+    regexes: &RegexSet,
+    searcher: &Searcher,
+    threads: usize,
+    printer: &mut dyn Printer,
+    stats: &mut Stats,
+) -> io::Result<()> {
+    // Whether the stream looks binary is settled by its start, for every chunk
+    let first = read_chunk(&mut reader)?;
+    let input = searcher.sniff(&first);
+    if input == Input::Skip {
+        stats.files_skipped += 1;
+        return Ok(());
+    }
+    let mut first = Some(first);
+    let (task_tx, task_rx) = mpsc::channel::<(usize, Vec<u8>)>();
+    let (result_tx, result_rx) = mpsc::channel::<(usize, io::Result<ChunkResult>)>();
+    let task_rx = Mutex::new(task_rx);
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let result_tx = result_tx.clone();
+            let task_rx = &task_rx;
+            scope.spawn(move || {
+                loop {
+                    let task = task_rx.lock().unwrap().recv();
+                    let Ok((index, chunk)) = task else {
+                        return;
+                    };
+                    let result = search_chunk(&chunk, input, regexes, searcher);
+                    let _ = result_tx.send((index, result));
+                }
+            });
+        }
+        drop(result_tx);
+
+        printer.begin(None);
+        if input == Input::Binary {
+            printer.binary(None);
+        }
+        stats.files_searched += 1;
+        let lines_before = stats.matched_lines;
+        let mut emitter = Emitter::default();
+        let mut sent = 0;
+        let mut outcome = Ok(());
+        loop {
+            let chunk = match first.take().map_or_else(|| read_chunk(&mut reader), Ok) {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    outcome = Err(e);
+                    break;
+                }
+            };
+            if chunk.is_empty() {
+                break;
+            }
+            let _ = task_tx.send((sent, chunk));
+            sent += 1;
+            // Read ahead at most two chunks per worker
+            while sent - emitter.next >= threads * 2 {
+                let Ok((index, result)) = result_rx.recv() else {
+                    break;
+                };
+                if let Err(e) = emitter.accept(index, result, printer, stats) {
+                    outcome = Err(e);
+                }
+            }
+            if outcome.is_err() {
+                break;
+            }
+        }
+        drop(task_tx);
+        for (index, result) in result_rx.iter() {
+            if let Err(e) = emitter.accept(index, result, printer, stats)
+                && outcome.is_ok()
+            {
+                outcome = Err(e);
+            }
+        }
+        printer.end();
+        if stats.matched_lines > lines_before {
+            stats.files_matched += 1;
+        }
+        outcome
+    })
+}
+
+// The next CHUNK_SIZE bytes, plus whatever completes the last line
+fn read_chunk<R: BufRead>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    reader
+        .by_ref()
+        .take(CHUNK_SIZE as u64)
+        .read_to_end(&mut chunk)?;
+    if chunk.last().is_some_and(|&b| b != b'\n') {
+        reader.read_until(b'\n', &mut chunk)?;
+    }
+    Ok(chunk)
+}
+
+fn search_chunk(
+    chunk: &[u8],
+    input: Input,
+    regexes: &RegexSet,
+    searcher: &Searcher,
+) -> io::Result<ChunkResult> {
+    let mut collect = Collector::default();
+    let mut stats = Stats::new(regexes.len());
+    searcher.search_bytes_as(chunk, input, regexes, None, &mut collect, &mut stats)?;
+    Ok(ChunkResult {
+        matches: collect.0,
+        stats,
+        lines: chunk.iter().filter(|&&b| b == b'\n').count(),
+        len: chunk.len(),
+    })
+}
+
+// Holds results that arrive early until every chunk before them is printed
+#[derive(Default)]
+struct Emitter {
+    next: usize,
+    pending: BTreeMap<usize, io::Result<ChunkResult>>,
+    lines: usize,  // newlines in the chunks printed so far
+    offset: usize, // bytes in the chunks printed so far
+}
+
+impl Emitter {
+    fn accept(
+        &mut self,
+        index: usize,
+        result: io::Result<ChunkResult>,
+        printer: &mut dyn Printer,
+        stats: &mut Stats,
+    ) -> io::Result<()> {
+        self.pending.insert(index, result);
+        while let Some(result) = self.pending.remove(&self.next) {
+            self.next += 1;
+            let chunk = result?;
+            for m in &chunk.matches {
+                printer.matched(&MatchedLines {
+                    line_number: self.lines + m.line_number,
+                    offset: self.offset + m.offset,
+                    text: &m.text,
+                    matches: &m.matches,
+                });
+            }
+            stats.matches += chunk.stats.matches;
+            stats.matched_lines += chunk.stats.matched_lines;
+            stats.bytes_searched += chunk.stats.bytes_searched;
+            for (total, n) in stats.per_pattern.iter_mut().zip(&chunk.stats.per_pattern) {
+                *total += n;
+            }
+            self.lines += chunk.lines;
+            self.offset += chunk.len;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CHUNK_SIZE, search_parallel};
+    use crate::printer::Collector;
+    use crate::regex::{ParseOptions, RegexSet};
+    use crate::search::SearcherBuilder;
+    use crate::stats::Stats;
+
+    #[test]
+    fn results_keep_input_order_across_chunks() {
+        let line = "some filler text\n";
+        let mut input = String::new();
+        for i in 0..(3 * CHUNK_SIZE / line.len()) {
+            input.push_str(if i % 10_000 == 0 { "needle\n" } else { line });
+        }
+        let regexes = RegexSet::new(&["needle".to_string()], &ParseOptions::default()).unwrap();
+        let searcher = SearcherBuilder::new().build();
+
+        let mut sequential = Collector::default();
+        let mut stats = Stats::new(1);
+        searcher.search(&input, &regexes, None, &mut sequential, &mut stats);
+
+        let mut parallel = Collector::default();
+        let mut parallel_stats = Stats::new(1);
+        search_parallel(
+            input.as_bytes(),
+            &regexes,
+            &searcher,
+            4,
+            &mut parallel,
+            &mut parallel_stats,
+        )
+        .unwrap();
+
+        let key = |c: &Collector| -> Vec<(usize, usize)> {
+            c.0.iter().map(|m| (m.line_number, m.offset)).collect()
+        };
+        assert!(sequential.0.len() > 5);
+        assert_eq!(key(&parallel), key(&sequential));
+        assert_eq!(parallel_stats.matches, stats.matches);
+        assert_eq!(parallel_stats.bytes_searched, input.len());
+    }
+}
@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::fd_budget::FdBudget;
+use crate::printer::{Collector, OwnedMatch};
+use crate::regex::RegexSet;
+use crate::search::Searcher;
+use crate::stats::Stats;
+
+// Everything found in one file, or why it couldn't be read
+#[derive(Debug)]
+pub struct FileResult {
+    pub path: PathBuf,
+    pub matches: io::Result<Vec<OwnedMatch>>,
+}
+
+type Task = (PathBuf, Sender<FileResult>);
+
+// Worker threads that keep a compiled pattern set and a Searcher between
+// queries, for long-running callers (GUIs, servers) that search repeatedly.
+// Each batch of paths gets its own channel of results, which closes once
+// every file in the batch is done. Results arrive in completion order. Open
+// files are capped by an FdBudget shared by the workers.
+pub struct SearchPool {
+    tasks: Option<Sender<Task>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl SearchPool {
+    // The descriptor budget comes from the process's open file limit
+    pub fn new(regexes: RegexSet, searcher: Searcher, threads: usize) -> SearchPool {
+        SearchPool::with_fd_budget(regexes, searcher, threads, FdBudget::from_rlimit())
+    }
+
+    pub fn with_fd_budget(
+        regexes: RegexSet,
+        searcher: Searcher,
+        threads: usize,
+        fd_budget: FdBudget,
+    ) -> SearchPool {
+        let (tasks, queue) = mpsc::channel::<Task>();
+        let fd_budget = Arc::new(fd_budget);
+        let queue = Arc::new(Mutex::new(queue));
+        let regexes = Arc::new(regexes);
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let worker = Worker {
+                    queue: Arc::clone(&queue),
+                    regexes: Arc::clone(&regexes),
+                    fd_budget: Arc::clone(&fd_budget),
+                    searcher: searcher.clone(),
+                    buffer: Vec::new(),
+                };
+                thread::spawn(move || worker.run())
+            })
+            .collect();
+        SearchPool {
+            tasks: Some(tasks),
+            workers,
+        }
+    }
+
+    // Queues `paths` and returns the channel their results come back on
+    pub fn search(&self, paths: impl IntoIterator<Item = PathBuf>) -> Receiver<FileResult> {
+        let (results, receiver) = mpsc::channel();
+        let tasks = self.tasks.as_ref().expect("pool is running");
+        for path in paths {
+            // Workers only exit once `tasks` is dropped, so this can't fail
+            let _ = tasks.send((path, results.clone()));
+        }
+        receiver
+    }
+}
+
+impl Drop for SearchPool {
+    // Lets the workers finish what's queued, then waits for them
+    fn drop(&mut self) {
+        self.tasks.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+struct Worker {
+    queue: Arc<Mutex<Receiver<Task>>>,
+    regexes: Arc<RegexSet>,
+    fd_budget: Arc<FdBudget>,
+    searcher: Searcher,
+    buffer: Vec<u8>, // file contents, reused from one file to the next
+}
+
+impl Worker {
+    fn run(mut self) {
+        loop {
+            let task = self.queue.lock().unwrap().recv();
+            let Ok((path, results)) = task else {
+                return;
+            };
+            let matches = self.search_file(&path);
+            // The caller may have stopped listening; that's fine
+            let _ = results.send(FileResult { path, matches });
+        }
+    }
+
+    fn search_file(&mut self, path: &Path) -> io::Result<Vec<OwnedMatch>> {
+        self.buffer.clear();
+        {
+            let _permit = self.fd_budget.acquire();
+            File::open(path)?.read_to_end(&mut self.buffer)?;
+        }
+        let mut collect = Collector::default();
+        let mut stats = Stats::new(self.regexes.len());
+        self.searcher.search_bytes(
+            &self.buffer,
+            &self.regexes,
+            Some(path),
+            &mut collect,
+            &mut stats,
+        )?;
+        Ok(collect.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SearchPool;
+    use crate::fd_budget::FdBudget;
+    use crate::regex::{ParseOptions, RegexSet};
+    use crate::search::SearcherBuilder;
+
+    #[test]
+    fn streams_results_for_each_batch() {
+        let dir = std::env::temp_dir().join(format!("rust-grep-pool-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "one\ntwo\n").unwrap();
+        std::fs::write(dir.join("b.txt"), "three two\n").unwrap();
+
+        let regexes = RegexSet::new(&["two".to_string()], &ParseOptions::default()).unwrap();
+        let searcher = SearcherBuilder::new().build();
+        let pool = SearchPool::with_fd_budget(regexes, searcher, 4, FdBudget::new(1));
+        for _ in 0..2 {
+            let batch = ["a.txt", "b.txt", "missing.txt"].map(|f| dir.join(f));
+            let mut results: Vec<_> = pool.search(batch).iter().collect();
+            results.sort_by(|a, b| a.path.cmp(&b.path));
+            assert_eq!(results.len(), 3);
+            assert_eq!(results[0].matches.as_ref().unwrap()[0].line_number, 2);
+            assert_eq!(results[1].matches.as_ref().unwrap()[0].text, "three two");
+            assert!(results[2].matches.is_err());
+        }
+        drop(pool);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,905 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::cli::{ColumnUnit, Config, OffsetBase, OutputFormat};
+use crate::output::{Styles, colorize_match, display_path, hexdump, maybe_colorize, paint};
+use crate::regex::RegexSet;
+use crate::replace::Template;
+use crate::report::Report;
+
+// One match within a MatchedLines. `span` is relative to the event's text
+// and `groups` to the start of the match.
+#[derive(Debug, Clone)]
+pub struct MatchSpan {
+    pub span: Range<usize>,
+    pub pattern: usize,
+    pub groups: Vec<Option<Range<usize>>>,
+}
+
+// What the searcher hands a Printer: a matching line, or with -U every line
+// a run of overlapping matches touches
+#[derive(Debug, Clone)]
+pub struct MatchedLines<'a> {
+    pub line_number: usize, // of the first line
+    pub offset: usize,      // byte offset of `text` in the input
+    pub text: &'a str,      // without the final newline
+    pub matches: &'a [MatchSpan],
+}
+
+// Turns search results into output. The searcher calls `begin` and `end`
+// around each input and `matched` for every hit in between; `finish` comes
+// once after the last input. Library users can implement this to collect
+// results their own way.
+pub trait Printer {
+    // `path` is None for standard input
+    fn begin(&mut self, _path: Option<&Path>) {}
+    // The input just begun looks binary. Printers that show lines say
+    // whether it matches instead of printing what it contains.
+    fn binary(&mut self, _path: Option<&Path>) {}
+    fn matched(&mut self, event: &MatchedLines);
+    // A line shown around matches (--after-context-until and friends);
+    // `event.matches` is empty
+    fn context(&mut self, _event: &MatchedLines) {}
+    fn end(&mut self) {}
+    fn finish(&mut self) {}
+    // Whether each match gets an output line of its own (-o, --offsets), so
+    // --stats counts matches rather than lines under -U
+    fn per_match(&self) -> bool {
+        false
+    }
+}
+
+// A MatchedLines that outlives the buffer it was found in
+#[derive(Debug, Clone)]
+pub struct OwnedMatch {
+    pub line_number: usize,
+    pub offset: usize,
+    pub text: String,
+    pub matches: Vec<MatchSpan>,
+}
+
+// Keeps every event instead of printing it
+#[derive(Debug, Default)]
+pub struct Collector(pub Vec<OwnedMatch>);
+
+impl Printer for Collector {
+    fn matched(&mut self, event: &MatchedLines) {
+        self.0.push(OwnedMatch {
+            line_number: event.line_number,
+            offset: event.offset,
+            text: event.text.to_string(),
+            matches: event.matches.to_vec(),
+        });
+    }
+}
+
+// The printer for the output options in `cfg`, with `regexes` to look up
+// the named groups in a --replace template
+pub fn for_config<'c>(
+    cfg: &'c Config,
+    regexes: &RegexSet,
+    use_color: bool,
+    show_filename: bool,
+) -> Box<dyn Printer + 'c> {
+    let json = cfg.format == OutputFormat::Json;
+    let summary = |kind| Box::new(Summary::new(kind, json, show_filename));
+    // -q prints nothing, and -l and -L only names, whatever else is asked for
+    if cfg.quiet {
+        return summary(SummaryKind::Quiet);
+    }
+    if cfg.files_with_matches {
+        return summary(SummaryKind::FilesWithMatches);
+    }
+    if cfg.files_without_match {
+        return summary(SummaryKind::FilesWithoutMatch);
+    }
+    if let Some(group) = cfg.group_by {
+        return Box::new(GroupBy::new(group));
+    }
+    if let Some(depth) = cfg.dir_summary {
+        return Box::new(DirSummary::new(depth));
+    }
+    let printer: Box<dyn Printer + 'c> = match cfg.format {
+        _ if cfg.count => summary(SummaryKind::Count),
+        OutputFormat::Text => {
+            let mut printer = Standard::new(cfg, use_color, show_filename);
+            printer.replace = cfg.replace.as_deref().map(|template| {
+                let template = Template::parse(template);
+                (0..regexes.len())
+                    .map(|pattern| template.resolve(|name| regexes.group_index(pattern, name)))
+                    .collect()
+            });
+            Box::new(printer)
+        }
+        OutputFormat::Json => Box::new(Json::new(cfg)),
+        OutputFormat::Sarif | OutputFormat::Github => Box::new(Report::new(cfg)),
+    };
+    if cfg.sort_by_count {
+        return Box::new(SortByCount::new(printer));
+    }
+    printer
+}
+
+// --sort=count: holds every input's results until the end, then hands them
+// to `inner` ordered by match count, most first. Inputs with equal counts
+// keep the order they were searched in.
+pub struct SortByCount<'a> {
+    inner: Box<dyn Printer + 'a>,
+    inputs: Vec<(Option<PathBuf>, bool, Vec<OwnedMatch>)>, // path, binary, matches
+}
+
+impl<'a> SortByCount<'a> {
+    pub fn new(inner: Box<dyn Printer + 'a>) -> SortByCount<'a> {
+        SortByCount {
+            inner,
+            inputs: Vec::new(),
+        }
+    }
+}
+
+impl Printer for SortByCount<'_> {
+    fn begin(&mut self, path: Option<&Path>) {
+        self.inputs
+            .push((path.map(Path::to_path_buf), false, Vec::new()));
+    }
+
+    fn binary(&mut self, _path: Option<&Path>) {
+        if let Some((_, binary, _)) = self.inputs.last_mut() {
+            *binary = true;
+        }
+    }
+
+    fn matched(&mut self, event: &MatchedLines) {
+        if let Some((_, _, matches)) = self.inputs.last_mut() {
+            matches.push(OwnedMatch {
+                line_number: event.line_number,
+                offset: event.offset,
+                text: event.text.to_string(),
+                matches: event.matches.to_vec(),
+            });
+        }
+    }
+
+    fn finish(&mut self) {
+        let count = |matches: &[OwnedMatch]| -> usize {
+            matches.iter().map(|m| m.matches.len().max(1)).sum()
+        };
+        self.inputs
+            .sort_by_key(|(_, _, matches)| std::cmp::Reverse(count(matches)));
+        for (path, binary, matches) in self.inputs.drain(..) {
+            self.inner.begin(path.as_deref());
+            if binary {
+                self.inner.binary(path.as_deref());
+            }
+            for m in &matches {
+                self.inner.matched(&MatchedLines {
+                    line_number: m.line_number,
+                    offset: m.offset,
+                    text: &m.text,
+                    matches: &m.matches,
+                });
+            }
+            self.inner.end();
+        }
+        self.inner.finish();
+    }
+
+    fn per_match(&self) -> bool {
+        self.inner.per_match()
+    }
+}
+
+// Human-readable grep output: matching lines, or with -o / --offsets one
+// line per match
+pub struct Standard<'c> {
+    cfg: &'c Config,
+    use_color: bool,
+    show_filename: bool,
+    prefix: String,
+    separator: String, // `:`, styled per GREP_COLORS
+    context_prefix: String,
+    context_separator: String, // `-` after file names on context lines
+    group_separator: String,   // `--` between groups of context
+    context_style: String,
+    line_number_style: String,
+    byte_offset_style: String,
+    last_line: Option<usize>, // last line printed from the current input
+    printed: bool,
+    binary: bool,                   // the current input looks binary
+    binary_name: Option<String>,    // its name, until `Binary file ... matches` is printed
+    heading: Option<String>,        // --heading: the input's name, until its first line prints
+    headed: bool,                   // a heading was printed, so the next one follows a blank line
+    replace: Option<Vec<Template>>, // --replace, resolved for each pattern
+}
+
+impl<'c> Standard<'c> {
+    pub fn new(cfg: &'c Config, use_color: bool, show_filename: bool) -> Standard<'c> {
+        let styles = Styles::from_env();
+        Standard {
+            cfg,
+            use_color,
+            show_filename,
+            prefix: String::new(),
+            separator: paint(":", &styles.separator, use_color),
+            context_prefix: String::new(),
+            context_separator: paint("-", &styles.separator, use_color),
+            group_separator: paint("--", &styles.separator, use_color),
+            context_style: styles.context,
+            line_number_style: styles.line_number,
+            byte_offset_style: styles.byte_offset,
+            last_line: None,
+            printed: false,
+            binary: false,
+            binary_name: None,
+            heading: None,
+            headed: false,
+            replace: None,
+        }
+    }
+
+    // --heading: the input's name on a line of its own ahead of its first
+    // line, with a blank line between inputs in place of `--`
+    fn print_heading(&mut self) {
+        if let Some(name) = self.heading.take() {
+            if self.headed {
+                println!();
+            }
+            println!("{name}");
+            self.headed = true;
+            self.printed = false;
+        }
+    }
+
+    // -n: `N:` (or `N-` on context lines) ahead of a line's text
+    fn line_number(&self, line_number: usize, sep: &str) -> String {
+        if !self.cfg.line_number {
+            return String::new();
+        }
+        let number = paint(
+            &line_number.to_string(),
+            &self.line_number_style,
+            self.use_color,
+        );
+        format!("{number}{sep}")
+    }
+
+    // -b: `N:` (or `N-` on context lines) with the byte offset in the input
+    // of a line, or with -o of a match
+    fn byte_offset(&self, offset: usize, sep: &str) -> String {
+        if !self.cfg.byte_offset {
+            return String::new();
+        }
+        let offset = paint(&offset.to_string(), &self.byte_offset_style, self.use_color);
+        format!("{offset}{sep}")
+    }
+
+    // With context lines, `--` between groups of lines that aren't adjacent,
+    // as in grep
+    fn start_group(&mut self, event: &MatchedLines) {
+        let cfg = self.cfg;
+        let context = cfg.after_context.is_some()
+            || cfg.before_context.is_some()
+            || cfg.after_context_until.is_some()
+            || cfg.before_context_until.is_some();
+        if !context {
+            return;
+        }
+        let adjacent = self.last_line.is_some_and(|l| event.line_number == l + 1);
+        if self.printed && !adjacent {
+            println!("{}", self.group_separator);
+        }
+        self.printed = true;
+        self.last_line = Some(event.line_number + event.text.matches('\n').count());
+    }
+
+    // The match, or with --replace what's printed in its place
+    fn colorize(&self, text: &str, m: &MatchSpan) -> String {
+        let matched = &text[m.span.clone()];
+        if let Some(templates) = &self.replace {
+            let mut replaced = String::new();
+            templates[m.pattern].expand(&mut replaced, matched, &m.groups);
+            return if self.cfg.multiline {
+                colorize_lines(&replaced, self.use_color)
+            } else {
+                maybe_colorize(&replaced, self.use_color)
+            };
+        }
+        if self.cfg.multiline {
+            colorize_lines(matched, self.use_color)
+        } else {
+            colorize_match(matched, &m.groups, self.use_color)
+        }
+    }
+}
+
+impl Printer for Standard<'_> {
+    fn per_match(&self) -> bool {
+        self.cfg.use_o || self.cfg.offsets.is_some()
+    }
+
+    fn begin(&mut self, path: Option<&Path>) {
+        self.heading = None;
+        (self.prefix, self.context_prefix) = match path {
+            Some(path) if self.show_filename && self.cfg.heading => {
+                self.heading = Some(display_path(path).into_owned());
+                (String::new(), String::new())
+            }
+            Some(path) if self.show_filename => (
+                format!("{}{}", display_path(path), self.separator),
+                format!("{}{}", display_path(path), self.context_separator),
+            ),
+            _ => (String::new(), String::new()),
+        };
+        self.last_line = None;
+        self.binary = false;
+    }
+
+    fn binary(&mut self, path: Option<&Path>) {
+        self.binary = true;
+        let name = path.map_or(Cow::Borrowed("(standard input)"), display_path);
+        self.binary_name = Some(name.into_owned());
+    }
+
+    fn context(&mut self, event: &MatchedLines) {
+        if self.binary {
+            return;
+        }
+        self.print_heading();
+        self.start_group(event);
+        let mut offset = event.offset;
+        for (i, line) in event.text.split('\n').enumerate() {
+            let number = self.line_number(event.line_number + i, &self.context_separator);
+            let byte = self.byte_offset(offset, &self.context_separator);
+            offset += line.len() + 1;
+            let line = trim_indent(line, self.cfg);
+            let line = paint(line, &self.context_style, self.use_color);
+            println!("{}{number}{byte}{line}", self.context_prefix);
+        }
+    }
+
+    fn matched(&mut self, event: &MatchedLines) {
+        // As in grep, rather than dump control characters to the terminal
+        if self.binary {
+            if let Some(name) = self.binary_name.take() {
+                println!("Binary file {name} matches");
+            }
+            return;
+        }
+        self.print_heading();
+        self.start_group(event);
+        let cfg = self.cfg;
+        let prefix = &self.prefix;
+        let sep = &self.separator;
+
+        if let Some(base) = cfg.offsets {
+            for m in event.matches {
+                let before = &event.text[..m.span.start];
+                let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+                let line_number = event.line_number + before.matches('\n').count();
+                let span = m.span.start - line_start..m.span.end - line_start;
+                let prefix = format!("{prefix}{}", pattern_label(cfg, &[m.pattern], sep));
+                print_offsets(
+                    &prefix,
+                    sep,
+                    base,
+                    line_number,
+                    event.offset + line_start,
+                    span,
+                );
+            }
+            return;
+        }
+
+        if cfg.use_o {
+            for m in event.matches {
+                let lines_before = event.text[..m.span.start].matches('\n').count();
+                let number = self.line_number(event.line_number + lines_before, sep);
+                let byte = self.byte_offset(event.offset + m.span.start, sep);
+                let label = pattern_label(cfg, &[m.pattern], sep);
+                let column = column_prefix(line_before(event.text, m.span.start), cfg, sep);
+                println!(
+                    "{prefix}{number}{byte}{label}{column}{}",
+                    self.colorize(event.text, m)
+                );
+            }
+            return;
+        }
+
+        let mut buffer = String::new();
+        let mut last = 0;
+        let mut patterns = Vec::new();
+        for m in event.matches {
+            buffer.push_str(&event.text[last..m.span.start]);
+            buffer.push_str(&self.colorize(event.text, m));
+            last = m.span.end;
+            if !patterns.contains(&m.pattern) {
+                patterns.push(m.pattern);
+            }
+        }
+        buffer.push_str(&event.text[last..]);
+        let label = pattern_label(cfg, &patterns, sep);
+
+        if cfg.hexdump {
+            let spans: Vec<Range<usize>> = event.matches.iter().map(|m| m.span.clone()).collect();
+            let number = self.line_number(event.line_number, sep);
+            let byte = self.byte_offset(event.offset, sep);
+            for row in hexdump(event.text.as_bytes(), event.offset, &spans, self.use_color) {
+                println!("{prefix}{number}{byte}{label}{row}");
+            }
+            return;
+        }
+
+        // --column: only the line the first match starts on has one
+        let first = event.matches.first().map_or(0, |m| m.span.start);
+        let mut column = column_prefix(line_before(event.text, first), cfg, sep);
+        // The colored lines are longer, so offsets come from the plain ones
+        let mut offset = event.offset;
+        let mut plain = event.text.split('\n');
+        for (i, line) in buffer.split('\n').enumerate() {
+            let number = self.line_number(event.line_number + i, sep);
+            let byte = self.byte_offset(offset, sep);
+            offset += plain.next().map_or(0, |l| l.len() + 1);
+            println!(
+                "{prefix}{number}{byte}{label}{column}{}",
+                trim_indent(line, cfg)
+            );
+            column.clear();
+        }
+    }
+}
+
+// --format=json: one object per matching line (or -U block), e.g.
+// `{"path":"a.rs","line_number":3,"text":"...","matches":[{"start":4,"end":9,"pattern":"1"}]}`
+pub struct Json<'c> {
+    cfg: &'c Config,
+    path: Option<String>,
+}
+
+impl<'c> Json<'c> {
+    pub fn new(cfg: &'c Config) -> Json<'c> {
+        Json { cfg, path: None }
+    }
+}
+
+impl Printer for Json<'_> {
+    fn begin(&mut self, path: Option<&Path>) {
+        self.path = path.map(|p| display_path(p).into_owned());
+    }
+
+    fn matched(&mut self, event: &MatchedLines) {
+        let matches: Vec<_> = event
+            .matches
+            .iter()
+            .map(|m| {
+                json!({
+                    "start": m.span.start,
+                    "end": m.span.end,
+                    "pattern": self.cfg.labels[m.pattern],
+                })
+            })
+            .collect();
+        let record = json!({
+            "path": self.path,
+            "line_number": event.line_number,
+            "text": event.text,
+            "matches": matches,
+        });
+        println!("{record}");
+    }
+}
+
+// What Summary reports for each input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SummaryKind {
+    Count,             // -c: how many lines matched
+    FilesWithMatches,  // -l: its name if anything matched
+    FilesWithoutMatch, // -L: its name if nothing did
+    Quiet,             // -q: nothing; only the exit status tells
+}
+
+// -c, -l, -L and -q: a line about each input instead of its lines, e.g.
+// `a.rs:3` or `a.rs`, or with --format=json `{"path":"a.rs","count":3}`
+pub struct Summary {
+    kind: SummaryKind,
+    json: bool,
+    show_filename: bool,
+    path: Option<String>,
+    count: usize,
+}
+
+impl Summary {
+    pub fn new(kind: SummaryKind, json: bool, show_filename: bool) -> Summary {
+        Summary {
+            kind,
+            json,
+            show_filename,
+            path: None,
+            count: 0,
+        }
+    }
+}
+
+impl Printer for Summary {
+    fn begin(&mut self, path: Option<&Path>) {
+        self.path = path.map(|p| display_path(p).into_owned());
+        self.count = 0;
+    }
+
+    fn matched(&mut self, event: &MatchedLines) {
+        self.count += event.text.split('\n').count();
+    }
+
+    fn end(&mut self) {
+        let path = self.path.as_deref();
+        match self.kind {
+            SummaryKind::Count if self.json => {
+                println!("{}", json!({ "path": path, "count": self.count }));
+            }
+            SummaryKind::Count => match path {
+                Some(path) if self.show_filename => println!("{path}:{}", self.count),
+                _ => println!("{}", self.count),
+            },
+            SummaryKind::FilesWithMatches if self.count == 0 => {}
+            SummaryKind::FilesWithoutMatch if self.count > 0 => {}
+            SummaryKind::Quiet => {}
+            _ if self.json => println!("{}", json!({ "path": path })),
+            _ => println!("{}", path.unwrap_or("(standard input)")),
+        }
+    }
+}
+
+// --group-by N: counts matches by the text capture group N captured (0 for
+// the whole match) and, after the last input, prints `value:count` for each
+// distinct value, most frequent first. Matches where the group took no part
+// aren't counted.
+pub struct GroupBy {
+    group: usize,
+    counts: HashMap<String, usize>,
+}
+
+impl GroupBy {
+    pub fn new(group: usize) -> GroupBy {
+        GroupBy {
+            group,
+            counts: HashMap::new(),
+        }
+    }
+
+    // Distinct values and their counts, most frequent first, ties by value
+    pub fn counts(&self) -> Vec<(&str, usize)> {
+        let mut counts: Vec<(&str, usize)> =
+            self.counts.iter().map(|(v, &n)| (v.as_str(), n)).collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        counts
+    }
+}
+
+impl Printer for GroupBy {
+    fn matched(&mut self, event: &MatchedLines) {
+        for m in event.matches {
+            let matched = &event.text[m.span.clone()];
+            let value = match self.group {
+                0 => Some(matched),
+                n => m.groups.get(n - 1).cloned().flatten().map(|g| &matched[g]),
+            };
+            if let Some(value) = value {
+                *self.counts.entry(value.to_string()).or_default() += 1;
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        for (value, count) in self.counts() {
+            println!("{value}:{count}");
+        }
+    }
+
+    fn per_match(&self) -> bool {
+        true
+    }
+}
+
+// --summary=dir: match counts rolled up by directory, down to `depth`
+// levels, printed after the last input as an indented tree:
+//
+//     src/ 12
+//       regex/ 7
+//
+// A directory's count includes everything below it.
+pub struct DirSummary {
+    depth: usize,
+    dir: Vec<String>, // of the current input
+    counts: BTreeMap<Vec<String>, usize>,
+}
+
+impl DirSummary {
+    pub fn new(depth: usize) -> DirSummary {
+        DirSummary {
+            depth,
+            dir: Vec::new(),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    // `(indent, name, count)` rows in the order they're printed
+    pub fn rows(&self) -> Vec<(usize, String, usize)> {
+        self.counts
+            .iter()
+            .map(|(dir, &count)| {
+                let name = dir.last().map_or(".", String::as_str);
+                let name = if name.ends_with('/') {
+                    name.to_string()
+                } else {
+                    format!("{name}/")
+                };
+                (dir.len() - 1, name, count)
+            })
+            .collect()
+    }
+}
+
+impl Printer for DirSummary {
+    fn begin(&mut self, path: Option<&Path>) {
+        let parent = path.and_then(Path::parent).unwrap_or(Path::new(""));
+        self.dir = parent
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        if self.dir.is_empty() {
+            self.dir.push(".".to_string());
+        }
+    }
+
+    fn matched(&mut self, event: &MatchedLines) {
+        let n = event.matches.len().max(1);
+        for level in 1..=self.dir.len().min(self.depth) {
+            *self.counts.entry(self.dir[..level].to_vec()).or_default() += n;
+        }
+    }
+
+    fn finish(&mut self) {
+        for (indent, name, count) in self.rows() {
+            println!("{}{name} {count}", "  ".repeat(indent));
+        }
+    }
+}
+
+// The part of the line holding byte `pos` of `text` that comes before it
+fn line_before(text: &str, pos: usize) -> &str {
+    let start = text[..pos].rfind('\n').map_or(0, |i| i + 1);
+    &text[start..pos]
+}
+
+// --pattern-label: `label:` naming the patterns behind a match or line
+fn pattern_label(cfg: &Config, patterns: &[usize], sep: &str) -> String {
+    if !cfg.pattern_label {
+        return String::new();
+    }
+    let names: Vec<&str> = patterns.iter().map(|&p| cfg.labels[p].as_str()).collect();
+    format!("{}{sep}", names.join(","))
+}
+
+// --column: `N:` for a match preceded by `before_match` on its line
+fn column_prefix(before_match: &str, cfg: &Config, sep: &str) -> String {
+    match cfg.column {
+        None => String::new(),
+        Some(ColumnUnit::Bytes) => format!("{}{sep}", before_match.len() + 1),
+        Some(ColumnUnit::Chars) => format!("{}{sep}", before_match.chars().count() + 1),
+    }
+}
+
+// --trim drops leading indentation from printed lines. Offsets are always
+// computed on the original line, and color escapes are not whitespace, so an
+// indented match keeps its highlighting.
+fn trim_indent<'a>(line: &'a str, cfg: &Config) -> &'a str {
+    if cfg.trim { line.trim_start() } else { line }
+}
+
+// --offsets: `LINE:START-END` with the span relative to the line, or
+// `START-END` relative to the start of the input
+fn print_offsets(
+    prefix: &str,
+    sep: &str,
+    base: OffsetBase,
+    line_number: usize,
+    line_start: usize,
+    span: Range<usize>,
+) {
+    match base {
+        OffsetBase::Line => println!("{prefix}{line_number}{sep}{}-{}", span.start, span.end),
+        OffsetBase::File => println!(
+            "{prefix}{}-{}",
+            line_start + span.start,
+            line_start + span.end
+        ),
+    }
+}
+
+// Highlight each line of a multi-line match separately so the color never
+// bleeds into the next line's prefix
+fn colorize_lines(text: &str, use_color: bool) -> String {
+    text.split('\n')
+        .map(|piece| {
+            if piece.is_empty() {
+                String::new()
+            } else {
+                maybe_colorize(piece, use_color)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DirSummary, GroupBy, MatchedLines, Printer, SortByCount, Standard, line_before};
+    use crate::cli::Config;
+    use crate::regex::{ParseOptions, RegexSet};
+    use crate::search::SearcherBuilder;
+    use crate::stats::Stats;
+    use std::cell::RefCell;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    // Embedders can collect results instead of printing them
+    #[derive(Default)]
+    struct Collect(Vec<(usize, String, usize)>);
+
+    impl Printer for Collect {
+        fn matched(&mut self, event: &MatchedLines) {
+            let spans = event.matches.len();
+            self.0
+                .push((event.line_number, event.text.to_string(), spans));
+        }
+    }
+
+    #[test]
+    fn searcher_hands_lines_and_blocks_to_the_printer() {
+        let regexes = RegexSet::new(&["b+".to_string()], &ParseOptions::default()).unwrap();
+        let mut printer = Collect::default();
+        let mut stats = Stats::new(1);
+        let searcher = SearcherBuilder::new().build();
+        searcher.search("a\nbb b\nc\n", &regexes, None, &mut printer, &mut stats);
+        assert_eq!(printer.0, [(2, "bb b".to_string(), 2)]);
+
+        let searcher = SearcherBuilder::new().multiline(true).build();
+        let regexes = RegexSet::new(&["a\nb".to_string()], &ParseOptions::default()).unwrap();
+        let mut printer = Collect::default();
+        searcher.search("x\na\nbc\n", &regexes, None, &mut printer, &mut stats);
+        assert_eq!(printer.0, [(2, "a\nbc".to_string(), 1)]);
+    }
+
+    #[test]
+    fn group_by_counts_capture_values() {
+        let regexes =
+            RegexSet::new(&[r"HTTP/(\d)(x)?".to_string()], &ParseOptions::default()).unwrap();
+        let mut printer = GroupBy::new(1);
+        let mut stats = Stats::new(1);
+        let input = "GET HTTP/2\nHTTP/1 HTTP/2x\nnone\nHTTP/3\n";
+        let searcher = SearcherBuilder::new().build();
+        searcher.search(input, &regexes, None, &mut printer, &mut stats);
+        assert_eq!(printer.counts(), [("2", 2), ("1", 1), ("3", 1)]);
+
+        let mut printer = GroupBy::new(2);
+        searcher.search(input, &regexes, None, &mut printer, &mut stats);
+        assert_eq!(printer.counts(), [("x", 1)]);
+    }
+
+    // Records the inputs it's given, in order
+    struct Order(Rc<RefCell<Vec<String>>>);
+
+    impl Printer for Order {
+        fn begin(&mut self, path: Option<&Path>) {
+            self.0
+                .borrow_mut()
+                .push(path.unwrap().display().to_string());
+        }
+
+        fn matched(&mut self, _event: &MatchedLines) {}
+    }
+
+    #[test]
+    fn sort_by_count_puts_the_busiest_inputs_first() {
+        let regexes = RegexSet::new(&["x".to_string()], &ParseOptions::default()).unwrap();
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let mut printer = SortByCount::new(Box::new(Order(Rc::clone(&order))));
+        let mut stats = Stats::new(1);
+        let searcher = SearcherBuilder::new().build();
+        for (name, text) in [
+            ("one", "x\n"),
+            ("none", "y\n"),
+            ("three", "xx\nx\n"),
+            ("also-one", "x\n"),
+        ] {
+            searcher.search(
+                text,
+                &regexes,
+                Some(Path::new(name)),
+                &mut printer,
+                &mut stats,
+            );
+        }
+        assert!(order.borrow().is_empty());
+        printer.finish();
+        assert_eq!(*order.borrow(), ["three", "one", "also-one", "none"]);
+    }
+
+    #[test]
+    fn dir_summary_rolls_counts_up_to_the_depth() {
+        let regexes = RegexSet::new(&["x".to_string()], &ParseOptions::default()).unwrap();
+        let mut printer = DirSummary::new(2);
+        let mut stats = Stats::new(1);
+        let searcher = SearcherBuilder::new().build();
+        for (name, text) in [
+            ("src/a.rs", "x\n"),
+            ("src/regex/deep/b.rs", "xx\n"),
+            ("top.rs", "x\n"),
+            ("docs/none.md", "y\n"),
+        ] {
+            searcher.search(
+                text,
+                &regexes,
+                Some(Path::new(name)),
+                &mut printer,
+                &mut stats,
+            );
+        }
+        let rows = printer.rows();
+        let rows: Vec<(usize, &str, usize)> =
+            rows.iter().map(|(i, n, c)| (*i, n.as_str(), *c)).collect();
+        assert_eq!(rows, [(0, "./", 1), (0, "src/", 3), (1, "regex/", 2)]);
+    }
+
+    #[test]
+    fn line_numbers_only_with_dash_n() {
+        let cfg = Config {
+            line_number: true,
+            ..Default::default()
+        };
+        let printer = Standard::new(&cfg, false, false);
+        assert_eq!(printer.line_number(12, ":"), "12:");
+        assert_eq!(printer.line_number(13, "-"), "13-");
+        let cfg = Config::default();
+        assert_eq!(Standard::new(&cfg, false, false).line_number(12, ":"), "");
+        assert_eq!(Standard::new(&cfg, false, false).byte_offset(40, ":"), "");
+        let cfg = Config {
+            byte_offset: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Standard::new(&cfg, false, false).byte_offset(40, "-"),
+            "40-"
+        );
+    }
+
+    #[test]
+    fn headings_replace_the_file_name_prefix() {
+        let cfg = Config {
+            heading: true,
+            ..Default::default()
+        };
+        let mut printer = Standard::new(&cfg, false, true);
+        printer.begin(Some(Path::new("src/main.rs")));
+        assert_eq!(printer.prefix, "");
+        assert_eq!(printer.heading.as_deref(), Some("src/main.rs"));
+        // Without file names there's nothing to head
+        let mut printer = Standard::new(&cfg, false, false);
+        printer.begin(Some(Path::new("src/main.rs")));
+        assert!(printer.heading.is_none());
+        let cfg = Config::default();
+        let mut printer = Standard::new(&cfg, false, true);
+        printer.begin(Some(Path::new("src/main.rs")));
+        assert_eq!(printer.prefix, "src/main.rs:");
+    }
+
+    #[test]
+    fn line_before_stops_at_the_previous_newline() {
+        assert_eq!(line_before("ab\ncd", 4), "c");
+        assert_eq!(line_before("ab\ncd", 1), "a");
+        assert_eq!(line_before("ab\ncd", 3), "");
+    }
+}
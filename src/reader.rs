@@ -0,0 +1,115 @@
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Virtual files (/proc, /sys) report a size of 0 or one page but still produce
+// content, so the buffer is grown chunk by chunk until EOF instead of being
+// sized from metadata. With `lossy` (-a) invalid UTF-8 is replaced with U+FFFD
+// instead of failing the read.
+pub fn read_all<R: Read>(reader: R, lossy: bool) -> io::Result<String> {
+    decode(read_bytes(reader)?, lossy)
+}
+
+// Everything `reader` produces, undecoded
+pub fn read_bytes<R: Read>(mut reader: R) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => data.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(data)
+}
+
+// `f` of the first block `reader` produces (one read of up to `len` bytes,
+// as grep sniffs inputs), and a reader for the whole input again
+pub fn peek<R: Read, T>(
+    mut reader: R,
+    len: usize,
+    f: impl FnOnce(&[u8]) -> T,
+) -> io::Result<(T, impl Read)> {
+    let mut block = vec![0u8; len];
+    let n = loop {
+        match reader.read(&mut block) {
+            Ok(n) => break n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    };
+    block.truncate(n);
+    Ok((f(&block), io::Cursor::new(block).chain(reader)))
+}
+
+// Feeds `f` the input as batches of complete lines (the final batch may lack
+// its newline) and stops reading as soon as `f` returns true. Returns whether
+// it stopped early.
+pub fn read_lines_until<R: Read>(
+    mut reader: R,
+    lossy: bool,
+    mut f: impl FnMut(&str) -> bool,
+) -> io::Result<bool> {
+    let mut pending = Vec::new();
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        pending.extend_from_slice(&chunk[..n]);
+        // Batches split after a newline, so no UTF-8 sequence straddles two
+        let Some(last_newline) = pending.iter().rposition(|&b| b == b'\n') else {
+            continue;
+        };
+        let rest = pending.split_off(last_newline + 1);
+        let batch = decode(std::mem::replace(&mut pending, rest), lossy)?;
+        if f(&batch) {
+            return Ok(true);
+        }
+    }
+    if pending.is_empty() {
+        return Ok(false);
+    }
+    Ok(f(&decode(pending, lossy)?))
+}
+
+fn decode(data: Vec<u8>, lossy: bool) -> io::Result<String> {
+    match String::from_utf8(data) {
+        Ok(text) => Ok(text),
+        Err(e) if lossy => Ok(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+        Err(e) => Err(io::Error::new(ErrorKind::InvalidData, e)),
+    }
+}
+
+pub fn read_file(path: &Path, lossy: bool) -> io::Result<String> {
+    read_all(File::open(path)?, lossy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_lines_until;
+
+    #[test]
+    fn read_lines_until_stops_reading_early() {
+        let mut input = "match\n".repeat(10).into_bytes();
+        input.extend(vec![b'x'; 200 * 1024]);
+        let mut reader = &input[..];
+        let mut batches = Vec::new();
+        let stopped = read_lines_until(&mut reader, false, |batch| {
+            batches.push(batch.to_string());
+            true
+        })
+        .unwrap();
+        assert!(stopped);
+        assert_eq!(batches, ["match\n".repeat(10)]);
+        // Only the first chunk was consumed
+        assert!(!reader.is_empty());
+    }
+}
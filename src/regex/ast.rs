@@ -1,19 +1,157 @@
+use std::cmp::Ordering;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum GroupType {
     Positive, // [abc]
     Negative, // [^abc]
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repetition {
+    Greedy, // a*: as many repeats as will still let the rest match
+    Lazy,   // a*?: as few
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token {
     Literal(char),
-    Digit,
-    Alphanumeric,
+    Digit,           // \d
+    Alphanumeric,    // \w
+    Whitespace,      // \s
+    NotDigit,        // \D
+    NotAlphanumeric, // \W
+    NotWhitespace,   // \S
     Wildcard,
-    BracketGroup(Vec<char>, GroupType),
-    EndAnchor,                                    // $
-    Quantifier(Box<Token>, usize, Option<usize>), // {n,}, {n,}, {n,m}, ?, *, +
-    Alternation(Vec<Token>, Vec<Token>),          // |
-    Group(Vec<Token>, usize),                     // Index of this group
-    Backreference(usize),                         // \1, \2, etc.
+    Grapheme, // \X, or . in grapheme mode
+    BracketGroup(CharClass, GroupType),
+    StartAnchor,                                              // ^
+    EndAnchor,                                                // $
+    LineEnd,                                                  // $ in multiline mode
+    Quantifier(Box<Token>, usize, Option<usize>, Repetition), // {n}, {n,}, {n,m}, ?, *, +
+    Alternation(Vec<Token>, Vec<Token>),                      // |
+    Group(Vec<Token>, usize),                                 // Index of this group
+    NonCapturing(Vec<Token>),                                 // (?:...)
+    LookBehind(Vec<Token>),                                   // (?<=...), ending in EndAnchor
+    NegativeLookBehind(Vec<Token>),                           // (?<!...), ending in EndAnchor
+    Backreference(usize),                                     // \1, \2, etc.
+    BackreferenceFold(usize),                                 // \1 under case-insensitive matching
+    WordBoundary,                                             // \b
+    NonWordBoundary,                                          // \B
+    NotAfterWord,                                             // start of a -w match
+    NotBeforeWord,                                            // end of a -w match
+}
+
+// Members of a bracket group. Chars below 256 live in a bitmap so membership is
+// a single bit test; anything above goes into sorted, merged ranges that are
+// binary searched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharClass {
+    low: [u64; 4],
+    ranges: Vec<(char, char)>,
+    fold: bool, // a char whose lowercase form is a member counts too (-i)
+}
+
+impl CharClass {
+    pub fn new(members: impl IntoIterator<Item = char>) -> CharClass {
+        CharClass::from_ranges(members.into_iter().map(|c| (c, c)))
+    }
+
+    // Members given as inclusive ranges, e.g. `('a', 'z')` for `[a-z]`
+    pub fn from_ranges(members: impl IntoIterator<Item = (char, char)>) -> CharClass {
+        let mut low = [0u64; 4];
+        let mut high = Vec::new();
+        for (start, end) in members {
+            for n in start as u32..=(end as u32).min(255) {
+                low[(n / 64) as usize] |= 1 << (n % 64);
+            }
+            if end as u32 >= 256 {
+                high.push((start.max('\u{100}'), end));
+            }
+        }
+        high.sort_unstable();
+
+        let mut ranges: Vec<(char, char)> = Vec::new();
+        for (start, end) in high {
+            match ranges.last_mut() {
+                Some((_, last)) if *last as u32 + 1 >= start as u32 => *last = (*last).max(end),
+                _ => ranges.push((start, end)),
+            }
+        }
+        CharClass {
+            low,
+            ranges,
+            fold: false,
+        }
+    }
+
+    // Also admit chars that lowercase to a member, e.g. the Kelvin sign for
+    // a class holding `k`
+    pub fn ignoring_case(self) -> CharClass {
+        CharClass { fold: true, ..self }
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        if self.contains_exactly(c) {
+            return true;
+        }
+        let mut lower = c.to_lowercase();
+        self.fold
+            && match (lower.next(), lower.next()) {
+                (Some(l), None) => l != c && self.contains_exactly(l),
+                _ => false,
+            }
+    }
+
+    fn contains_exactly(&self, c: char) -> bool {
+        let n = c as u32;
+        if n < 256 {
+            return self.low[(n / 64) as usize] & (1 << (n % 64)) != 0;
+        }
+        self.ranges
+            .binary_search_by(|&(start, end)| {
+                if end < c {
+                    Ordering::Less
+                } else if start > c {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CharClass;
+
+    #[test]
+    fn char_class_ranges() {
+        let class = CharClass::from_ranges([
+            ('a', 'f'),
+            ('0', '9'),
+            ('\u{3b1}', '\u{3c9}'),
+            ('x', '\u{101}'),
+        ]);
+        for c in ['a', 'f', '5', 'x', '\u{ff}', '\u{101}', '\u{3b5}'] {
+            assert!(class.contains(c), "{c:?}");
+        }
+        for c in ['g', 'w', '/', '\u{102}', '\u{3ca}'] {
+            assert!(!class.contains(c), "{c:?}");
+        }
+    }
+
+    #[test]
+    fn char_class_membership() {
+        let members = "az_\u{e9}\u{3b1}\u{3b2}\u{3b3}\u{1f600}";
+        let class = CharClass::new(members.chars());
+        for c in members.chars() {
+            assert!(class.contains(c), "{c:?}");
+        }
+        for c in ['b', 'A', '\0', '\u{ff}', '\u{3b4}', '\u{1f601}'] {
+            assert!(!class.contains(c), "{c:?}");
+        }
+        // Consecutive non-Latin-1 members collapse into one range
+        assert_eq!(class.ranges.len(), 2);
+    }
 }
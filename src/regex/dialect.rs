@@ -0,0 +1,138 @@
+use std::ops::Range;
+
+// Syntax from Perl-compatible regexes that the default dialect leaves out,
+// as ripgrep's does, so a pattern written for `grep -P` fails loudly under
+// -E instead of meaning something else. Only lookbehinds are supported, and
+// only with -P; the rest aren't supported at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PerlSyntax {
+    pub name: &'static str,
+    pub syntax: &'static str,
+    pub supported: bool, // with -P
+}
+
+const GROUPS: &[PerlSyntax] = &[
+    PerlSyntax {
+        name: "lookbehind",
+        syntax: "(?<=",
+        supported: true,
+    },
+    PerlSyntax {
+        name: "negative lookbehind",
+        syntax: "(?<!",
+        supported: true,
+    },
+    PerlSyntax {
+        name: "lookahead",
+        syntax: "(?=",
+        supported: false,
+    },
+    PerlSyntax {
+        name: "negative lookahead",
+        syntax: "(?!",
+        supported: false,
+    },
+    PerlSyntax {
+        name: "atomic group",
+        syntax: "(?>",
+        supported: false,
+    },
+];
+
+pub const POSSESSIVE: PerlSyntax = PerlSyntax {
+    name: "possessive quantifier",
+    syntax: "++",
+    supported: false,
+};
+
+// Supported groups to suggest in place of an unsupported one, as a name and
+// how it opens
+pub const ALTERNATIVES: &[(&str, &str)] = &[
+    ("lookbehind", "(?<="),
+    ("negative lookbehind", "(?<!"),
+    ("non-capturing group", "(?:"),
+    ("named group", "(?<name>"),
+];
+
+// The first Perl-only construct in `pattern`, with its span.
+// Escaped chars and bracket expressions are skipped, so `\(?=` and `[(?=]`
+// are plain text.
+pub fn perl_syntax(pattern: &str) -> Option<(PerlSyntax, Range<usize>)> {
+    let bytes = pattern.as_bytes();
+    let mut i = 0;
+    let mut in_class = false;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            // A `]` right after `[` or `[^` is a member, not the end
+            b'[' if !in_class => {
+                in_class = true;
+                i += usize::from(bytes.get(i + 1) == Some(&b'^'));
+                i += usize::from(bytes.get(i + 1) == Some(&b']'));
+            }
+            b']' if in_class => in_class = false,
+            _ if in_class => {}
+            b'(' => {
+                let rest = &pattern[i..];
+                if let Some(group) = GROUPS.iter().find(|g| rest.starts_with(g.syntax)) {
+                    return Some((*group, i..i + group.syntax.len()));
+                }
+                // The `?` of `(?` isn't a quantifier
+                i += usize::from(rest.starts_with("(?"));
+            }
+            b'{' => {
+                // Only a `{n,m}` repetition can be possessive
+                if let Some(len) = pattern[i..].find('}')
+                    && pattern[i + 1..i + len]
+                        .bytes()
+                        .all(|b| b.is_ascii_digit() || b == b',')
+                {
+                    if bytes.get(i + len + 1) == Some(&b'+') {
+                        return Some((POSSESSIVE, i..i + len + 2));
+                    }
+                    i += len;
+                }
+            }
+            b'+' | b'*' | b'?' if i > 0 && bytes.get(i + 1) == Some(&b'+') => {
+                return Some((POSSESSIVE, i..i + 2));
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::perl_syntax;
+
+    fn found(pattern: &str) -> Option<(&'static str, &str)> {
+        perl_syntax(pattern).map(|(syntax, span)| (syntax.name, &pattern[span]))
+    }
+
+    #[test]
+    fn finds_perl_only_syntax_outside_escapes_and_classes() {
+        assert_eq!(found(r"(?<=\$)\d+"), Some(("lookbehind", "(?<=")));
+        assert_eq!(found("x(?<!-)y"), Some(("negative lookbehind", "(?<!")));
+        assert_eq!(found("a(?=b)"), Some(("lookahead", "(?=")));
+        assert_eq!(found("(?>ab)"), Some(("atomic group", "(?>")));
+        assert_eq!(found("a++"), Some(("possessive quantifier", "++")));
+        assert_eq!(found("a{2,3}+b"), Some(("possessive quantifier", "{2,3}+")));
+        assert_eq!(found("(?:a)?+"), Some(("possessive quantifier", "?+")));
+        // Everything here is plain -E syntax
+        for pattern in [
+            r"\(?=",
+            "[(?=]",
+            "[]++]",
+            "[^]?+]",
+            r"a\++",
+            "(?:a|b)+",
+            "(?<name>a)+?",
+            "(?i)ab*",
+            "a+?",
+        ] {
+            assert_eq!(found(pattern), None, "{pattern}");
+        }
+    }
+}
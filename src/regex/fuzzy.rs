@@ -0,0 +1,208 @@
+use std::ops::Range;
+
+use crate::regex::ast::Token;
+use crate::regex::matcher::matches_token;
+use crate::regex::parser::{ParseOptions, parse_regex_with};
+
+// Bit-parallel state holds one bit per pattern item
+const MAX_ITEMS: usize = 64;
+
+// --fuzzy: approximate matching with up to `max_edits` insertions, deletions
+// or substitutions, agrep-style. Ends are found with the Wu-Manber bitap
+// algorithm, so only patterns made of single-character items (literals,
+// classes, `.`) are supported.
+#[derive(Debug, Clone)]
+pub struct FuzzyPattern {
+    items: Vec<Token>,
+    max_edits: usize,
+    anchored: bool, // a leading `^`: matches must start a line
+}
+
+impl FuzzyPattern {
+    pub fn new(pattern: &str, max_edits: usize, opts: &ParseOptions) -> Option<FuzzyPattern> {
+        let mut items = parse_regex_with(pattern, opts).ok()?;
+        let anchored = items.first() == Some(&Token::StartAnchor);
+        if anchored {
+            items.remove(0);
+        }
+        let supported = !items.is_empty()
+            && items.len() <= MAX_ITEMS
+            && items.iter().all(|t| {
+                matches!(
+                    t,
+                    Token::Literal(_)
+                        | Token::Digit
+                        | Token::Alphanumeric
+                        | Token::Whitespace
+                        | Token::NotDigit
+                        | Token::NotAlphanumeric
+                        | Token::NotWhitespace
+                        | Token::Wildcard
+                        | Token::BracketGroup(..)
+                )
+            });
+        // More edits than items would match anywhere; capping also keeps the
+        // initial state shifts in range
+        let max_edits = max_edits.min(items.len()).min(MAX_ITEMS - 1);
+        supported.then_some(FuzzyPattern {
+            items,
+            max_edits,
+            anchored,
+        })
+    }
+
+    pub fn is_anchored(&self) -> bool {
+        self.anchored
+    }
+
+    // The leftmost approximate match in `text`
+    pub fn find(&self, text: &str) -> Option<Range<usize>> {
+        let end = self.find_end(text)?;
+        Some(self.find_start(text, end)..end)
+    }
+
+    // Byte offset just past the leftmost match end. Once an end within budget
+    // turns up, the next few chars are checked for one needing fewer edits,
+    // so `hello` isn't cut short to `hell` plus a deletion. Otherwise the
+    // earliest end wins, e.g. `v\d\d` with one edit matches just `v1` in
+    // `v1x0`.
+    fn find_end(&self, text: &str) -> Option<usize> {
+        let k = self.max_edits;
+        let accept = 1u64 << (self.items.len() - 1);
+        // r[d]: bit i set when items[..=i] match a suffix of the text so far
+        // with at most d edits
+        let mut r: Vec<u64> = (0..=k).map(|d| (1u64 << d) - 1).collect();
+
+        let mut best: Option<(usize, usize)> = None; // (edits, end)
+        let mut lookahead = 0;
+        for (i, c) in text.char_indices() {
+            let mask = self.mask(c);
+            let mut prev_old = r[0];
+            r[0] = ((r[0] << 1) | 1) & mask;
+            for d in 1..=k {
+                let old = r[d];
+                r[d] = (((old << 1) | 1) & mask) // match
+                    | prev_old // extra char in the text
+                    | ((prev_old << 1) | 1) // substitution
+                    | ((r[d - 1] << 1) | 1); // item missing from the text
+                prev_old = old;
+            }
+
+            let end = i + c.len_utf8();
+            if let Some(edits) = (0..=k).find(|&d| r[d] & accept != 0)
+                && best.is_none_or(|(best_edits, _)| edits < best_edits)
+            {
+                best = Some((edits, end));
+            }
+            if best.is_some() {
+                if lookahead == k || best.is_some_and(|(edits, _)| edits == 0) {
+                    break;
+                }
+                lookahead += 1;
+            }
+        }
+        best.map(|(_, end)| end)
+    }
+
+    // Whether all of `text` is within the edit budget of the pattern (-x)
+    pub fn matches_whole(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        if chars.len().abs_diff(self.items.len()) > self.max_edits {
+            return false;
+        }
+        // row[j]: edits between the items so far and chars[..j]
+        let mut row: Vec<usize> = (0..=chars.len()).collect();
+        for (i, item) in self.items.iter().enumerate() {
+            let mut diagonal = row[0];
+            row[0] = i + 1;
+            for (j, &c) in chars.iter().enumerate() {
+                let cost = usize::from(!matches_token(item, c));
+                let next = (diagonal + cost).min(row[j] + 1).min(row[j + 1] + 1);
+                diagonal = row[j + 1];
+                row[j + 1] = next;
+            }
+        }
+        row[chars.len()] <= self.max_edits
+    }
+
+    // Start of the match ending at `end`: the one with the fewest edits,
+    // preferring the longest on a tie
+    fn find_start(&self, text: &str, end: usize) -> usize {
+        let m = self.items.len();
+        let mut window: Vec<(usize, char)> = text[..end]
+            .char_indices()
+            .rev()
+            .take(m + self.max_edits)
+            .collect();
+        window.reverse();
+        let n = window.len();
+
+        // dist[i][j]: edits between items[i..] and window[j..]
+        let mut dist = vec![vec![0; n + 1]; m + 1];
+        for (j, d) in dist[m].iter_mut().enumerate() {
+            *d = n - j;
+        }
+        for i in (0..m).rev() {
+            dist[i][n] = m - i;
+            for j in (0..n).rev() {
+                let cost = usize::from(!matches_token(&self.items[i], window[j].1));
+                dist[i][j] = (dist[i + 1][j + 1] + cost)
+                    .min(dist[i + 1][j] + 1)
+                    .min(dist[i][j + 1] + 1);
+            }
+        }
+
+        let best = (0..n).min_by_key(|&j| dist[0][j]).unwrap_or(n);
+        window.get(best).map_or(end, |&(offset, _)| offset)
+    }
+
+    fn mask(&self, c: char) -> u64 {
+        self.items
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| matches_token(t, c))
+            .fold(0, |mask, (i, _)| mask | (1 << i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FuzzyPattern;
+    use crate::regex::ParseOptions;
+
+    fn find(pattern: &str, k: usize, text: &str) -> Option<String> {
+        let fuzzy = FuzzyPattern::new(pattern, k, &ParseOptions::default()).unwrap();
+        fuzzy.find(text).map(|r| text[r].to_string())
+    }
+
+    #[test]
+    fn finds_matches_within_the_edit_budget() {
+        assert_eq!(find("hello", 0, "say hello"), Some("hello".into()));
+        assert_eq!(find("hello", 1, "say hello"), Some("hello".into()));
+        assert_eq!(find("hello", 1, "say helo there"), Some("helo".into()));
+        assert_eq!(find("hello", 1, "say hallo there"), Some("hallo".into()));
+        assert_eq!(find("hello", 1, "say hemllo there"), Some("hemllo".into()));
+        assert_eq!(find("hello", 1, "say hxllx there"), None);
+        assert_eq!(find("hello", 2, "say hxllx there"), Some("hxll".into()));
+        assert_eq!(find(r"v\d\d", 1, "version v1x0"), Some("v1".into()));
+    }
+
+    #[test]
+    fn whole_text_matches_count_every_edit() {
+        let fuzzy = FuzzyPattern::new("fox", 1, &ParseOptions::default()).unwrap();
+        for text in ["fox", "foo", "foxy", "fx"] {
+            assert!(fuzzy.matches_whole(text), "{text}");
+        }
+        for text in ["fooo", "a fox", ""] {
+            assert!(!fuzzy.matches_whole(text), "{text}");
+        }
+    }
+
+    #[test]
+    fn rejects_patterns_bitap_cannot_express() {
+        let opts = ParseOptions::default();
+        assert!(FuzzyPattern::new("ab+", 1, &opts).is_none());
+        assert!(FuzzyPattern::new("(ab)", 1, &opts).is_none());
+        assert!(FuzzyPattern::new("ab$", 1, &opts).is_none());
+    }
+}
@@ -1,163 +1,792 @@
-use crate::regex::ast::{GroupType, Token};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 
-fn matches_token(token: &Token, c: char) -> bool {
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::regex::ast::{CharClass, GroupType, Repetition, Token};
+use crate::regex::pikevm::Found;
+use crate::regex::prefilter::Prefilter;
+
+// Settings that apply to a whole match rather than to individual tokens
+#[derive(Debug, Clone)]
+pub struct MatchContext {
+    pub ascii: bool, // ASCII-only case folding and byte-level fast paths
+    // Besides letters and digits, what `\b` and -w count as part of a word
+    // (--word-chars)
+    pub word_chars: CharClass,
+}
+
+impl Default for MatchContext {
+    fn default() -> MatchContext {
+        MatchContext {
+            ascii: false,
+            word_chars: CharClass::new(['_']),
+        }
+    }
+}
+
+impl MatchContext {
+    fn is_word(&self, c: char) -> bool {
+        let alphanumeric = if self.ascii {
+            c.is_ascii_alphanumeric()
+        } else {
+            c.is_alphanumeric()
+        };
+        alphanumeric || self.word_chars.contains(c)
+    }
+}
+
+// Whether the chars either side of byte `pos` of `haystack` are word chars
+pub fn word_around(haystack: &str, pos: usize, ctx: &MatchContext) -> (bool, bool) {
+    let before = haystack[..pos].chars().next_back();
+    let after = haystack[pos..].chars().next();
+    (
+        before.is_some_and(|c| ctx.is_word(c)),
+        after.is_some_and(|c| ctx.is_word(c)),
+    )
+}
+
+pub fn matches_token(token: &Token, c: char) -> bool {
     match token {
         Token::Wildcard => true,
         Token::Literal(l) => c == *l,
         Token::Digit => c.is_ascii_digit(),
-        Token::Alphanumeric => c.is_ascii_alphanumeric() || c == '_',
+        Token::Alphanumeric => is_word_char(c),
+        Token::Whitespace => is_space(c),
+        Token::NotDigit => !c.is_ascii_digit(),
+        Token::NotAlphanumeric => !is_word_char(c),
+        Token::NotWhitespace => !is_space(c),
         Token::BracketGroup(members, group_type) => {
-            let found = members.contains(&c);
+            let found = members.contains(c);
             match group_type {
                 GroupType::Positive => found,
                 GroupType::Negative => !found,
             }
         }
-        _ => false, // This covers EndAnchor and any other future positional tokens
+        _ => false, // This covers the anchors and any other future positional tokens
     }
 }
 
-// Checks if the pattern matches starting exactly at the beginning of 'text'
-fn match_here(tokens: &[Token], text: &str, captures: &mut Vec<Option<String>>) -> Option<usize> {
-    if tokens.is_empty() {
-        return Some(0); // Pattern exhausted, we matched!
+// Tokens that always match exactly one char
+fn is_single_char(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Literal(_)
+            | Token::Digit
+            | Token::Alphanumeric
+            | Token::Whitespace
+            | Token::NotDigit
+            | Token::NotAlphanumeric
+            | Token::NotWhitespace
+            | Token::Wildcard
+            | Token::BracketGroup(..)
+    )
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// Space, \t, \n, \r, \f and \v, as in PCRE
+fn is_space(c: char) -> bool {
+    c.is_ascii_whitespace() || c == '\x0b'
+}
+
+// Character equality shared by every comparison that may ignore case
+fn chars_eq(a: char, b: char, fold: bool, ascii: bool) -> bool {
+    if a == b || !fold {
+        return a == b;
+    }
+    if ascii {
+        a.eq_ignore_ascii_case(&b)
+    } else {
+        a.to_lowercase().eq(b.to_lowercase())
     }
+}
 
-    match &tokens[0] {
-        Token::EndAnchor => {
-            if text.is_empty() {
-                Some(0)
-            } else {
-                None
-            }
+// First character of `text` and its byte length. In ASCII mode an ASCII byte
+// is taken as-is without going through UTF-8 decoding.
+fn next_char(text: &str, ascii: bool) -> Option<(char, usize)> {
+    let &b = text.as_bytes().first()?;
+    if ascii && b.is_ascii() {
+        return Some((b as char, 1));
+    }
+    let c = text.chars().next()?;
+    Some((c, c.len_utf8()))
+}
+
+// Byte length of the prefix of `text` equal to `captured`. Folded lengths can
+// differ from the capture's, so the text side is measured separately.
+fn backref_len(captured: &str, text: &str, fold: bool, ascii: bool) -> Option<usize> {
+    if !fold {
+        return text.starts_with(captured).then_some(captured.len());
+    }
+    let mut text_chars = text.char_indices();
+    for c in captured.chars() {
+        let (_, t) = text_chars.next()?;
+        if !chars_eq(c, t, true, ascii) {
+            return None;
         }
-        Token::Alternation(left, right) => {
-            // We need to find the best match at this position.
-            // Standard engines usually pick the first branch that results in a successful
-            // match for the whole pattern.
-
-            // Try Left branch + rest
-            let mut left_captures = captures.clone();
-            if let Some(left_len) = match_here(left, text, &mut left_captures) {
-                if let Some(rest_len) =
-                    match_here(&tokens[1..], &text[left_len..], &mut left_captures)
-                {
-                    *captures = left_captures;
-                    return Some(left_len + rest_len);
-                }
+    }
+    Some(text_chars.next().map_or(text.len(), |(i, _)| i))
+}
+
+// A counted repeat is copied out once per repeat, as the Pike VM does, unless
+// that takes more instructions than this, as `(?:a|b){5000}` would. Then its
+// body is looped over with a counter instead.
+const MAX_COPIED_INSTS: usize = 10_000;
+
+// States remembered before the memo is cleared for the next start. A group
+// a backreference reads that's still being matched keys states by where it
+// started, so with `(.*)x\1` no start shares any with the next.
+const MAX_MEMO: usize = 1 << 20;
+
+// One step of a compiled pattern: the Pike VM's, plus what only the
+// backtracker can do
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(Token),          // one char matching a single-char token
+    Grapheme,             // a whole extended grapheme cluster
+    Assert(Token),        // `^`, `$`, `\b` and the like; consumes nothing
+    Backref(usize, bool), // the text a group captured, ignoring case if set
+    LookBehind(Box<LookBehind>),
+    Split(usize, usize), // go both ways, the first with higher priority
+    Jump(usize),
+    Open(usize),  // a group starts here, by index from 0
+    Close(usize), // and ends here, capturing the text in between
+    // A counted repeat's counter is zeroed on the way in and out, and its
+    // head picks between another repeat and going on to `exit`
+    Reset(usize),
+    Count {
+        counter: usize,
+        min: usize,
+        max: usize,
+        greedy: bool,
+        exit: usize,
+    },
+    CountUp(usize, usize), // counter, then back to the head
+    Match,
+}
+
+#[derive(Debug, Clone)]
+struct LookBehind {
+    negated: bool,
+    // With one single-char token per char, as in `(?<=id: )`, the text is
+    // compared backwards instead of running `insts`
+    chars: Option<Vec<Token>>,
+    insts: Vec<Inst>, // ending in `$`, run with the text cut short where it is
+    reach: usize,     // the most chars it can match
+}
+
+// A pattern compiled for the backtracker. The ways through it are tried one
+// at a time, in the order the Pike VM ranks them, so both find the same
+// match. Since where a match can go from an instruction depends only on the
+// offset, the counters and what backreferences will read, each such state
+// that fails is remembered and not tried again, from any start: matching
+// takes time polynomial in the text, as `.*.*.*x` and `(a|aa)*c\1` would not
+// otherwise.
+#[derive(Debug, Clone)]
+pub struct Backtracker {
+    insts: Vec<Inst>,
+    groups: usize,
+    counters: usize,   // one per repeat looped over with a counter
+    keyed: Vec<usize>, // groups backreferences read, by index from 0
+}
+
+// States already tried: instruction, offset, and the number Run::state gave
+// the rest
+type Memo = HashSet<(usize, usize, usize)>;
+
+impl Backtracker {
+    pub fn compile(tokens: &[Token]) -> Backtracker {
+        let mut compiler = Compiler {
+            insts: Vec::new(),
+            groups: 0,
+            counters: 0,
+        };
+        compiler.sequence(tokens);
+        compiler.push(Inst::Match);
+        Backtracker {
+            insts: compiler.insts,
+            groups: compiler.groups,
+            counters: compiler.counters,
+            keyed: referenced_groups(tokens)
+                .into_iter()
+                .filter(|&id| id > 0 && id <= compiler.groups)
+                .map(|id| id - 1)
+                .collect(),
+        }
+    }
+
+    // The match starting exactly at byte `pos`, as Regex::captures_at
+    // reports it
+    pub fn captures_at<'a>(
+        &self,
+        haystack: &'a str,
+        pos: usize,
+        ctx: &MatchContext,
+    ) -> Option<(&'a str, Vec<Option<Range<usize>>>)> {
+        let mut run = Run::new(self, haystack, ctx);
+        let end = run.run(&self.insts, pos, haystack.len(), &mut Memo::new())?;
+        Some(run.report(pos, end))
+    }
+
+    // The leftmost match starting at or after byte `pos`, with its start.
+    // What failed from one start isn't tried again from the next.
+    pub fn find_from<'a>(
+        &self,
+        haystack: &'a str,
+        mut pos: usize,
+        ctx: &MatchContext,
+        prefilter: Option<&Prefilter>,
+    ) -> Option<Found<'a>> {
+        let mut run = Run::new(self, haystack, ctx);
+        let mut memo = Memo::new();
+        loop {
+            if let Some(prefilter) = prefilter {
+                pos = prefilter.find(haystack, pos)?;
             }
-            let mut right_captures = captures.clone();
-            if let Some(right_len) = match_here(right, text, &mut right_captures) {
-                if let Some(rest_len) =
-                    match_here(&tokens[1..], &text[right_len..], &mut right_captures)
-                {
-                    *captures = right_captures;
-                    return Some(right_len + rest_len);
-                }
+            if memo.len() > MAX_MEMO {
+                memo.clear();
+                run.forget_states();
             }
-            None
+            if let Some(end) = run.run(&self.insts, pos, haystack.len(), &mut memo) {
+                let (m, groups) = run.report(pos, end);
+                return Some((pos, m, groups));
+            }
+            pos += haystack[pos..].chars().next()?.len_utf8();
         }
-        Token::Group(inner_tokens, id) => {
-            // Ensure the Vec is big enough to hold this group ID
-            if captures.len() < *id {
-                captures.resize(*id, None);
+    }
+}
+
+// What's left to do when the current way through fails: another way, or
+// putting back what it changed
+enum Frame {
+    Step(usize, usize), // instruction, offset
+    Uncapture(usize, Option<(usize, usize)>),
+    Unopen(usize, Option<usize>),
+    Uncount(usize, usize),
+}
+
+// Matching a Backtracker against one haystack
+struct Run<'a, 'p, 'c> {
+    haystack: &'a str,
+    ctx: &'c MatchContext,
+    keyed: &'p [usize],
+    captures: Vec<Option<(usize, usize)>>, // each group's start and end, if any
+    opens: Vec<Option<usize>>,             // where groups being matched started
+    counters: Vec<usize>,
+    stack: Vec<Frame>,
+    // Numbers for the texts keyed groups captured, by span, and for states
+    texts: HashMap<&'a str, usize>,
+    spans: HashMap<(usize, usize), usize>,
+    states: HashMap<Vec<usize>, usize>,
+    state: Vec<usize>, // kept between calls to state for its allocation
+}
+
+impl<'a, 'p> Run<'a, 'p, '_> {
+    fn new<'c>(
+        backtracker: &'p Backtracker,
+        haystack: &'a str,
+        ctx: &'c MatchContext,
+    ) -> Run<'a, 'p, 'c> {
+        Run {
+            haystack,
+            ctx,
+            keyed: &backtracker.keyed,
+            captures: vec![None; backtracker.groups],
+            opens: vec![None; backtracker.groups],
+            counters: vec![0; backtracker.counters],
+            stack: Vec::new(),
+            texts: HashMap::new(),
+            spans: HashMap::new(),
+            states: HashMap::new(),
+            state: Vec::new(),
+        }
+    }
+
+    // Where the first way through `insts` from `pos` ends, with the text cut
+    // short at `end`. Captures are left as that way made them; on failure
+    // they're put back.
+    fn run(&mut self, insts: &'p [Inst], pos: usize, end: usize, memo: &mut Memo) -> Option<usize> {
+        let base = self.stack.len();
+        self.stack.push(Frame::Step(0, pos));
+        let mut found = None;
+        while found.is_none() && self.stack.len() > base {
+            match self.stack.pop().expect("checked above") {
+                Frame::Step(pc, pos) => found = self.step(insts, pc, pos, end, memo),
+                Frame::Uncapture(group, old) => self.captures[group] = old,
+                Frame::Unopen(group, old) => self.opens[group] = old,
+                Frame::Uncount(counter, old) => self.counters[counter] = old,
             }
+        }
+        self.stack.truncate(base);
+        found
+    }
 
-            // Standard engines try to match as much as possible, then backtrack.
-            for try_len in (0..=text.len()).rev() {
-                let mut inner_caps = captures.clone();
-
-                if let Some(group_len) = match_here(inner_tokens, &text[..try_len], &mut inner_caps)
-                {
-                    // The inner match must consume exactly the length we are testing
-                    if group_len == try_len {
-                        inner_caps[*id - 1] = Some(text[..group_len].to_string());
-
-                        if let Some(rest_len) =
-                            match_here(&tokens[1..], &text[group_len..], &mut inner_caps)
-                        {
-                            *captures = inner_caps;
-                            return Some(group_len + rest_len);
-                        }
+    // Follows one way through from `pc` until it matches or fails, leaving
+    // a frame for each other way it passes
+    fn step(
+        &mut self,
+        insts: &'p [Inst],
+        mut pc: usize,
+        mut pos: usize,
+        end: usize,
+        memo: &mut Memo,
+    ) -> Option<usize> {
+        loop {
+            let text = &self.haystack[pos..end];
+            match &insts[pc] {
+                Inst::Match => return Some(pos),
+                Inst::Char(token) => match next_char(text, self.ctx.ascii) {
+                    Some((c, len)) if matches_token(token, c) => pos += len,
+                    _ => return None,
+                },
+                Inst::Grapheme => pos += text.graphemes(true).next()?.len(),
+                Inst::Assert(token) => {
+                    if !self.holds(token, pos, end) {
+                        return None;
+                    }
+                }
+                Inst::Backref(group, fold) => {
+                    let captured = self.captured(*group)?;
+                    pos += backref_len(captured, text, *fold, self.ctx.ascii)?;
+                }
+                Inst::LookBehind(look) => {
+                    if !self.look_behind(look, pos) {
+                        return None;
+                    }
+                }
+                // Only where ways split can two meet again, so that's where
+                // states are remembered. A loop that consumes nothing ends
+                // here too.
+                Inst::Split(first, second) => {
+                    if !self.visit(memo, pc, pos) {
+                        return None;
                     }
+                    self.stack.push(Frame::Step(*second, pos));
+                    pc = *first;
+                    continue;
+                }
+                Inst::Jump(to) => {
+                    pc = *to;
+                    continue;
+                }
+                Inst::Open(group) => {
+                    let old = self.opens[*group].replace(pos);
+                    self.stack.push(Frame::Unopen(*group, old));
+                }
+                Inst::Close(group) => {
+                    let start = self.opens[*group].take().expect("opened first");
+                    self.stack.push(Frame::Unopen(*group, Some(start)));
+                    let old = self.captures[*group].replace((start, pos));
+                    self.stack.push(Frame::Uncapture(*group, old));
+                }
+                Inst::Reset(counter) => self.set_counter(*counter, 0),
+                Inst::Count {
+                    counter,
+                    min,
+                    max,
+                    greedy,
+                    exit,
+                } => {
+                    if !self.visit(memo, pc, pos) {
+                        return None;
+                    }
+                    let n = self.counters[*counter];
+                    pc = if n < *min {
+                        pc + 1
+                    } else if n == *max {
+                        *exit
+                    } else {
+                        let (first, second) = if *greedy {
+                            (pc + 1, *exit)
+                        } else {
+                            (*exit, pc + 1)
+                        };
+                        self.stack.push(Frame::Step(second, pos));
+                        first
+                    };
+                    continue;
+                }
+                Inst::CountUp(counter, head) => {
+                    self.set_counter(*counter, self.counters[*counter] + 1);
+                    pc = *head;
+                    continue;
                 }
             }
-            None
+            pc += 1;
+        }
+    }
+
+    // Records that the state at `pc` and `pos` is being tried; false if it
+    // was before. A second try would go exactly as the first, which can
+    // only have failed, or else be going round a loop that consumed nothing.
+    fn visit(&mut self, memo: &mut Memo, pc: usize, pos: usize) -> bool {
+        let state = self.state();
+        memo.insert((pc, pos, state))
+    }
+
+    // A number for what besides the instruction and offset decides how the
+    // match goes on: the text each keyed group captured, so the same text
+    // captured from elsewhere counts the same, where any keyed group being
+    // matched started, and the counters
+    fn state(&mut self) -> usize {
+        if self.keyed.is_empty() && self.counters.is_empty() {
+            return 0;
         }
-        Token::Backreference(n) => {
-            // Check if we have a capture for this index
-            if let Some(Some(captured_val)) = captures.get(*n - 1) {
-                if text.starts_with(captured_val.as_str()) {
-                    let len = captured_val.len();
-                    return match_here(&tokens[1..], &text[len..], captures)
-                        .map(|rest_len| len + rest_len);
+        let mut state = std::mem::take(&mut self.state);
+        state.clear();
+        for &group in self.keyed {
+            let text = self.captures[group].map_or(usize::MAX, |span| {
+                *self.spans.entry(span).or_insert_with(|| {
+                    let next = self.texts.len();
+                    *self
+                        .texts
+                        .entry(&self.haystack[span.0..span.1])
+                        .or_insert(next)
+                })
+            });
+            state.extend([text, self.opens[group].unwrap_or(usize::MAX)]);
+        }
+        state.extend_from_slice(&self.counters);
+        let id = match self.states.get(&state) {
+            Some(&id) => id,
+            None => {
+                let id = self.states.len();
+                self.states.insert(state.clone(), id);
+                id
+            }
+        };
+        self.state = state;
+        id
+    }
+
+    // Drops the numbers given out by state, once no memo holds them
+    fn forget_states(&mut self) {
+        self.texts.clear();
+        self.spans.clear();
+        self.states.clear();
+    }
+
+    fn set_counter(&mut self, counter: usize, n: usize) {
+        let old = std::mem::replace(&mut self.counters[counter], n);
+        self.stack.push(Frame::Uncount(counter, old));
+    }
+
+    // What group `id` captured, if anything
+    fn captured(&self, id: usize) -> Option<&'a str> {
+        let (start, end) = self.captures.get(id.checked_sub(1)?).copied()??;
+        Some(&self.haystack[start..end])
+    }
+
+    // Whether an assertion holds at `pos`, with the text ending at `end`
+    fn holds(&self, token: &Token, pos: usize, end: usize) -> bool {
+        let bytes = self.haystack.as_bytes();
+        match token {
+            Token::StartAnchor => pos == 0 || bytes[pos - 1] == b'\n',
+            Token::EndAnchor => pos == end,
+            Token::LineEnd => pos == end || bytes[pos] == b'\n',
+            _ => {
+                let (before, after) = word_around(self.haystack, pos, self.ctx);
+                match token {
+                    Token::WordBoundary => before != after,
+                    Token::NonWordBoundary => before == after,
+                    Token::NotAfterWord => !before,
+                    _ => !after,
                 }
             }
-            None
         }
-        Token::Quantifier(inner, min, max) => {
-            // If we've hit the maximum allowed matches (Some(0)), move to the rest of the pattern
-            if let Some(0) = max {
-                return match_here(&tokens[1..], text, captures);
+    }
+
+    // Whether some text ending at `at` matches the lookbehind, trying the
+    // nearest start first. It's settled once and for all: groups a positive
+    // one captured are kept, to be put back on backtracking.
+    fn look_behind(&mut self, look: &'p LookBehind, at: usize) -> bool {
+        let haystack = self.haystack;
+        if let Some(chars) = &look.chars {
+            let mut before = haystack[..at].chars().rev();
+            let found = chars
+                .iter()
+                .rev()
+                .all(|t| before.next().is_some_and(|c| matches_token(t, c)));
+            return found != look.negated;
+        }
+        let (captures, opens, counters) = (
+            self.captures.clone(),
+            self.opens.clone(),
+            self.counters.clone(),
+        );
+        let mut memo = Memo::new();
+        let found = std::iter::once(at)
+            .chain(haystack[..at].char_indices().rev().map(|(i, _)| i))
+            .take(look.reach.saturating_add(1))
+            .any(|start| self.run(&look.insts, start, at, &mut memo).is_some());
+        (self.opens, self.counters) = (opens, counters);
+        if found && !look.negated {
+            for (group, old) in captures.into_iter().enumerate() {
+                if self.captures[group] != old {
+                    self.stack.push(Frame::Uncapture(group, old));
+                }
             }
+        } else {
+            self.captures = captures;
+        }
+        found != look.negated
+    }
 
-            // Save captures state before greedy attempt
-            let saved_captures = captures.clone();
+    // The match from `start` to `end` and each group's span within it. A
+    // group captured inside a lookbehind, before the match, has no span.
+    fn report(&self, start: usize, end: usize) -> (&'a str, Vec<Option<Range<usize>>>) {
+        let groups = self
+            .captures
+            .iter()
+            .map(|&span| {
+                let (s, e) = span?;
+                (start <= s).then(|| s - start..e - start)
+            })
+            .collect();
+        (&self.haystack[start..end], groups)
+    }
+}
 
-            // Greedy Attempt: Try to match the 'inner' token once
-            if let Some(inner_len) = match_here(&[*inner.clone()], text, captures) {
-                // Only recurse if we actually consumed something OR we are satisfying 'min'
-                if inner_len > 0 || *min > 0 {
-                    let next_min = if *min > 0 { min - 1 } else { 0 };
-                    let next_max = max.map(|m| m - 1);
+struct Compiler {
+    insts: Vec<Inst>,
+    groups: usize,
+    counters: usize,
+}
+
+impl Compiler {
+    fn push(&mut self, inst: Inst) -> usize {
+        self.insts.push(inst);
+        self.insts.len() - 1
+    }
 
-                    // Construct the "next" state for the quantifier
-                    let next_token = Token::Quantifier(inner.clone(), next_min, next_max);
-                    let mut sequence = vec![next_token];
-                    sequence.extend_from_slice(&tokens[1..]);
+    fn sequence(&mut self, tokens: &[Token]) {
+        for token in tokens {
+            self.token(token);
+        }
+    }
 
-                    // Try to match as many as possible (Greedy)
-                    if let Some(total_len) = match_here(&sequence, &text[inner_len..], captures) {
-                        return Some(inner_len + total_len);
+    fn token(&mut self, token: &Token) {
+        match token {
+            Token::Group(inner, id) => {
+                self.groups = self.groups.max(*id);
+                self.push(Inst::Open(id - 1));
+                self.sequence(inner);
+                self.push(Inst::Close(id - 1));
+            }
+            Token::NonCapturing(inner) => self.sequence(inner),
+            Token::Alternation(left, right) => {
+                let split = self.push(Inst::Split(0, 0));
+                self.sequence(left);
+                let jump = self.push(Inst::Jump(0));
+                let right_start = self.insts.len();
+                self.sequence(right);
+                self.insts[split] = Inst::Split(split + 1, right_start);
+                self.insts[jump] = Inst::Jump(self.insts.len());
+            }
+            Token::Quantifier(inner, min, max, repetition) => {
+                let greedy = *repetition == Repetition::Greedy;
+                let copies = max.unwrap_or(min.saturating_add(1));
+                if copied_size(inner).saturating_mul(copies) <= MAX_COPIED_INSTS {
+                    for _ in 0..*min {
+                        self.token(inner);
+                    }
+                    self.optional(inner, max.map(|max| max - min), greedy);
+                } else {
+                    self.counted(inner, *min, max.unwrap_or(*min), greedy);
+                    if max.is_none() {
+                        self.optional(inner, None, greedy);
                     }
                 }
             }
-
-            // Backtracking/Fallback: Restore captures and try without matching this iteration
-            *captures = saved_captures;
-            if *min == 0 {
-                match_here(&tokens[1..], text, captures)
-            } else {
-                None
+            Token::LookBehind(inner) | Token::NegativeLookBehind(inner) => {
+                let chars = match inner.as_slice() {
+                    [chars @ .., Token::EndAnchor] if chars.iter().all(is_single_char) => {
+                        Some(chars.to_vec())
+                    }
+                    _ => None,
+                };
+                let outer = std::mem::take(&mut self.insts);
+                self.sequence(inner);
+                self.push(Inst::Match);
+                let insts = std::mem::replace(&mut self.insts, outer);
+                self.push(Inst::LookBehind(Box::new(LookBehind {
+                    negated: matches!(token, Token::NegativeLookBehind(_)),
+                    chars,
+                    insts,
+                    reach: max_chars(inner).unwrap_or(usize::MAX),
+                })));
+            }
+            Token::Backreference(n) => {
+                self.push(Inst::Backref(*n, false));
+            }
+            Token::BackreferenceFold(n) => {
+                self.push(Inst::Backref(*n, true));
+            }
+            Token::Grapheme => {
+                self.push(Inst::Grapheme);
+            }
+            Token::StartAnchor
+            | Token::EndAnchor
+            | Token::LineEnd
+            | Token::WordBoundary
+            | Token::NonWordBoundary
+            | Token::NotAfterWord
+            | Token::NotBeforeWord => {
+                self.push(Inst::Assert(token.clone()));
+            }
+            _ => {
+                self.push(Inst::Char(token.clone()));
             }
         }
-        // Handle normal single-character tokens
-        _ => {
-            let mut text_chars = text.chars();
-            if let Some(c) = text_chars.next() {
-                if matches_token(&tokens[0], c) {
-                    let char_len = c.len_utf8();
-                    return match_here(&tokens[1..], &text[char_len..], captures)
-                        .map(|rest_len| char_len + rest_len);
+    }
+
+    // Up to `copies` more repeats of `inner`, each of which may be skipped,
+    // or a loop of them if None
+    fn optional(&mut self, inner: &Token, copies: Option<usize>, greedy: bool) {
+        let mut splits = Vec::new();
+        match copies {
+            None => {
+                let split = self.push(Inst::Split(0, 0));
+                self.token(inner);
+                self.push(Inst::Jump(split));
+                splits.push(split);
+            }
+            Some(copies) => {
+                for _ in 0..copies {
+                    splits.push(self.push(Inst::Split(0, 0)));
+                    self.token(inner);
                 }
             }
-            None
         }
+        let out = self.insts.len();
+        for split in splits {
+            self.insts[split] = if greedy {
+                Inst::Split(split + 1, out)
+            } else {
+                Inst::Split(out, split + 1)
+            };
+        }
+    }
+
+    // Between `min` and `max` repeats of `inner`, compiled once
+    fn counted(&mut self, inner: &Token, min: usize, max: usize, greedy: bool) {
+        let counter = self.counters;
+        self.counters += 1;
+        self.push(Inst::Reset(counter));
+        let head = self.push(Inst::Match); // replaced below
+        self.token(inner);
+        self.push(Inst::CountUp(counter, head));
+        let exit = self.push(Inst::Reset(counter));
+        self.insts[head] = Inst::Count {
+            counter,
+            min,
+            max,
+            greedy,
+            exit,
+        };
     }
 }
 
+// About how many instructions `token` compiles to with every repeat copied
+// out, saturating rather than overflowing
+fn copied_size(token: &Token) -> usize {
+    let sequence = |tokens: &[Token]| {
+        tokens.iter().fold(0usize, |total, token| {
+            total.saturating_add(copied_size(token))
+        })
+    };
+    match token {
+        Token::Group(inner, _) => sequence(inner).saturating_add(2),
+        Token::NonCapturing(inner) => sequence(inner),
+        Token::Alternation(left, right) => sequence(left)
+            .saturating_add(sequence(right))
+            .saturating_add(2),
+        Token::Quantifier(inner, min, max, _) => copied_size(inner)
+            .saturating_add(1)
+            .saturating_mul(max.unwrap_or(min.saturating_add(1))),
+        _ => 1,
+    }
+}
+
+// The groups backreferences in `tokens` refer to, in order, without repeats
+fn referenced_groups(tokens: &[Token]) -> Vec<usize> {
+    let mut groups: Vec<usize> = tokens
+        .iter()
+        .flat_map(|token| match token {
+            Token::Backreference(n) | Token::BackreferenceFold(n) => vec![*n],
+            Token::Group(inner, _)
+            | Token::NonCapturing(inner)
+            | Token::LookBehind(inner)
+            | Token::NegativeLookBehind(inner) => referenced_groups(inner),
+            Token::Alternation(left, right) => {
+                [referenced_groups(left), referenced_groups(right)].concat()
+            }
+            Token::Quantifier(inner, ..) => referenced_groups(std::slice::from_ref(inner)),
+            _ => Vec::new(),
+        })
+        .collect();
+    groups.sort_unstable();
+    groups.dedup();
+    groups
+}
+
+// The most chars `tokens` can match, or None if there's no bound (`a*`,
+// backreferences, grapheme clusters)
+fn max_chars(tokens: &[Token]) -> Option<usize> {
+    tokens.iter().try_fold(0usize, |total, token| {
+        let width = match token {
+            Token::Quantifier(inner, _, max, _) => {
+                max_chars(std::slice::from_ref(inner))?.checked_mul((*max)?)?
+            }
+            Token::Alternation(left, right) => max_chars(left)?.max(max_chars(right)?),
+            Token::Group(inner, _) | Token::NonCapturing(inner) => max_chars(inner)?,
+            Token::Grapheme | Token::Backreference(_) | Token::BackreferenceFold(_) => {
+                return None;
+            }
+            Token::StartAnchor
+            | Token::EndAnchor
+            | Token::LineEnd
+            | Token::WordBoundary
+            | Token::NonWordBoundary
+            | Token::NotAfterWord
+            | Token::NotBeforeWord
+            | Token::LookBehind(_)
+            | Token::NegativeLookBehind(_) => 0,
+            _ => 1,
+        };
+        total.checked_add(width)
+    })
+}
+
+#[cfg(test)]
 pub fn match_pattern<'a>(input_line: &'a str, tokens: &[Token]) -> Option<&'a str> {
-    let mut captures: Vec<Option<String>> = Vec::new();
-    match_here(tokens, input_line, &mut captures).map(|len| &input_line[..len])
+    match_captures(input_line, 0, tokens, &MatchContext::default()).map(|(m, _)| m)
 }
 
+// Like match_pattern, but matching at byte `pos` of `haystack`, and also
+// reporting each group's byte span within the match (index 0 is group 1)
+#[cfg(test)]
+pub fn match_captures<'a>(
+    haystack: &'a str,
+    pos: usize,
+    tokens: &[Token],
+    ctx: &MatchContext,
+) -> Option<(&'a str, Vec<Option<Range<usize>>>)> {
+    Backtracker::compile(tokens).captures_at(haystack, pos, ctx)
+}
 
 #[cfg(test)]
 mod tests {
-    use crate::regex::{match_pattern, parse_regex};
+    use std::time::{Duration, Instant};
+
+    use crate::regex::matcher::{MatchContext, match_captures};
+    use crate::regex::{ParseOptions, match_pattern, parse_regex, parse_regex_with};
 
     fn m(pattern: &str, text: &str) -> Option<String> {
-        let tokens = parse_regex(pattern);
+        let tokens = parse_regex(pattern).unwrap();
         match_pattern(text, &tokens).map(|s| s.to_string())
     }
 
@@ -183,6 +812,60 @@ mod tests {
         assert_eq!(m(r"\w\w", "a-"), None);
     }
 
+    #[test]
+    fn lazy_quantifiers_take_as_few_repeats_as_will_do() {
+        assert_eq!(m("<.+?>", "<a><b>"), Some("<a>".into()));
+        assert_eq!(m("<.+>", "<a><b>"), Some("<a><b>".into()));
+        assert_eq!(m("a*?", "aaa"), Some("".into()));
+        assert_eq!(m("a??b", "ab"), Some("ab".into()));
+        assert_eq!(m("a{2,4}?", "aaaa"), Some("aa".into()));
+        assert_eq!(m(r"(\d+?)(\d)", "123"), Some("12".into()));
+        assert_eq!(m("x.*?y", "x1y2y"), Some("x1y".into()));
+    }
+
+    #[test]
+    fn non_capturing_groups_leave_backreferences_alone() {
+        assert_eq!(m(r"(?:ab)+(c)\1", "ababcc"), Some("ababcc".into()));
+        assert_eq!(m(r"(?:ab)+(c)\1", "ababcab"), None);
+        let re: crate::regex::Regex = "(?:x)(y)".parse().unwrap();
+        let (_, groups) = re.captures_at("xy", 0).unwrap();
+        assert_eq!(groups, [Some(1..2)]);
+    }
+
+    #[test]
+    fn lookbehind_checks_the_text_before_the_match() {
+        let find = |pattern: &str, text: &str| {
+            let re: crate::regex::Regex = pattern.parse().unwrap();
+            (0..=text.len())
+                .find_map(|i| re.captures_at(text, i))
+                .map(|(m, groups)| (m.to_string(), groups))
+        };
+        assert_eq!(find(r"(?<=\$)\d+", "cost $42").unwrap().0, "42");
+        assert_eq!(find(r"(?<=a|bc)d", "xbcd").unwrap().0, "d");
+        assert_eq!(find(r"(?<!-)\b\d+", "-5 7").unwrap().0, "7");
+        assert!(find(r"(?<=^)x", "ax").is_none());
+        // Unbounded lookbehinds scan back to the start of the line
+        assert_eq!(find(r"(?<=k.*= )\w+", "key = v").unwrap().0, "v");
+        // A group captured behind the match can be referred back to, but
+        // has no span within it
+        let (m, groups) = find(r"(?<=(\w)=)\d\1", "a=1a").unwrap();
+        assert_eq!((m.as_str(), groups), ("1a", vec![None]));
+    }
+
+    #[test]
+    fn matches_whitespace_and_negated_classes() {
+        let assignment = r"\w+\s*=\s*\d+";
+        assert_eq!(m(assignment, "width =\t42;"), Some("width =\t42".into()));
+        assert_eq!(m(assignment, "width=42"), Some("width=42".into()));
+        assert_eq!(m(assignment, "width = x"), None);
+
+        assert_eq!(m(r"\S+", "ab c"), Some("ab".into()));
+        assert_eq!(m(r"\D\W", "a-"), Some("a-".into()));
+        assert_eq!(m(r"\D", "4"), None);
+        assert_eq!(m(r"\W", "_"), None);
+        assert_eq!(m(r"\s", "\u{0b}"), Some("\u{0b}".into()));
+    }
+
     #[test]
     fn matches_bracket_group_positive_and_negative() {
         assert_eq!(m("[abc]", "a"), Some("a".into()));
@@ -258,4 +941,110 @@ mod tests {
         assert_eq!(m("(ab)+", "abx"), Some("ab".into()));
         assert_eq!(m("(ab)+", "ax"), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn grapheme_escape_matches_whole_cluster() {
+        // "e" + COMBINING ACUTE ACCENT is a single cluster
+        assert_eq!(m(r"\X", "e\u{301}x"), Some("e\u{301}".into()));
+        assert_eq!(
+            m(r"\X\X", "\u{1F44D}\u{1F3FD}!"),
+            Some("\u{1F44D}\u{1F3FD}!".into())
+        );
+    }
+
+    #[test]
+    fn grapheme_mode_dot_and_quantifiers_count_clusters() {
+        let opts = ParseOptions {
+            graphemes: true,
+            ..Default::default()
+        };
+        let g = |pattern: &str, text: &str| {
+            let tokens = parse_regex_with(pattern, &opts).unwrap();
+            match_pattern(text, &tokens).map(|s| s.to_string())
+        };
+        assert_eq!(g("a.b", "ae\u{301}b"), Some("ae\u{301}b".into()));
+        assert_eq!(
+            g(".{2}", "e\u{301}o\u{308}x"),
+            Some("e\u{301}o\u{308}".into())
+        );
+
+        // Without the mode, `.` only takes the base character
+        assert_eq!(m("a.b", "ae\u{301}b"), None);
+    }
+
+    #[test]
+    fn case_insensitive_backreference_folds_case() {
+        let opts = ParseOptions {
+            case_insensitive: true,
+            ..Default::default()
+        };
+        let tokens = parse_regex_with(r"(\w+) \1", &opts).unwrap();
+        assert_eq!(match_pattern("Hello hello", &tokens), Some("Hello hello"));
+        assert_eq!(match_pattern("Hello help", &tokens), None);
+
+        // Case-sensitive stays byte-exact
+        assert_eq!(m(r"(\w+) \1", "Hello hello"), None);
+    }
+
+    #[test]
+    fn reports_capture_spans() {
+        let tokens = parse_regex(r"(\w+)=(\d+)").unwrap();
+        let (m, spans) = match_captures("key=42;", 0, &tokens, &MatchContext::default()).unwrap();
+        assert_eq!(m, "key=42");
+        assert_eq!(spans, vec![Some(0..3), Some(4..6)]);
+
+        // Groups in the untaken branch have no span
+        let tokens = parse_regex("x((a)|(b))").unwrap();
+        let (_, spans) = match_captures("xb", 0, &tokens, &MatchContext::default()).unwrap();
+        assert_eq!(spans, vec![Some(1..2), None, Some(1..2)]);
+
+        // Captures made on a path that was backtracked out of are undone
+        let tokens = parse_regex(r"(?:(a)(b)x|(a)b)\3").unwrap();
+        let (_, spans) = match_captures("aba", 0, &tokens, &MatchContext::default()).unwrap();
+        assert_eq!(spans, vec![None, None, Some(0..1)]);
+    }
+
+    #[test]
+    fn group_lengths_respect_char_boundaries() {
+        assert_eq!(m("(a)", "\u{e9}"), None);
+        assert_eq!(
+            m("(\u{e9}+)x", "\u{e9}\u{e9}x"),
+            Some("\u{e9}\u{e9}x".into())
+        );
+    }
+
+    #[test]
+    fn long_inputs_and_repeats_do_not_overflow_the_stack() {
+        let line = "a".repeat(200_000);
+        assert_eq!(m(r"(a)\1a*b", &line), None);
+        assert_eq!(m(r"(a)\1a*", &line).map(|s| s.len()), Some(line.len()));
+        let tokens = parse_regex("(?:a|b){5000}c").unwrap();
+        let text = format!("{}c", "ab".repeat(2500));
+        assert_eq!(match_pattern(&text, &tokens), Some(text.as_str()));
+    }
+
+    #[test]
+    fn failed_states_are_not_retried_across_long_lines() {
+        // Without the memo each split of the a's between the `.*`s would be
+        // tried, from every start
+        let started = Instant::now();
+        let find = |pattern: &str, text: &str| {
+            let re: crate::regex::Regex = pattern.parse().unwrap();
+            re.find_from(text, 0).map(|(start, m, _)| (start, m.len()))
+        };
+        let line = "a".repeat(5000);
+        assert_eq!(find(r"(x)?.*.*.*b\1", &line), None);
+        assert_eq!(find(r"(x)?.*.*.*.*.*.*.*.*b\1", &line), None);
+        assert_eq!(find(r"(a|aa)*c\1", &line), None);
+        let text = format!("{line}ca");
+        assert_eq!(find(r"(a|aa)*c\1", &text), Some((0, text.len())));
+        let text = format!("{line}c");
+        let pattern = r"(x?)(?:.*.*.*.*.*.*.*.*b|a*c)\1";
+        assert_eq!(find(pattern, &text), Some((0, text.len())));
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "{:?}",
+            started.elapsed()
+        );
+    }
+}
@@ -1,7 +1,20 @@
 pub mod ast;
+pub mod dialect;
+pub mod fuzzy;
 pub mod matcher;
 pub mod parser;
+pub mod pattern;
+pub mod pikevm;
+pub mod prefilter;
+pub mod set;
 
-pub use ast::Token;
+pub use parser::{
+    DEFAULT_NESTING_LIMIT, NestingError, ParseOptions, RegexError, check_nesting, escape,
+};
+pub use pattern::{OverlappingMatches, Regex, RegexBuilder};
+pub use set::RegexSet;
+
+#[cfg(test)]
 pub use matcher::match_pattern;
-pub use parser::parse_regex;
+#[cfg(test)]
+pub use parser::{parse_regex, parse_regex_with};
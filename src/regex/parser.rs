@@ -1,153 +1,704 @@
-use crate::regex::ast::{GroupType, Token};
+use std::error::Error;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
 
-pub fn parse_regex(pattern: &str) -> Vec<Token> {
-    let mut group_counter = 0;
-    parse_pattern(pattern, &mut group_counter)
+use regex_syntax::hir::{Class, HirKind};
+
+use crate::regex::ast::{CharClass, GroupType, Repetition, Token};
+
+// Parsing and matching recurse once per level of nested groups, so a pattern
+// like `((((...))))` thousands deep would overflow the stack. Patterns are
+// checked against a limit before they're parsed; ParseOptions can raise it.
+pub const DEFAULT_NESTING_LIMIT: usize = 200;
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct ParseOptions {
+    pub graphemes: bool,            // `.` consumes a whole extended grapheme cluster
+    pub multiline: bool,            // `$` also matches before a newline, `.` stops at one
+    pub dotall: bool,               // with multiline, `.` matches newlines too
+    pub case_insensitive: bool,     // comparisons ignore case (simple case folding)
+    pub ascii: bool,                // ASCII-only semantics; disables grapheme mode
+    pub fuzzy: Option<usize>,       // allowed edits for approximate matching (--fuzzy)
+    pub whole_word: bool,           // matches must not touch word chars on either side (-w)
+    pub whole_line: bool,           // matches must span a whole line (-x)
+    pub word_chars: Option<String>, // non-alphanumerics counted as word chars; default `_`
+    pub nesting_limit: Option<usize>, // how deep groups may nest; DEFAULT_NESTING_LIMIT if None
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NestingError {
+    pub depth: usize,
+    pub limit: usize,
+}
+
+impl fmt::Display for NestingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "groups nested {} deep, more than the limit of {}",
+            self.depth, self.limit
+        )
+    }
+}
+
+impl Error for NestingError {}
+
+// Why a pattern couldn't be parsed. `pos` is the byte offset in the pattern
+// of the construct at fault, e.g. the `[` of an unclosed class.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegexError {
+    UnclosedClass { pos: usize },     // `[` without `]`
+    BadRange { pos: usize },          // `z-a` in a class: the range ends before it starts
+    UnclosedGroup { pos: usize },     // `(` without `)`
+    UnopenedGroup { pos: usize },     // `)` without `(`
+    BadRepetition { pos: usize },     // `{` not followed by `n}`, `n,}` or `n,m}`
+    NothingToRepeat { pos: usize },   // a quantifier with nothing before it
+    UnknownProperty { pos: usize },   // `\p{...}` naming no Unicode property
+    TrailingBackslash { pos: usize }, // `\` at the very end
+    Nesting(NestingError),
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (what, pos) = match self {
+            RegexError::UnclosedClass { pos } => ("unclosed `[`", pos),
+            RegexError::BadRange { pos } => ("invalid range end", pos),
+            RegexError::UnclosedGroup { pos } => ("unclosed `(`", pos),
+            RegexError::UnopenedGroup { pos } => ("unmatched `)`", pos),
+            RegexError::BadRepetition { pos } => ("invalid `{n,m}` repetition", pos),
+            RegexError::NothingToRepeat { pos } => ("quantifier with nothing to repeat", pos),
+            RegexError::UnknownProperty { pos } => ("unknown Unicode property", pos),
+            RegexError::TrailingBackslash { pos } => ("trailing backslash", pos),
+            RegexError::Nesting(e) => return e.fmt(f),
+        };
+        write!(f, "{what} at byte {pos}")
+    }
+}
+
+impl Error for RegexError {}
+
+impl From<NestingError> for RegexError {
+    fn from(e: NestingError) -> RegexError {
+        RegexError::Nesting(e)
+    }
+}
+
+// Fails if `pattern` nests groups more than `limit` deep. Escaped
+// parentheses don't count.
+pub fn check_nesting(pattern: &str, limit: usize) -> Result<(), NestingError> {
+    let mut depth = 0usize;
+    let mut deepest = 0;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '(' => {
+                depth += 1;
+                deepest = deepest.max(depth);
+            }
+            ')' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    if deepest > limit {
+        return Err(NestingError {
+            depth: deepest,
+            limit,
+        });
+    }
+    Ok(())
+}
+
+// `literal` as a pattern matching exactly that text (-F): every ASCII
+// punctuation char is escaped, which the parser reads back as itself
+pub fn escape(literal: &str) -> String {
+    let mut escaped = String::with_capacity(literal.len());
+    for c in literal.chars() {
+        if c.is_ascii_punctuation() {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+pub fn parse_regex(pattern: &str) -> Result<Vec<Token>, RegexError> {
+    parse_regex_with(pattern, &ParseOptions::default())
+}
+
+pub fn parse_regex_with(pattern: &str, opts: &ParseOptions) -> Result<Vec<Token>, RegexError> {
+    Ok(parse_named(pattern, opts)?.0)
+}
+
+// Like parse_regex_with, plus the index of each named group, first
+// definition first
+pub fn parse_named(
+    pattern: &str,
+    opts: &ParseOptions,
+) -> Result<(Vec<Token>, GroupNames), RegexError> {
+    check_nesting(pattern, opts.nesting_limit.unwrap_or(DEFAULT_NESTING_LIMIT))?;
+    let mut groups = Groups::default();
+    let tokens = parse_pattern(pattern, 0, &mut groups, opts)?;
+    Ok((tokens, groups.names))
+}
+
+// Each named group with its index, first definition first
+type GroupNames = Vec<(String, usize)>;
+
+// Capture groups seen so far: how many, and the named ones' indexes
+#[derive(Default)]
+struct Groups {
+    count: usize,
+    names: GroupNames,
+}
+
+impl Groups {
+    fn index_of(&self, name: &str) -> Option<usize> {
+        self.names.iter().find(|(n, _)| n == name).map(|&(_, i)| i)
+    }
+}
+
+enum GroupKind {
+    Plain,
+    NonCapturing,        // (?:...)
+    Named(String),       // (?P<name>...) or (?<name>...)
+    LookBehind,          // (?<=...)
+    NegativeLookBehind,  // (?<!...)
+    SetFlags(String),    // (?i), (?-i), (?im-s): for the rest of the enclosing branch
+    ScopedFlags(String), // (?i:...): for the group's contents only
+}
+
+// What the chars after a `(` make of the group, and how many of them are
+// syntax rather than its contents
+fn group_kind(mut rest: Peekable<Chars>) -> (GroupKind, usize) {
+    if rest.next() != Some('?') {
+        return (GroupKind::Plain, 0);
+    }
+    let named = match rest.peek() {
+        Some(':') => return (GroupKind::NonCapturing, 2),
+        Some('i' | 'm' | 's' | '-') => {
+            let flags: String = rest.clone().take_while(|c| "ims-".contains(*c)).collect();
+            let len = flags.len() + 1;
+            match rest.nth(flags.len()) {
+                _ if flags == "-" => None,
+                Some(')') => return (GroupKind::SetFlags(flags), len),
+                Some(':') => return (GroupKind::ScopedFlags(flags), len + 1),
+                _ => None,
+            }
+        }
+        Some('P') => {
+            rest.next();
+            angle_name(rest).map(|(name, len)| (GroupKind::Named(name), len + 2))
+        }
+        Some('<') => {
+            let mut after = rest.clone();
+            after.next();
+            match after.next() {
+                Some('=') => return (GroupKind::LookBehind, 3),
+                Some('!') => return (GroupKind::NegativeLookBehind, 3),
+                _ => angle_name(rest).map(|(name, len)| (GroupKind::Named(name), len + 1)),
+            }
+        }
+        _ => None,
+    };
+    named.unwrap_or((GroupKind::Plain, 0))
+}
+
+// `opts` with inline flags like `im-s` applied: `i` ignores case, `m` is
+// multiline and `s` dot-all; those after a `-` are turned off
+fn apply_flags(flags: &str, opts: &mut ParseOptions) {
+    let mut on = true;
+    for flag in flags.chars() {
+        match flag {
+            '-' => on = false,
+            'i' => opts.case_insensitive = on,
+            'm' => opts.multiline = on,
+            's' => opts.dotall = on,
+            _ => {}
+        }
+    }
+}
+
+// A `<name>` of word chars at the start of `rest`, with its length in chars
+fn angle_name(mut rest: Peekable<Chars>) -> Option<(String, usize)> {
+    if rest.next()? != '<' {
+        return None;
+    }
+    let mut name = String::new();
+    loop {
+        match rest.next()? {
+            '>' if !name.is_empty() => break,
+            c if c.is_alphanumeric() || c == '_' => name.push(c),
+            _ => return None,
+        }
+    }
+    let len = name.chars().count() + 2;
+    Some((name, len))
+}
+
+// The property after `\p`: a braced name or a single letter, with its
+// length in chars
+fn property_name(mut rest: Peekable<Chars>) -> Option<(String, usize)> {
+    match rest.next()? {
+        '{' => {
+            let mut name = String::new();
+            loop {
+                match rest.next()? {
+                    '}' if !name.is_empty() => break,
+                    c => name.push(c),
+                }
+            }
+            let len = name.chars().count() + 2;
+            Some((name, len))
+        }
+        c if c.is_ascii_alphabetic() => Some((c.to_string(), 1)),
+        _ => None,
+    }
+}
+
+// Code point ranges of a Unicode property, general category (`L`, `Nd`) or
+// script (`Greek`), as regex-syntax's tables have them
+fn property_ranges(name: &str) -> Option<Vec<(char, char)>> {
+    let hir = regex_syntax::Parser::new()
+        .parse(&format!(r"\p{{{name}}}"))
+        .ok()?;
+    match hir.kind() {
+        HirKind::Class(Class::Unicode(class)) => {
+            Some(class.iter().map(|r| (r.start(), r.end())).collect())
+        }
+        _ => None,
+    }
+}
+
+// The char an escape like `\n`, `\x41` or `\u{1F600}` stands for, given the
+// letter after the backslash, with how many more chars it takes
+fn escaped_char(letter: char, mut rest: Peekable<Chars>) -> Option<(char, usize)> {
+    match letter {
+        'n' => Some(('\n', 0)),
+        't' => Some(('\t', 0)),
+        'r' => Some(('\r', 0)),
+        'x' => {
+            let hex: String = [rest.next()?, rest.next()?].iter().collect();
+            let byte = u8::from_str_radix(&hex, 16).ok()?;
+            Some((char::from(byte), 2))
+        }
+        'u' if rest.next()? == '{' => {
+            let hex: String = rest.by_ref().take_while(|&c| c != '}').collect();
+            if hex.is_empty() || hex.len() > 6 {
+                return None;
+            }
+            let c = char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?;
+            Some((c, hex.len() + 2))
+        }
+        _ => None,
+    }
+}
+
+fn backreference(n: usize, opts: &ParseOptions) -> Token {
+    if opts.case_insensitive {
+        Token::BackreferenceFold(n)
+    } else {
+        Token::Backreference(n)
+    }
 }
 
-fn parse_pattern(pattern: &str, group_counter: &mut usize) -> Vec<Token> {
+// `base` is where `pattern` starts in the whole pattern, for error positions
+fn parse_pattern(
+    pattern: &str,
+    base: usize,
+    groups: &mut Groups,
+    opts: &ParseOptions,
+) -> Result<Vec<Token>, RegexError> {
     let mut tokens = Vec::new();
     let mut chars = pattern.chars().peekable();
+    // Inline flags change the options for the rest of this branch
+    let opts = &mut opts.clone();
+    // Byte offset of the next char `chars` yields
+    let here = |chars: &Peekable<Chars>| {
+        base + pattern.len() - chars.clone().map(char::len_utf8).sum::<usize>()
+    };
 
     while let Some(c) = chars.next() {
         match c {
             '\\' => match chars.next() {
                 Some('d') => tokens.push(Token::Digit),
                 Some('w') => tokens.push(Token::Alphanumeric),
-                Some(digit) if digit.is_digit(10) => {
+                Some('s') => tokens.push(Token::Whitespace),
+                Some('D') => tokens.push(Token::NotDigit),
+                Some('W') => tokens.push(Token::NotAlphanumeric),
+                Some('S') => tokens.push(Token::NotWhitespace),
+                Some('X') => tokens.push(Token::Grapheme),
+                Some('b') => tokens.push(Token::WordBoundary),
+                Some('B') => tokens.push(Token::NonWordBoundary),
+                Some(digit) if digit.is_ascii_digit() => {
                     // Handle \1, \2, \3...
                     let n = digit.to_digit(10).unwrap() as usize;
-                    tokens.push(Token::Backreference(n));
+                    tokens.push(backreference(n, opts));
+                }
+                // \p{Greek}, \pL and their negations \P{...}
+                Some(p @ ('p' | 'P')) if let Some((name, len)) = property_name(chars.clone()) => {
+                    let pos = here(&chars) - 2;
+                    chars.nth(len - 1);
+                    let ranges =
+                        property_ranges(&name).ok_or(RegexError::UnknownProperty { pos })?;
+                    let group_type = if p == 'p' {
+                        GroupType::Positive
+                    } else {
+                        GroupType::Negative
+                    };
+                    tokens.push(Token::BracketGroup(
+                        CharClass::from_ranges(ranges),
+                        group_type,
+                    ));
+                }
+                // \k<name> refers back to a named group defined before it;
+                // an unknown name is read literally
+                Some('k')
+                    if let Some((name, len)) = angle_name(chars.clone())
+                        && let Some(n) = groups.index_of(&name) =>
+                {
+                    chars.nth(len - 1);
+                    tokens.push(backreference(n, opts));
+                }
+                Some(letter) if let Some((c, len)) = escaped_char(letter, chars.clone()) => {
+                    if len > 0 {
+                        chars.nth(len - 1);
+                    }
+                    tokens.push(literal(c, opts));
+                }
+                Some(escaped) => tokens.push(literal(escaped, opts)),
+                None => {
+                    return Err(RegexError::TrailingBackslash {
+                        pos: base + pattern.len() - 1,
+                    });
                 }
-                Some(escaped) => tokens.push(Token::Literal(escaped)),
-                None => {}
             },
+            '^' => tokens.push(Token::StartAnchor),
+            '$' if opts.multiline => tokens.push(Token::LineEnd),
             '$' => tokens.push(Token::EndAnchor),
             '[' => {
+                let open = here(&chars) - 1;
                 let mut group_type = GroupType::Positive;
                 if chars.peek() == Some(&'^') {
                     group_type = GroupType::Negative;
                     chars.next();
                 }
+                // Each member with its byte offset, for errors
                 let mut class_chars = Vec::new();
+                let mut closed = false;
                 while let Some(next_c) = chars.next() {
                     if next_c == ']' {
+                        closed = true;
                         break;
                     }
-                    class_chars.push(next_c);
+                    let pos = here(&chars) - next_c.len_utf8();
+                    // Control, hex and Unicode escapes stand for their char;
+                    // any other backslash is a member
+                    let mut rest = chars.clone();
+                    if next_c == '\\'
+                        && let Some(letter) = rest.next()
+                        && let Some((c, len)) = escaped_char(letter, rest)
+                    {
+                        chars.nth(len);
+                        class_chars.push((c, pos));
+                        continue;
+                    }
+                    class_chars.push((next_c, pos));
                 }
-                tokens.push(Token::BracketGroup(class_chars, group_type));
+                if !closed {
+                    return Err(RegexError::UnclosedClass { pos: open });
+                }
+                tokens.push(Token::BracketGroup(
+                    class_ranges(&class_chars, opts)?,
+                    group_type,
+                ));
             }
             '(' => {
-                *group_counter += 1;
-                let current_group_id = *group_counter;
+                let open = here(&chars) - 1;
+                // `(?:...)` and lookbehinds group without taking a capture
+                // index
+                let (kind, prefix_len) = group_kind(chars.clone());
+                if prefix_len > 0 {
+                    chars.nth(prefix_len - 1);
+                }
+                let start = here(&chars);
+                let capturing = matches!(kind, GroupKind::Plain | GroupKind::Named(_));
+                let current_group_id = groups.count + 1;
+                if capturing {
+                    groups.count += 1;
+                }
+                if let GroupKind::Named(name) = &kind
+                    && groups.index_of(name).is_none()
+                {
+                    groups.names.push((name.clone(), current_group_id));
+                }
 
-                // Collect everything inside the parentheses into a buffer
+                // Collect everything inside the parentheses into a buffer;
+                // escaped parentheses don't count
                 let mut group_buffer = String::new();
                 let mut depth = 1;
 
                 while let Some(inner_c) = chars.next() {
-                    if inner_c == '(' {
-                        depth += 1;
-                    } else if inner_c == ')' {
-                        depth -= 1;
-                        if depth == 0 {
-                            break;
+                    match inner_c {
+                        '\\' => {
+                            group_buffer.push(inner_c);
+                            group_buffer.extend(chars.next());
+                            continue;
                         }
+                        '(' => depth += 1,
+                        ')' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => {}
                     }
                     group_buffer.push(inner_c);
                 }
+                if depth > 0 {
+                    return Err(RegexError::UnclosedGroup { pos: open });
+                }
+                if let GroupKind::SetFlags(flags) = &kind {
+                    apply_flags(flags, opts);
+                    continue;
+                }
+                let mut inner_opts = opts.clone();
+                if let GroupKind::ScopedFlags(flags) = &kind {
+                    apply_flags(flags, &mut inner_opts);
+                }
 
-                // Split by '|' only at the top level of this group
+                let group = |mut inner: Vec<Token>| match kind {
+                    GroupKind::NonCapturing
+                    | GroupKind::SetFlags(_)
+                    | GroupKind::ScopedFlags(_) => Token::NonCapturing(inner),
+                    // Ending in `$` makes a lookbehind match exactly the text
+                    // it's given, which stops at the current position
+                    GroupKind::LookBehind | GroupKind::NegativeLookBehind => {
+                        inner.push(Token::EndAnchor);
+                        if matches!(kind, GroupKind::LookBehind) {
+                            Token::LookBehind(inner)
+                        } else {
+                            Token::NegativeLookBehind(inner)
+                        }
+                    }
+                    GroupKind::Plain | GroupKind::Named(_) => Token::Group(inner, current_group_id),
+                };
+
+                // Split by '|' only at the top level of this group, keeping
+                // where each part starts
                 let mut parts = Vec::new();
                 let mut current_part = String::new();
+                let mut part_start = 0;
                 let mut paren_depth = 0;
-                for char in group_buffer.chars() {
-                    if char == '(' {
-                        paren_depth += 1;
-                    } else if char == ')' {
-                        paren_depth -= 1;
-                    }
-
-                    if char == '|' && paren_depth == 0 {
-                        parts.push(current_part.clone());
-                        current_part.clear();
-                    } else {
-                        current_part.push(char);
+                let mut rest = group_buffer.char_indices();
+                while let Some((i, char)) = rest.next() {
+                    match char {
+                        '\\' => {
+                            current_part.push(char);
+                            current_part.extend(rest.next().map(|(_, e)| e));
+                            continue;
+                        }
+                        '(' => paren_depth += 1,
+                        ')' => paren_depth -= 1,
+                        '|' if paren_depth == 0 => {
+                            parts.push((part_start, std::mem::take(&mut current_part)));
+                            part_start = i + 1;
+                            continue;
+                        }
+                        _ => {}
                     }
+                    current_part.push(char);
                 }
-                parts.push(current_part);
+                parts.push((part_start, current_part));
 
+                let mut parse_part = |(at, part): &(usize, String)| {
+                    parse_pattern(part, start + at, groups, &inner_opts)
+                };
                 if parts.len() > 1 {
-                    let mut alt_token = Token::Alternation(
-                        parse_pattern(&parts[0], group_counter),
-                        parse_pattern(&parts[1], group_counter),
-                    );
+                    let mut alt_token =
+                        Token::Alternation(parse_part(&parts[0])?, parse_part(&parts[1])?);
 
                     // Nest any additional parts
                     for part in parts.iter().skip(2) {
-                        alt_token =
-                            Token::Alternation(vec![alt_token], parse_pattern(part, group_counter));
+                        alt_token = Token::Alternation(vec![alt_token], parse_part(part)?);
                     }
-                    tokens.push(Token::Group(vec![alt_token], current_group_id));
+                    tokens.push(group(vec![alt_token]));
                 } else {
                     // If no pipe, wrap the sequence in a Group
                     // This allows the next quantifier to pop the whole group
-                    let group_tokens = parse_pattern(&group_buffer, group_counter);
-                    tokens.push(Token::Group(group_tokens, current_group_id));
+                    let group_tokens = parse_part(&parts[0])?;
+                    tokens.push(group(group_tokens));
                 }
             }
-            '{' => {
-                let mut buffer = String::new();
-                while let Some(&next_c) = chars.peek() {
-                    if next_c == '}' {
-                        chars.next();
-                        break;
-                    }
-                    buffer.push(chars.next().unwrap());
-                }
-                if let Some(prev) = tokens.pop() {
-                    let parts: Vec<&str> = buffer.split(',').collect();
-                    let n = parts[0].trim().parse().unwrap_or(0);
-                    if buffer.contains(',') {
-                        let m = parts[1].trim().parse().ok();
-                        tokens.push(Token::Quantifier(Box::new(prev), n, m));
-                    } else {
-                        tokens.push(Token::Quantifier(Box::new(prev), n, Some(n)));
-                    }
-                }
+            ')' => {
+                return Err(RegexError::UnopenedGroup {
+                    pos: here(&chars) - 1,
+                });
             }
-            '+' => {
-                if let Some(prev) = tokens.pop() {
-                    tokens.push(Token::Quantifier(Box::new(prev), 1, None));
-                }
-            }
-            '?' => {
-                if let Some(prev) = tokens.pop() {
-                    tokens.push(Token::Quantifier(Box::new(prev), 0, Some(1)));
-                }
-            }
-            '*' => {
-                if let Some(prev) = tokens.pop() {
-                    tokens.push(Token::Quantifier(Box::new(prev), 0, None));
-                }
+            '{' | '+' | '?' | '*' => {
+                let Some(prev) = tokens.pop() else {
+                    return Err(RegexError::NothingToRepeat {
+                        pos: here(&chars) - 1,
+                    });
+                };
+                let (min, max) = match c {
+                    '+' => (1, None),
+                    '?' => (0, Some(1)),
+                    '*' => (0, None),
+                    _ => {
+                        let open = here(&chars) - 1;
+                        let mut buffer = String::new();
+                        let mut closed = false;
+                        for next_c in chars.by_ref() {
+                            if next_c == '}' {
+                                closed = true;
+                                break;
+                            }
+                            buffer.push(next_c);
+                        }
+                        repetition_bounds(&buffer)
+                            .filter(|_| closed)
+                            .ok_or(RegexError::BadRepetition { pos: open })?
+                    }
+                };
+                // A trailing `?` makes the quantifier lazy
+                let repetition = match chars.next_if_eq(&'?') {
+                    Some(_) => Repetition::Lazy,
+                    None => Repetition::Greedy,
+                };
+                tokens.push(Token::Quantifier(Box::new(prev), min, max, repetition));
             }
+            '.' if opts.graphemes && !opts.ascii => tokens.push(Token::Grapheme),
+            '.' if opts.multiline && !opts.dotall => tokens.push(Token::BracketGroup(
+                CharClass::new(['\n']),
+                GroupType::Negative,
+            )),
             '.' => tokens.push(Token::Wildcard),
-            _ => tokens.push(Token::Literal(c)),
+            _ => tokens.push(literal(c, opts)),
         }
     }
-    tokens
+    Ok(tokens)
 }
 
+// `n`, `n,` or `n,m` from inside `{...}`, with m no less than n
+fn repetition_bounds(buffer: &str) -> Option<(usize, Option<usize>)> {
+    match buffer.split_once(',') {
+        None => {
+            let n = buffer.trim().parse().ok()?;
+            Some((n, Some(n)))
+        }
+        Some((min, max)) => {
+            let min = min.trim().parse().ok()?;
+            if max.trim().is_empty() {
+                return Some((min, None));
+            }
+            let max = max.trim().parse().ok()?;
+            (min <= max).then_some((min, Some(max)))
+        }
+    }
+}
+
+// A literal char; when ignoring case, a class of its case variants instead
+fn literal(c: char, opts: &ParseOptions) -> Token {
+    if opts.case_insensitive {
+        let variants = case_variants(c, opts.ascii);
+        if variants.len() > 1 {
+            let class = fold_class(CharClass::new(variants), opts);
+            return Token::BracketGroup(class, GroupType::Positive);
+        }
+    }
+    Token::Literal(c)
+}
+
+// Outside ASCII mode the class also checks each char's lowercase form at
+// match time, for chars like the Kelvin sign that only map one way
+fn fold_class(class: CharClass, opts: &ParseOptions) -> CharClass {
+    if opts.ascii {
+        class
+    } else {
+        class.ignoring_case()
+    }
+}
+
+// `c` and the chars it maps to under simple (one char to one char) case
+// conversion, e.g. `k` and `K`
+fn case_variants(c: char, ascii: bool) -> Vec<char> {
+    if ascii {
+        return if c.is_ascii_alphabetic() {
+            vec![c.to_ascii_lowercase(), c.to_ascii_uppercase()]
+        } else {
+            vec![c]
+        };
+    }
+    let mut variants = vec![c];
+    let mut add = |mapped: String| {
+        let mut chars = mapped.chars();
+        if let (Some(m), None) = (chars.next(), chars.next())
+            && !variants.contains(&m)
+        {
+            variants.push(m);
+        }
+    };
+    let upper: String = c.to_uppercase().collect();
+    add(c.to_lowercase().collect());
+    add(upper.to_lowercase());
+    add(upper);
+    variants
+}
+
+// `a-z` between other members is a range; a `-` first or last is itself a
+// member. A reversed range like `z-a` is an error, as in grep. When ignoring
+// case, every member brings its case variants along.
+fn class_ranges(members: &[(char, usize)], opts: &ParseOptions) -> Result<CharClass, RegexError> {
+    let mut ranges = Vec::new();
+    let mut i = 0;
+    while i < members.len() {
+        let (start, pos) = members[i];
+        if i + 2 < members.len() && members[i + 1].0 == '-' {
+            let end = members[i + 2].0;
+            if start > end {
+                return Err(RegexError::BadRange { pos });
+            }
+            ranges.push((start, end));
+            i += 3;
+        } else {
+            ranges.push((start, start));
+            i += 1;
+        }
+    }
+    if opts.case_insensitive {
+        let folded: Vec<(char, char)> = ranges
+            .iter()
+            .flat_map(|&(start, end)| start..=end)
+            .flat_map(|c| case_variants(c, opts.ascii).into_iter().skip(1))
+            .map(|c| (c, c))
+            .collect();
+        ranges.extend(folded);
+        return Ok(fold_class(CharClass::from_ranges(ranges), opts));
+    }
+    Ok(CharClass::from_ranges(ranges))
+}
 
 #[cfg(test)]
 mod tests {
-    use super::parse_regex;
-    use crate::regex::ast::{GroupType, Token};
+    use super::{
+        ParseOptions, RegexError, check_nesting, escape, parse_named, parse_regex, parse_regex_with,
+    };
+    use crate::regex::ast::{CharClass, GroupType, Repetition, Token};
 
     #[test]
     fn parses_literals() {
-        let t = parse_regex("abc");
+        let t = parse_regex("abc").unwrap();
         assert_eq!(
             t,
             vec![Token::Literal('a'), Token::Literal('b'), Token::Literal('c')]
@@ -156,83 +707,142 @@ mod tests {
 
     #[test]
     fn parses_escapes_digit_and_word_and_literal_escape() {
-        let t = parse_regex(r"\d\w\.");
+        let t = parse_regex(r"\d\w\.").unwrap();
         assert_eq!(
             t,
             vec![Token::Digit, Token::Alphanumeric, Token::Literal('.')]
         );
+        assert_eq!(
+            parse_regex(r"\s\D\W\S").unwrap(),
+            vec![
+                Token::Whitespace,
+                Token::NotDigit,
+                Token::NotAlphanumeric,
+                Token::NotWhitespace
+            ]
+        );
     }
 
     #[test]
     fn parses_wildcard_and_end_anchor() {
-        let t = parse_regex("a.$");
+        let t = parse_regex("a.$").unwrap();
         assert_eq!(
             t,
             vec![Token::Literal('a'), Token::Wildcard, Token::EndAnchor]
         );
     }
 
+    #[test]
+    fn parses_start_anchor_anywhere() {
+        assert_eq!(
+            parse_regex(r"^a\^").unwrap(),
+            vec![Token::StartAnchor, Token::Literal('a'), Token::Literal('^')]
+        );
+        let Token::Group(inner, 1) = &parse_regex("(^a|b)").unwrap()[0] else {
+            panic!("expected a group");
+        };
+        assert!(matches!(&inner[0], Token::Alternation(left, _) if left[0] == Token::StartAnchor));
+    }
+
     #[test]
     fn parses_bracket_group_positive() {
-        let t = parse_regex("[abc]");
+        let t = parse_regex("[abc]").unwrap();
         assert_eq!(
             t,
-            vec![Token::BracketGroup(vec!['a', 'b', 'c'], GroupType::Positive)]
+            vec![Token::BracketGroup(CharClass::new(['a', 'b', 'c']), GroupType::Positive)]
         );
     }
 
+    #[test]
+    fn parses_bracket_group_ranges() {
+        let t = parse_regex("[a-c0-9_-]").unwrap();
+        assert_eq!(
+            t,
+            vec![Token::BracketGroup(
+                CharClass::from_ranges([('a', 'c'), ('0', '9'), ('_', '_'), ('-', '-')]),
+                GroupType::Positive
+            )]
+        );
+        assert_eq!(
+            parse_regex("ab[xz-a]"),
+            Err(RegexError::BadRange { pos: 4 })
+        );
+    }
+
+    #[test]
+    fn escaped_text_parses_as_literals() {
+        let text = r"^a.b*(c)[d]\1{2}$|e";
+        let tokens = parse_regex(&escape(text)).unwrap();
+        assert_eq!(tokens, text.chars().map(Token::Literal).collect::<Vec<_>>());
+    }
+
     #[test]
     fn parses_bracket_group_negative() {
-        let t = parse_regex("[^abc]");
+        let t = parse_regex("[^abc]").unwrap();
         assert_eq!(
             t,
-            vec![Token::BracketGroup(vec!['a', 'b', 'c'], GroupType::Negative)]
+            vec![Token::BracketGroup(CharClass::new(['a', 'b', 'c']), GroupType::Negative)]
         );
     }
 
     #[test]
     fn parses_quantifiers_question_star_plus() {
-        let t = parse_regex("a?b*c+");
+        let t = parse_regex("a?b*c+").unwrap();
         assert_eq!(
             t,
             vec![
-                Token::Quantifier(Box::new(Token::Literal('a')), 0, Some(1)),
-                Token::Quantifier(Box::new(Token::Literal('b')), 0, None),
-                Token::Quantifier(Box::new(Token::Literal('c')), 1, None),
+                Token::Quantifier(Box::new(Token::Literal('a')), 0, Some(1), Repetition::Greedy),
+                Token::Quantifier(Box::new(Token::Literal('b')), 0, None, Repetition::Greedy),
+                Token::Quantifier(Box::new(Token::Literal('c')), 1, None, Repetition::Greedy),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_lazy_quantifiers() {
+        let lazy =
+            |min, max| Token::Quantifier(Box::new(Token::Literal('a')), min, max, Repetition::Lazy);
+        assert_eq!(
+            parse_regex("a*?a+?a??a{2,3}?").unwrap(),
+            vec![
+                lazy(0, None),
+                lazy(1, None),
+                lazy(0, Some(1)),
+                lazy(2, Some(3))
             ]
         );
     }
 
     #[test]
     fn parses_braced_quantifier_exact() {
-        let t = parse_regex("a{3}");
+        let t = parse_regex("a{3}").unwrap();
         assert_eq!(
             t,
-            vec![Token::Quantifier(Box::new(Token::Literal('a')), 3, Some(3))]
+            vec![Token::Quantifier(Box::new(Token::Literal('a')), 3, Some(3), Repetition::Greedy)]
         );
     }
 
     #[test]
     fn parses_braced_quantifier_min_only() {
-        let t = parse_regex("a{2,}");
+        let t = parse_regex("a{2,}").unwrap();
         assert_eq!(
             t,
-            vec![Token::Quantifier(Box::new(Token::Literal('a')), 2, None)]
+            vec![Token::Quantifier(Box::new(Token::Literal('a')), 2, None, Repetition::Greedy)]
         );
     }
 
     #[test]
     fn parses_braced_quantifier_range() {
-        let t = parse_regex("a{2,4}");
+        let t = parse_regex("a{2,4}").unwrap();
         assert_eq!(
             t,
-            vec![Token::Quantifier(Box::new(Token::Literal('a')), 2, Some(4))]
+            vec![Token::Quantifier(Box::new(Token::Literal('a')), 2, Some(4), Repetition::Greedy)]
         );
     }
 
     #[test]
     fn parses_group_assigns_id_1() {
-        let t = parse_regex("(ab)");
+        let t = parse_regex("(ab)").unwrap();
         assert_eq!(
             t,
             vec![Token::Group(vec![Token::Literal('a'), Token::Literal('b')], 1)]
@@ -241,7 +851,7 @@ mod tests {
 
     #[test]
     fn parses_nested_groups_increment_ids() {
-        let t = parse_regex("(a(b))");
+        let t = parse_regex("(a(b))").unwrap();
         // Outer group gets id=1, inner group gets id=2 (based on your group_counter behavior)
         assert_eq!(
             t,
@@ -255,9 +865,108 @@ mod tests {
         );
     }
 
+    #[test]
+    fn non_capturing_groups_take_no_id() {
+        let t = parse_regex("(?:a|b)(c)").unwrap();
+        assert_eq!(
+            t,
+            vec![
+                Token::NonCapturing(vec![Token::Alternation(
+                    vec![Token::Literal('a')],
+                    vec![Token::Literal('b')]
+                )]),
+                Token::Group(vec![Token::Literal('c')], 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn inline_flags_apply_to_the_rest_or_the_scope() {
+        let t = parse_regex("a(?i)b(?-i)c(?s-i:.)").unwrap();
+        assert_eq!(t[0], Token::Literal('a'));
+        assert!(matches!(&t[1], Token::BracketGroup(class, _) if class.contains('B')));
+        assert_eq!(t[2], Token::Literal('c'));
+        assert_eq!(t[3], Token::NonCapturing(vec![Token::Wildcard]));
+        // A scoped flag ends with its group and takes no capture index
+        let t = parse_regex("(?m:$)$(a)").unwrap();
+        assert_eq!(t[0], Token::NonCapturing(vec![Token::LineEnd]));
+        assert_eq!(t[1], Token::EndAnchor);
+        assert_eq!(t[2], Token::Group(vec![Token::Literal('a')], 1));
+        // Only the enclosing group is affected
+        let t = parse_regex("((?i)a)a").unwrap();
+        assert!(matches!(&t[0], Token::Group(inner, 1) if inner.len() == 1));
+        assert_eq!(t[1], Token::Literal('a'));
+        assert!(parse_regex("(?-)a").is_err());
+    }
+
+    #[test]
+    fn named_groups_are_numbered_with_the_rest() {
+        let (tokens, names) =
+            parse_named(r"(a)(?P<x>b)(?<y_2>c)\k<x>\k<zz>", &ParseOptions::default()).unwrap();
+        assert_eq!(names, [("x".to_string(), 2), ("y_2".to_string(), 3)]);
+        assert_eq!(tokens[1], Token::Group(vec![Token::Literal('b')], 2));
+        assert_eq!(tokens[3], Token::Backreference(2));
+        // An unknown name is literal text
+        assert_eq!(tokens[4], Token::Literal('k'));
+        assert_eq!(tokens.len(), 4 + "k<zz>".len());
+    }
+
+    #[test]
+    fn parses_lookbehinds_without_capturing() {
+        assert_eq!(
+            parse_regex("(?<=a)(?<!b)(c)").unwrap(),
+            vec![
+                Token::LookBehind(vec![Token::Literal('a'), Token::EndAnchor]),
+                Token::NegativeLookBehind(vec![Token::Literal('b'), Token::EndAnchor]),
+                Token::Group(vec![Token::Literal('c')], 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_unicode_properties() {
+        let tokens = parse_regex(r"\p{Greek}\PL").unwrap();
+        let [
+            Token::BracketGroup(greek, GroupType::Positive),
+            Token::BracketGroup(letters, GroupType::Negative),
+        ] = &tokens[..]
+        else {
+            panic!("unexpected tokens {tokens:?}");
+        };
+        assert!(greek.contains('α') && !greek.contains('a'));
+        assert!(letters.contains('Ж') && !letters.contains('1'));
+        assert_eq!(
+            parse_regex(r"a\p{Bogus}"),
+            Err(RegexError::UnknownProperty { pos: 1 })
+        );
+        // Without a closing brace it's the literal `p` and a bad repetition
+        assert_eq!(
+            parse_regex(r"\p{L"),
+            Err(RegexError::BadRepetition { pos: 2 })
+        );
+    }
+
+    #[test]
+    fn parses_control_hex_and_unicode_escapes() {
+        assert_eq!(
+            parse_regex(r"\t\x41\u{e9}\u{1F600}\r\n").unwrap(),
+            "\tAé😀\r\n".chars().map(Token::Literal).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            parse_regex(r"[\t\x30-\x39\u{3b1}]").unwrap(),
+            vec![Token::BracketGroup(
+                CharClass::from_ranges([('\t', '\t'), ('0', '9'), ('α', 'α')]),
+                GroupType::Positive
+            )]
+        );
+        // Malformed ones are read as the letter, as before
+        assert_eq!(parse_regex(r"\xZ").unwrap()[0], Token::Literal('x'));
+        assert_eq!(parse_regex(r"\u0041").unwrap()[0], Token::Literal('u'));
+    }
+
     #[test]
     fn parses_alternation_inside_group() {
-        let t = parse_regex("(a|bc)");
+        let t = parse_regex("(a|bc)").unwrap();
         assert_eq!(
             t,
             vec![Token::Group(
@@ -272,7 +981,7 @@ mod tests {
 
     #[test]
     fn parses_backreference() {
-        let t = parse_regex(r"(ab)\1");
+        let t = parse_regex(r"(ab)\1").unwrap();
         assert_eq!(
             t,
             vec![
@@ -285,7 +994,7 @@ mod tests {
     #[test]
     fn parses_three_way_alternation_nesting() {
         // The parser nests alternations for more than 2 parts.
-        let t = parse_regex("(a|b|c)");
+        let t = parse_regex("(a|b|c)").unwrap();
 
         // Expected nesting:
         // Alternation( Alternation(a,b), c ) wrapped in Group(id=1)
@@ -303,4 +1012,86 @@ mod tests {
             )]
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parses_grapheme_escape_and_dot_in_grapheme_mode() {
+        assert_eq!(
+            parse_regex(r"\X.").unwrap(),
+            vec![Token::Grapheme, Token::Wildcard]
+        );
+
+        let opts = ParseOptions {
+            graphemes: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            parse_regex_with(r"\X.", &opts).unwrap(),
+            vec![Token::Grapheme, Token::Grapheme]
+        );
+    }
+
+    #[test]
+    fn multiline_mode_changes_dot_and_dollar() {
+        let opts = ParseOptions {
+            multiline: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            parse_regex_with(".$", &opts).unwrap(),
+            vec![
+                Token::BracketGroup(CharClass::new(['\n']), GroupType::Negative),
+                Token::LineEnd
+            ]
+        );
+
+        let opts = ParseOptions {
+            multiline: true,
+            dotall: true,
+            ..Default::default()
+        };
+        assert_eq!(parse_regex_with(".", &opts).unwrap(), vec![Token::Wildcard]);
+    }
+
+    #[test]
+    fn malformed_patterns_are_errors_with_positions() {
+        let cases = [
+            ("a[bc", RegexError::UnclosedClass { pos: 1 }),
+            ("é(ab|(c)", RegexError::UnclosedGroup { pos: 2 }),
+            ("ab)", RegexError::UnopenedGroup { pos: 2 }),
+            ("a{3", RegexError::BadRepetition { pos: 1 }),
+            ("a{x}", RegexError::BadRepetition { pos: 1 }),
+            ("a{2,1}", RegexError::BadRepetition { pos: 1 }),
+            ("*a", RegexError::NothingToRepeat { pos: 0 }),
+            ("x(a|+b)", RegexError::NothingToRepeat { pos: 4 }),
+            (r"ab\", RegexError::TrailingBackslash { pos: 2 }),
+        ];
+        for (pattern, err) in cases {
+            assert_eq!(parse_regex(pattern), Err(err), "{pattern}");
+        }
+        assert_eq!(
+            RegexError::UnclosedClass { pos: 1 }.to_string(),
+            "unclosed `[` at byte 1"
+        );
+        // Escaped parentheses don't open or close groups
+        assert_eq!(
+            parse_regex(r"(\)|a)").unwrap(),
+            vec![Token::Group(
+                vec![Token::Alternation(
+                    vec![Token::Literal(')')],
+                    vec![Token::Literal('a')]
+                )],
+                1
+            )]
+        );
+    }
+
+    #[test]
+    fn nesting_beyond_the_limit_is_an_error() {
+        assert!(check_nesting("((a)(b))", 2).is_ok());
+        let err = check_nesting("(((a)))", 2).unwrap_err();
+        assert_eq!((err.depth, err.limit), (3, 2));
+        assert!(check_nesting(r"(\(\(\(a)", 1).is_ok());
+        let deep = format!("{}a{}", "(".repeat(100_000), ")".repeat(100_000));
+        assert!(check_nesting(&deep, 200).is_err());
+    }
+}
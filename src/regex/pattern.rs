@@ -0,0 +1,566 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::regex::ast::{CharClass, Token};
+use crate::regex::fuzzy::FuzzyPattern;
+use crate::regex::matcher::{Backtracker, MatchContext};
+use crate::regex::parser::{ParseOptions, RegexError, parse_named};
+use crate::regex::pikevm::{Found, Program};
+use crate::regex::prefilter::{Prefilter, literal_prefixes};
+
+// A parsed pattern ready for matching. The source pattern and options are
+// kept so the Regex can be displayed, stored and rebuilt. Patterns the Pike VM
+// can run are matched on it; the rest (backreferences, lookbehinds, grapheme
+// clusters) fall back to the backtracker.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    pattern: String,
+    opts: ParseOptions,
+    tokens: Vec<Token>,
+    group_names: HashMap<String, usize>, // (?P<name>...) to its group index
+    ctx: MatchContext,
+    fuzzy: Option<FuzzyPattern>,
+    program: Option<Program>,
+    backtracker: Option<Backtracker>, // for what the Pike VM can't run
+    prefilter: Option<Prefilter>,
+}
+
+impl Regex {
+    pub fn new(pattern: &str, opts: &ParseOptions) -> Result<Regex, RegexError> {
+        let (mut tokens, names) = parse_named(pattern, opts)?;
+        if opts.whole_word {
+            tokens.insert(0, Token::NotAfterWord);
+            tokens.push(Token::NotBeforeWord);
+        }
+        // -x anchors both ends, as `^` and `$` around the pattern would
+        if opts.whole_line {
+            tokens.insert(0, Token::StartAnchor);
+            tokens.push(if opts.multiline {
+                Token::LineEnd
+            } else {
+                Token::EndAnchor
+            });
+        }
+        let word_chars = opts.word_chars.as_deref().unwrap_or("_");
+        let fuzzy = opts.fuzzy.and_then(|k| FuzzyPattern::new(pattern, k, opts));
+        let program = fuzzy.is_none().then(|| Program::compile(&tokens)).flatten();
+        let backtracker =
+            (fuzzy.is_none() && program.is_none()).then(|| Backtracker::compile(&tokens));
+        let prefilter = fuzzy.is_none().then(|| Prefilter::new(&tokens)).flatten();
+        Ok(Regex {
+            pattern: pattern.to_string(),
+            opts: opts.clone(),
+            tokens,
+            group_names: names.into_iter().collect(),
+            ctx: MatchContext {
+                ascii: opts.ascii,
+                word_chars: CharClass::new(word_chars.chars()),
+            },
+            fuzzy,
+            program,
+            backtracker,
+            prefilter,
+        })
+    }
+
+    // The pattern as written
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn options(&self) -> &ParseOptions {
+        &self.opts
+    }
+
+    // Index of the group called `name`, counting from 1 as `\1` does, so
+    // its span is `groups[index - 1]` in what captures_at returns
+    pub fn group_index(&self, name: &str) -> Option<usize> {
+        self.group_names.get(name).copied()
+    }
+
+    // The texts this pattern matches if it's nothing but literal characters
+    // or an alternation of them, as `(?:ERROR|WARN)` is, so they can be
+    // searched for together with other literals. None if one is a prefix of
+    // another, since a search for the longest wouldn't pick the alternative
+    // the pattern prefers.
+    pub fn as_literals(&self) -> Option<Vec<String>> {
+        if self.fuzzy.is_some() {
+            return None;
+        }
+        let (mut literals, exact) = literal_prefixes(&self.tokens);
+        if !exact || literals.iter().any(String::is_empty) {
+            return None;
+        }
+        literals.sort_unstable();
+        let prefix_free = literals
+            .windows(2)
+            .all(|pair| pair[0] == pair[1] || !pair[1].starts_with(&pair[0]));
+        prefix_free.then_some(literals)
+    }
+
+    // The literals every match starts with one of, if there are any
+    pub fn prefilter(&self) -> Option<&Prefilter> {
+        self.prefilter.as_ref()
+    }
+
+    // Match starting exactly at byte `pos` of `haystack`, plus the span of
+    // each capture group within it. The text before `pos` is only looked at
+    // for `^` and word boundaries.
+    pub fn captures_at<'a>(
+        &self,
+        haystack: &'a str,
+        pos: usize,
+    ) -> Option<(&'a str, Vec<Option<Range<usize>>>)> {
+        // Approximate matches are found leftmost-first, so one only counts
+        // here if it starts right at `pos`
+        if let Some(fuzzy) = &self.fuzzy {
+            let at_line_start = pos == 0 || haystack.as_bytes()[pos - 1] == b'\n';
+            if (fuzzy.is_anchored() || self.opts.whole_line) && !at_line_start {
+                return None;
+            }
+            let text = &haystack[pos..];
+            if self.opts.whole_line {
+                let line = &text[..text.find('\n').unwrap_or(text.len())];
+                return fuzzy.matches_whole(line).then(|| (line, Vec::new()));
+            }
+            let span = fuzzy.find(text).filter(|span| span.start == 0)?;
+            return Some((&text[span], Vec::new()));
+        }
+        if let Some(program) = &self.program {
+            return program.captures_at(haystack, pos, &self.ctx);
+        }
+        let backtracker = self.backtracker.as_ref()?;
+        backtracker.captures_at(haystack, pos, &self.ctx)
+    }
+
+    // The same pattern matched by the backtracker alone, as a reference for
+    // the other engines
+    pub fn on_backtracker(self) -> Regex {
+        let backtracker = self.fuzzy.is_none().then(|| Backtracker::compile(&self.tokens));
+        Regex {
+            program: None,
+            backtracker,
+            ..self
+        }
+    }
+
+    // The first byte at or after `pos` where a match could start: the next
+    // occurrence of the literal every match starts with, if there is one
+    pub fn next_candidate(&self, haystack: &str, pos: usize) -> Option<usize> {
+        match &self.prefilter {
+            Some(prefilter) => prefilter.find(haystack, pos),
+            None => Some(pos),
+        }
+    }
+
+    // The leftmost match starting at or after byte `pos` of `haystack`, with
+    // its start. The Pike VM, the backtracker and approximate matching each
+    // find it in one pass over the text; a fuzzy match that has to start a
+    // line is tried at each candidate position in turn.
+    pub fn find_from<'a>(&self, haystack: &'a str, mut pos: usize) -> Option<Found<'a>> {
+        if let Some(fuzzy) = &self.fuzzy
+            && !fuzzy.is_anchored()
+            && !self.opts.whole_line
+        {
+            let span = fuzzy.find(&haystack[pos..])?;
+            let (start, end) = (pos + span.start, pos + span.end);
+            return Some((start, &haystack[start..end], Vec::new()));
+        }
+        if let Some(program) = &self.program {
+            return program.find_from(haystack, pos, &self.ctx);
+        }
+        if let Some(backtracker) = &self.backtracker {
+            let prefilter = self.prefilter.as_ref();
+            return backtracker.find_from(haystack, pos, &self.ctx, prefilter);
+        }
+        loop {
+            pos = self.next_candidate(haystack, pos)?;
+            if let Some((m, groups)) = self.captures_at(haystack, pos) {
+                return Some((pos, m, groups));
+            }
+            pos += haystack[pos..].chars().next()?.len_utf8();
+        }
+    }
+
+    // Whether the pattern matches anywhere in `text`
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find_from(text, 0).is_some()
+    }
+
+    // Every match in `text`, overlapping ones included: one span per
+    // position a match starts at. Printing uses non-overlapping matches;
+    // this is for callers counting k-mers or scanning for signatures.
+    pub fn find_overlapping_iter<'r, 't>(&'r self, text: &'t str) -> OverlappingMatches<'r, 't> {
+        OverlappingMatches {
+            regex: self,
+            text,
+            pos: Some(0),
+        }
+    }
+}
+
+pub struct OverlappingMatches<'r, 't> {
+    regex: &'r Regex,
+    text: &'t str,
+    pos: Option<usize>, // next start to try; None once past the end
+}
+
+impl Iterator for OverlappingMatches<'_, '_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Range<usize>> {
+        while let Some(pos) = self
+            .pos
+            .and_then(|pos| self.regex.next_candidate(self.text, pos))
+        {
+            self.pos = self.text[pos..].chars().next().map(|c| pos + c.len_utf8());
+            if let Some((m, _)) = self.regex.captures_at(self.text, pos) {
+                return Some(pos..pos + m.len());
+            }
+        }
+        None
+    }
+}
+
+// `"a+b".parse::<Regex>()` compiles with default options
+impl FromStr for Regex {
+    type Err = RegexError;
+
+    fn from_str(pattern: &str) -> Result<Regex, RegexError> {
+        Regex::new(pattern, &ParseOptions::default())
+    }
+}
+
+// Shows the pattern as written; options aren't included
+impl fmt::Display for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+// With the `serde` feature a Regex is stored as its pattern plus options,
+// e.g. `{"pattern": "err(or)?", "case_insensitive": true}`; options left out
+// take their defaults.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredRegex {
+    pattern: String,
+    #[serde(flatten)]
+    opts: ParseOptions,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Regex {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        StoredRegex {
+            pattern: self.pattern.clone(),
+            opts: self.opts.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Regex {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+        let stored = StoredRegex::deserialize(deserializer)?;
+        Regex::new(&stored.pattern, &stored.opts).map_err(serde::de::Error::custom)
+    }
+}
+
+// Configures and compiles a Regex, e.g.
+// `RegexBuilder::new("error").case_insensitive(true).unicode(false).build()`
+#[derive(Debug, Clone)]
+pub struct RegexBuilder {
+    pattern: String,
+    opts: ParseOptions,
+}
+
+impl RegexBuilder {
+    pub fn new(pattern: &str) -> RegexBuilder {
+        RegexBuilder {
+            pattern: pattern.to_string(),
+            opts: ParseOptions::default(),
+        }
+    }
+
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.opts.case_insensitive = yes;
+        self
+    }
+
+    // `false` restricts classes and case folding to ASCII (--no-unicode)
+    pub fn unicode(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.opts.ascii = !yes;
+        self
+    }
+
+    pub fn graphemes(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.opts.graphemes = yes;
+        self
+    }
+
+    pub fn multiline(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.opts.multiline = yes;
+        self
+    }
+
+    pub fn dotall(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.opts.dotall = yes;
+        self
+    }
+
+    pub fn fuzzy(&mut self, max_edits: Option<usize>) -> &mut RegexBuilder {
+        self.opts.fuzzy = max_edits;
+        self
+    }
+
+    pub fn whole_word(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.opts.whole_word = yes;
+        self
+    }
+
+    pub fn whole_line(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.opts.whole_line = yes;
+        self
+    }
+
+    // Non-alphanumeric chars that count as part of a word, instead of `_`
+    pub fn word_chars(&mut self, chars: &str) -> &mut RegexBuilder {
+        self.opts.word_chars = Some(chars.to_string());
+        self
+    }
+
+    // How deep groups may nest before the pattern is refused, instead of
+    // DEFAULT_NESTING_LIMIT
+    pub fn nesting_limit(&mut self, limit: usize) -> &mut RegexBuilder {
+        self.opts.nesting_limit = Some(limit);
+        self
+    }
+
+    pub fn build(&self) -> Result<Regex, RegexError> {
+        Regex::new(&self.pattern, &self.opts)
+    }
+
+    // Same as build, which checks the nesting limit too
+    pub fn try_build(&self) -> Result<Regex, RegexError> {
+        self.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Regex, RegexBuilder};
+
+    #[test]
+    fn ascii_mode_folds_only_ascii_case() {
+        let re = RegexBuilder::new(r"(\w+) \1")
+            .case_insensitive(true)
+            .unicode(false)
+            .build()
+            .unwrap();
+        assert!(re.captures_at("Hello hello", 0).is_some());
+
+        // Non-ASCII letters are compared exactly under --no-unicode
+        let re = RegexBuilder::new(r"(.) \1")
+            .case_insensitive(true)
+            .unicode(false)
+            .build()
+            .unwrap();
+        assert!(re.captures_at("\u{c9} \u{e9}", 0).is_none());
+
+        let re = RegexBuilder::new(r"(.) \1")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        assert!(re.captures_at("\u{c9} \u{e9}", 0).is_some());
+    }
+
+    #[test]
+    fn case_insensitive_literals_and_classes() {
+        let re = RegexBuilder::new("err[a-c]r")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        assert!(re.is_match("ERRBR"));
+        assert!(re.as_literals().is_none());
+        let re = RegexBuilder::new("[^x]")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        assert!(!re.is_match("X"));
+
+        // The Kelvin sign only lowercases to `k`; nothing uppercases to it
+        let re = RegexBuilder::new("[k]elvin")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        assert!(re.is_match("\u{212a}ELVIN"));
+        let re = RegexBuilder::new("k")
+            .case_insensitive(true)
+            .unicode(false)
+            .build()
+            .unwrap();
+        assert!(re.is_match("K") && !re.is_match("\u{212a}"));
+    }
+
+    #[test]
+    fn overlapping_iter_reports_a_match_per_start() {
+        let re = RegexBuilder::new("aa").build().unwrap();
+        let spans: Vec<_> = re.find_overlapping_iter("aaaa").collect();
+        assert_eq!(spans, [0..2, 1..3, 2..4]);
+
+        let re = RegexBuilder::new(r"\w\w\w").build().unwrap();
+        assert_eq!(re.find_overlapping_iter("é-abcd").count(), 2);
+    }
+
+    #[test]
+    fn is_match_searches_every_position() {
+        let re = RegexBuilder::new(r"src/\w+\.rs").build().unwrap();
+        assert!(re.is_match("./src/main.rs"));
+        assert!(!re.is_match("./tests/main.rs"));
+
+        let re = RegexBuilder::new("^src").build().unwrap();
+        assert!(re.is_match("src/lib.rs"));
+        assert!(!re.is_match("./src/lib.rs"));
+    }
+
+    #[test]
+    fn word_chars_decide_boundaries() {
+        let re = RegexBuilder::new(r"\bcolor\b").build().unwrap();
+        assert!(re.is_match("a color here"));
+        assert!(!re.is_match("colors"));
+        assert!(re.is_match("bg-color"));
+        assert!(!re.is_match("bg_color"));
+
+        let re = RegexBuilder::new(r"\bcolor\b")
+            .word_chars("-_")
+            .build()
+            .unwrap();
+        assert!(!re.is_match("bg-color"));
+        assert!(re.is_match("(color)"));
+
+        let re = RegexBuilder::new("snake")
+            .whole_word(true)
+            .word_chars("")
+            .build()
+            .unwrap();
+        assert!(re.is_match("snake_case"));
+        assert!(!re.is_match("snakes"));
+
+        // Inside a group the boundary still sees the text after it
+        let re = RegexBuilder::new(r"(foo\b)").build().unwrap();
+        assert!(!re.is_match("foobar"));
+        assert_eq!(re.captures_at("x foo", 2).unwrap().0, "foo");
+    }
+
+    #[test]
+    fn whole_line_anchors_both_ends() {
+        let re = RegexBuilder::new(r"\d+").whole_line(true).build().unwrap();
+        assert!(re.is_match("2024"));
+        assert!(!re.is_match("v2024"));
+        assert!(!re.is_match("2024 "));
+
+        let re = RegexBuilder::new("b")
+            .whole_line(true)
+            .multiline(true)
+            .build()
+            .unwrap();
+        assert_eq!(re.captures_at("a\nb\nc", 2).unwrap().0, "b");
+        assert!(re.captures_at("a\nbc", 2).is_none());
+    }
+
+    #[test]
+    fn inline_flags_toggle_options_mid_pattern() {
+        let re: Regex = "(?i)error: (?-i)Disk".parse().unwrap();
+        assert!(re.is_match("ERROR: Disk"));
+        assert!(!re.is_match("ERROR: disk"));
+        let re: Regex = r"(?i)(k)\1".parse().unwrap();
+        assert!(re.is_match("kK"));
+        let re: Regex = "(?m)a.$".parse().unwrap();
+        assert!(re.is_match("ab\nc"));
+        assert!(!re.is_match("a\n"));
+        let re = RegexBuilder::new("(?s)a.b")
+            .multiline(true)
+            .build()
+            .unwrap();
+        assert!(re.is_match("a\nb"));
+    }
+
+    #[test]
+    fn named_groups_map_to_capture_indexes() {
+        let re: Regex = r"(?P<key>\w+)=(?<value>\w+);\k<key>".parse().unwrap();
+        assert_eq!(re.group_index("key"), Some(1));
+        assert_eq!(re.group_index("value"), Some(2));
+        assert_eq!(re.group_index("other"), None);
+        let (m, groups) = re.captures_at("a=1;a", 0).unwrap();
+        assert_eq!(m, "a=1;a");
+        assert_eq!(groups[re.group_index("value").unwrap() - 1], Some(2..3));
+        assert!(!re.is_match("a=1;b"));
+    }
+
+    #[test]
+    fn start_anchor_holds_only_at_line_starts() {
+        let re: Regex = "(^foo|bar)".parse().unwrap();
+        assert!(re.is_match("foo x"));
+        assert!(!re.is_match("x foo"));
+        assert!(re.is_match("x bar"));
+        assert_eq!(re.captures_at("x\nfoo", 2).unwrap().0, "foo");
+
+        let re: Regex = "a^b".parse().unwrap();
+        assert!(!re.is_match("a^b"));
+    }
+
+    #[test]
+    fn parses_and_displays_the_source_pattern() {
+        let re: Regex = r"^(\w+)@x\.com".parse().unwrap();
+        assert_eq!(re.to_string(), r"^(\w+)@x\.com");
+        assert!(re.is_match("me@x.com"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn round_trips_through_serde_with_options() {
+        use super::ParseOptions;
+
+        let re = RegexBuilder::new(r"(a)\1")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        let json = serde_json::to_string(&re).unwrap();
+        let back: Regex = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.as_str(), r"(a)\1");
+        assert!(back.options().case_insensitive);
+        assert!(back.is_match("aA"));
+        let plain: Regex = serde_json::from_str(r#"{"pattern": "a+"}"#).unwrap();
+        assert!(!plain.options().case_insensitive);
+
+        let opts = ParseOptions {
+            word_chars: Some("_-".to_string()),
+            fuzzy: Some(1),
+            ..ParseOptions::default()
+        };
+        let json = serde_json::to_string(&opts).unwrap();
+        let back: ParseOptions = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.word_chars.as_deref(), Some("_-"));
+        assert_eq!(back.fuzzy, Some(1));
+    }
+
+    #[test]
+    fn every_constructor_refuses_patterns_nested_past_the_limit() {
+        use crate::regex::{ParseOptions, RegexError};
+
+        let deep = format!("{}a{}", "(".repeat(5000), ")".repeat(5000));
+        let nested = |r: Result<Regex, RegexError>| matches!(r, Err(RegexError::Nesting(_)));
+        assert!(nested(Regex::new(&deep, &ParseOptions::default())));
+        assert!(nested(deep.parse()));
+        assert!(nested(RegexBuilder::new(&deep).build()));
+
+        let raised = format!("{}a{}", "(".repeat(300), ")".repeat(300));
+        assert!(nested(raised.parse()));
+        let re = RegexBuilder::new(&raised).nesting_limit(300).build();
+        assert!(re.unwrap().is_match("a"));
+    }
+}
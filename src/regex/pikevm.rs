@@ -0,0 +1,409 @@
+use std::ops::Range;
+
+use crate::regex::ast::{Repetition, Token};
+use crate::regex::matcher::{MatchContext, matches_token, word_around};
+use crate::regex::prefilter::Prefilter;
+
+// Patterns that would compile to more instructions than this, such as
+// `(a|b){5000}`, are left to the backtracker
+const MAX_INSTS: usize = 10_000;
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(Token),         // one char matching a single-char token
+    Assert(Token),       // `^`, `$`, `\b` and the like; consumes nothing
+    Split(usize, usize), // go both ways, the first with higher priority
+    Jump(usize),
+    Save(usize), // record the position in a capture slot
+    Match,
+}
+
+// Capture slots of one thread: the match start and end, then a start and end
+// for each group
+type Slots = Vec<Option<usize>>;
+
+// A match's start, its text and each group's span relative to that start
+pub type Found<'a> = (usize, &'a str, Vec<Option<Range<usize>>>);
+
+// A pattern compiled for the Pike VM. Every way through the pattern is
+// followed in lockstep, one char at a time, so matching takes time linear in
+// the text however the pattern nests (`(a+)+b` on a long run of `a`s). Among
+// matches at the same start the one the backtracker would find first wins:
+// greedy quantifiers prefer another repeat, alternation its left branch.
+#[derive(Debug, Clone)]
+pub struct Program {
+    insts: Vec<Inst>,
+    slots: usize,
+    prefilter: Option<Prefilter>,
+}
+
+impl Program {
+    // None for what only the backtracker handles: backreferences,
+    // lookbehinds and grapheme clusters
+    pub fn compile(tokens: &[Token]) -> Option<Program> {
+        let mut compiler = Compiler {
+            insts: Vec::new(),
+            groups: 0,
+        };
+        compiler.sequence(tokens)?;
+        compiler.push(Inst::Match)?;
+        Some(Program {
+            insts: compiler.insts,
+            slots: 2 + 2 * compiler.groups,
+            prefilter: Prefilter::new(tokens),
+        })
+    }
+
+    // The match starting exactly at byte `pos`, as Regex::captures_at
+    // reports it
+    pub fn captures_at<'a>(
+        &self,
+        haystack: &'a str,
+        pos: usize,
+        ctx: &MatchContext,
+    ) -> Option<(&'a str, Vec<Option<Range<usize>>>)> {
+        let slots = self.run(haystack, pos, ctx, true)?;
+        Some(self.report(haystack, &slots))
+    }
+
+    // The leftmost match starting at or after byte `pos`, with its start
+    pub fn find_from<'a>(
+        &self,
+        haystack: &'a str,
+        pos: usize,
+        ctx: &MatchContext,
+    ) -> Option<Found<'a>> {
+        let slots = self.run(haystack, pos, ctx, false)?;
+        let (m, groups) = self.report(haystack, &slots);
+        Some((slots[0]?, m, groups))
+    }
+
+    // The text matched and each group's span relative to its start
+    fn report<'a>(&self, haystack: &'a str, slots: &Slots) -> (&'a str, Vec<Option<Range<usize>>>) {
+        let (start, end) = (slots[0].unwrap_or(0), slots[1].unwrap_or(0));
+        let groups = slots[2..]
+            .chunks(2)
+            .map(|pair| match *pair {
+                [Some(s), Some(e)] if start <= s && s <= e => Some(s - start..e - start),
+                _ => None,
+            })
+            .collect();
+        (&haystack[start..end], groups)
+    }
+
+    // Slots of the winning match. Unanchored, a new thread starts at every
+    // position, behind those already running, until something matches.
+    fn run(
+        &self,
+        haystack: &str,
+        start: usize,
+        ctx: &MatchContext,
+        anchored: bool,
+    ) -> Option<Slots> {
+        let mut current = Threads::new(self.insts.len());
+        let mut next = Threads::new(self.insts.len());
+        let mut matched: Option<Slots> = None;
+        let mut pos = start;
+        loop {
+            let idle = !anchored && matched.is_none() && current.list.is_empty();
+            // With nothing running, skip ahead to where the required prefix
+            // next occurs
+            if idle && let Some(prefilter) = &self.prefilter {
+                pos = prefilter.find(haystack, pos)?;
+            }
+            let c = haystack[pos..].chars().next();
+            // Otherwise a char that can't start a match is passed over
+            // without starting a thread
+            if idle
+                && let Inst::Char(first) = &self.insts[0]
+                && !c.is_some_and(|c| matches_token(first, c))
+            {
+                let c = c?;
+                pos += c.len_utf8();
+                continue;
+            }
+            if matched.is_none() && (pos == start || !anchored) {
+                let mut slots = vec![None; self.slots];
+                slots[0] = Some(pos);
+                self.add(&mut current, 0, slots, haystack, pos, ctx);
+            }
+            if current.list.is_empty() && (matched.is_some() || anchored) {
+                break;
+            }
+            for (pc, mut slots) in current.list.drain(..) {
+                match &self.insts[pc] {
+                    // Threads behind this one have lower priority
+                    Inst::Match => {
+                        slots[1] = Some(pos);
+                        matched = Some(slots);
+                        break;
+                    }
+                    Inst::Char(token) => {
+                        if let Some(c) = c
+                            && matches_token(token, c)
+                        {
+                            let at = pos + c.len_utf8();
+                            self.add(&mut next, pc + 1, slots, haystack, at, ctx);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            let Some(c) = c else {
+                break;
+            };
+            pos += c.len_utf8();
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        matched
+    }
+
+    // Follows jumps, splits, saves and assertions from `pc`, adding each
+    // thread that reaches a char or the end to `threads` in priority order
+    fn add(
+        &self,
+        threads: &mut Threads,
+        pc: usize,
+        slots: Slots,
+        haystack: &str,
+        pos: usize,
+        ctx: &MatchContext,
+    ) {
+        let mut stack = std::mem::take(&mut threads.stack);
+        stack.push((pc, slots));
+        while let Some((pc, mut slots)) = stack.pop() {
+            if threads.seen[pc] == threads.generation {
+                continue;
+            }
+            threads.seen[pc] = threads.generation;
+            match &self.insts[pc] {
+                Inst::Jump(to) => stack.push((*to, slots)),
+                Inst::Split(first, second) => {
+                    stack.push((*second, slots.clone()));
+                    stack.push((*first, slots));
+                }
+                Inst::Save(slot) => {
+                    slots[*slot] = Some(pos);
+                    stack.push((pc + 1, slots));
+                }
+                Inst::Assert(token) => {
+                    if assertion_holds(token, haystack, pos, ctx) {
+                        stack.push((pc + 1, slots));
+                    }
+                }
+                Inst::Char(_) | Inst::Match => threads.list.push((pc, slots)),
+            }
+        }
+        threads.stack = stack;
+    }
+}
+
+// What the backtracker's positional tokens check, at byte `pos`
+fn assertion_holds(token: &Token, haystack: &str, pos: usize, ctx: &MatchContext) -> bool {
+    let bytes = haystack.as_bytes();
+    match token {
+        Token::StartAnchor => pos == 0 || bytes[pos - 1] == b'\n',
+        Token::EndAnchor => pos == haystack.len(),
+        Token::LineEnd => pos == haystack.len() || bytes[pos] == b'\n',
+        _ => {
+            let (before, after) = word_around(haystack, pos, ctx);
+            match token {
+                Token::WordBoundary => before != after,
+                Token::NonWordBoundary => before == after,
+                Token::NotAfterWord => !before,
+                _ => !after,
+            }
+        }
+    }
+}
+
+// Threads waiting on the same char, highest priority first. Each instruction
+// is entered at most once per position, which keeps the list no longer than
+// the program.
+struct Threads {
+    list: Vec<(usize, Slots)>,
+    seen: Vec<usize>, // generation in which each instruction was last entered
+    generation: usize,
+    stack: Vec<(usize, Slots)>, // kept between calls to add for its allocation
+}
+
+impl Threads {
+    fn new(len: usize) -> Threads {
+        Threads {
+            list: Vec::new(),
+            seen: vec![0; len],
+            generation: 1,
+            stack: Vec::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.generation += 1;
+    }
+}
+
+struct Compiler {
+    insts: Vec<Inst>,
+    groups: usize,
+}
+
+impl Compiler {
+    fn push(&mut self, inst: Inst) -> Option<usize> {
+        if self.insts.len() >= MAX_INSTS {
+            return None;
+        }
+        self.insts.push(inst);
+        Some(self.insts.len() - 1)
+    }
+
+    fn sequence(&mut self, tokens: &[Token]) -> Option<()> {
+        tokens.iter().try_for_each(|t| self.token(t))
+    }
+
+    fn token(&mut self, token: &Token) -> Option<()> {
+        match token {
+            Token::Group(inner, id) => {
+                self.groups = self.groups.max(*id);
+                self.push(Inst::Save(2 * id))?;
+                self.sequence(inner)?;
+                self.push(Inst::Save(2 * id + 1))?;
+            }
+            Token::NonCapturing(inner) => self.sequence(inner)?,
+            Token::Alternation(left, right) => {
+                let split = self.push(Inst::Split(0, 0))?;
+                self.sequence(left)?;
+                let jump = self.push(Inst::Jump(0))?;
+                let right_start = self.insts.len();
+                self.sequence(right)?;
+                self.insts[split] = Inst::Split(split + 1, right_start);
+                self.insts[jump] = Inst::Jump(self.insts.len());
+            }
+            Token::Quantifier(inner, min, max, repetition) => {
+                for _ in 0..*min {
+                    self.token(inner)?;
+                }
+                let greedy = *repetition == Repetition::Greedy;
+                let mut splits = Vec::new();
+                match max {
+                    // A loop back to a split between another repeat and
+                    // moving on
+                    None => {
+                        let split = self.push(Inst::Split(0, 0))?;
+                        self.token(inner)?;
+                        self.push(Inst::Jump(split))?;
+                        splits.push(split);
+                    }
+                    // Up to max - min more repeats, each of which may be
+                    // skipped
+                    Some(max) => {
+                        for _ in *min..*max {
+                            splits.push(self.push(Inst::Split(0, 0))?);
+                            self.token(inner)?;
+                        }
+                    }
+                }
+                let out = self.insts.len();
+                for split in splits {
+                    self.insts[split] = if greedy {
+                        Inst::Split(split + 1, out)
+                    } else {
+                        Inst::Split(out, split + 1)
+                    };
+                }
+            }
+            Token::StartAnchor
+            | Token::EndAnchor
+            | Token::LineEnd
+            | Token::WordBoundary
+            | Token::NonWordBoundary
+            | Token::NotAfterWord
+            | Token::NotBeforeWord => {
+                self.push(Inst::Assert(token.clone()))?;
+            }
+            Token::Grapheme
+            | Token::Backreference(_)
+            | Token::BackreferenceFold(_)
+            | Token::LookBehind(_)
+            | Token::NegativeLookBehind(_) => return None,
+            _ => {
+                self.push(Inst::Char(token.clone()))?;
+            }
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Program;
+    use crate::regex::matcher::{MatchContext, match_captures};
+    use crate::regex::parse_regex;
+
+    // The VM and the backtracker on the same pattern and text
+    fn both(pattern: &str, text: &str) -> (Option<String>, Option<String>) {
+        let tokens = parse_regex(pattern).unwrap();
+        let ctx = MatchContext::default();
+        let vm = Program::compile(&tokens)
+            .unwrap()
+            .captures_at(text, 0, &ctx);
+        let backtracker = match_captures(text, 0, &tokens, &ctx);
+        let show =
+            |found: Option<(&str, Vec<_>)>| found.map(|(m, groups)| format!("{m} {groups:?}"));
+        (show(vm), show(backtracker))
+    }
+
+    #[test]
+    fn agrees_with_the_backtracker() {
+        let cases = [
+            ("a+b", "aaab"),
+            ("a*?b", "aab"),
+            ("<.+?>", "<a><b>"),
+            ("(a|b)+c", "abac"),
+            ("(x)?(y)", "y"),
+            (r"(\d+)-(\d+)", "12-345x"),
+            ("(a|ab)(c|bcd)", "abcd"),
+            (r"\bfoo\b", "foo bar"),
+            ("^a$", "a"),
+            ("a{2,3}", "aaaa"),
+            ("(?:ab){2}", "ababab"),
+            ("(a*)*b", "aab"),
+            ("x", "y"),
+        ];
+        for (pattern, text) in cases {
+            let (vm, backtracker) = both(pattern, text);
+            assert_eq!(vm, backtracker, "{pattern} on {text}");
+        }
+    }
+
+    #[test]
+    fn nested_repeats_take_linear_time() {
+        let tokens = parse_regex("(a+)+b").unwrap();
+        let program = Program::compile(&tokens).unwrap();
+        let text = "a".repeat(10_000);
+        let ctx = MatchContext::default();
+        assert!(program.captures_at(&text, 0, &ctx).is_none());
+        assert!(program.find_from(&text, 0, &ctx).is_none());
+        let text = text + "b";
+        let (start, m, groups) = program.find_from(&text, 0, &ctx).unwrap();
+        assert_eq!((start, m.len()), (0, 10_001));
+        assert_eq!(groups, [Some(0..10_000)]);
+    }
+
+    #[test]
+    fn finds_the_leftmost_match() {
+        let ctx = MatchContext::default();
+        let program = Program::compile(&parse_regex(r"(\d+|x)").unwrap()).unwrap();
+        assert_eq!(
+            program
+                .find_from("ab 123 x", 1, &ctx)
+                .map(|(s, m, _)| (s, m)),
+            Some((3, "123"))
+        );
+        assert_eq!(program.find_from("ab 123 x", 4, &ctx).unwrap().1, "23");
+        assert!(program.find_from("ab", 0, &ctx).is_none());
+        // Backreferences need the backtracker
+        assert!(Program::compile(&parse_regex(r"(a)\1").unwrap()).is_none());
+    }
+}
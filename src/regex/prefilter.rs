@@ -0,0 +1,172 @@
+use aho_corasick::{AhoCorasick, Input, MatchKind};
+use memchr::memmem::Finder;
+
+use crate::regex::ast::Token;
+
+// Prefixes stop growing before there would be more than this many of them,
+// as for `(a|b)(c|d)(e|f)...`
+const MAX_PREFIXES: usize = 64;
+
+// The literal texts every match of a pattern starts with one of, as in
+// `error:` for `error:\d+` or `ERROR`, `WARN` and `FATAL` for
+// `(ERROR|WARN|FATAL) .*`. A substring search for them finds the only places
+// a match can start, so the matchers needn't be tried anywhere else.
+#[derive(Debug, Clone)]
+pub struct Prefilter {
+    prefixes: Vec<String>,
+    search: Search,
+}
+
+#[derive(Debug, Clone)]
+enum Search {
+    One(Box<Finder<'static>>), // memchr's substring search
+    Many(AhoCorasick),
+}
+
+impl Prefilter {
+    // None when some match could start with anything
+    pub fn new(tokens: &[Token]) -> Option<Prefilter> {
+        Prefilter::from_prefixes(literal_prefixes(tokens).0)
+    }
+
+    pub fn from_prefixes(mut prefixes: Vec<String>) -> Option<Prefilter> {
+        prefixes.sort_unstable();
+        prefixes.dedup();
+        if prefixes.is_empty() || prefixes.iter().any(String::is_empty) {
+            return None;
+        }
+        let search = match prefixes.as_slice() {
+            [prefix] => Search::One(Box::new(Finder::new(prefix.as_bytes()).into_owned())),
+            _ => Search::Many(
+                AhoCorasick::builder()
+                    .match_kind(MatchKind::LeftmostFirst)
+                    .build(&prefixes)
+                    .expect("prefix automaton fits in memory"),
+            ),
+        };
+        Some(Prefilter { prefixes, search })
+    }
+
+    pub fn prefixes(&self) -> &[String] {
+        &self.prefixes
+    }
+
+    // The first byte at or after `pos` where a match could start. UTF-8
+    // never has a char's bytes inside another's, so it's a char boundary.
+    pub fn find(&self, haystack: &str, pos: usize) -> Option<usize> {
+        match &self.search {
+            Search::One(finder) => Some(pos + finder.find(&haystack.as_bytes()[pos..])?),
+            Search::Many(automaton) => {
+                let m = automaton.find(Input::new(haystack).range(pos..))?;
+                Some(m.start())
+            }
+        }
+    }
+}
+
+// The literal texts that every match of `tokens` starts with one of, and
+// whether they're exactly what the pattern matches: nothing but literals and
+// alternations of them, with no groups to capture or assertions to check
+pub fn literal_prefixes(tokens: &[Token]) -> (Vec<String>, bool) {
+    let mut prefixes = vec![String::new()];
+    let mut exact = true;
+    let complete = extend_prefixes(tokens, &mut prefixes, &mut exact);
+    (prefixes, exact && complete)
+}
+
+// Adds what `tokens` start with to each of `prefixes`; true if all of them
+// were literal, so what follows extends the prefixes too
+fn extend_prefixes(tokens: &[Token], prefixes: &mut Vec<String>, exact: &mut bool) -> bool {
+    for token in tokens {
+        match token {
+            Token::Literal(c) => prefixes.iter_mut().for_each(|p| p.push(*c)),
+            // Assertions consume nothing, so the literals after them still
+            // follow on directly
+            Token::StartAnchor
+            | Token::EndAnchor
+            | Token::LineEnd
+            | Token::WordBoundary
+            | Token::NonWordBoundary
+            | Token::NotAfterWord
+            | Token::NotBeforeWord
+            | Token::LookBehind(_)
+            | Token::NegativeLookBehind(_) => *exact = false,
+            Token::Group(inner, _) => {
+                *exact = false;
+                if !extend_prefixes(inner, prefixes, exact) {
+                    return false;
+                }
+            }
+            Token::NonCapturing(inner) => {
+                if !extend_prefixes(inner, prefixes, exact) {
+                    return false;
+                }
+            }
+            // A match starts with one of the left branch's prefixes or one
+            // of the right's
+            Token::Alternation(left, right) => {
+                let mut left_prefixes = prefixes.clone();
+                let mut right_prefixes = prefixes.clone();
+                let complete = extend_prefixes(left, &mut left_prefixes, exact)
+                    & extend_prefixes(right, &mut right_prefixes, exact);
+                if left_prefixes.len() + right_prefixes.len() > MAX_PREFIXES {
+                    return false;
+                }
+                left_prefixes.append(&mut right_prefixes);
+                *prefixes = left_prefixes;
+                if !complete {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Prefilter, literal_prefixes};
+    use crate::regex::{ParseOptions, parse_regex, parse_regex_with};
+
+    fn prefix_of(pattern: &str) -> Option<usize> {
+        let tokens = parse_regex(pattern).unwrap();
+        Prefilter::new(&tokens)?.find("xx error: 42 warn", 0)
+    }
+
+    #[test]
+    fn finds_where_matches_can_start() {
+        assert_eq!(prefix_of(r"error:\s\d+"), Some(3));
+        assert_eq!(prefix_of(r"\berr(or|no)"), Some(3));
+        assert_eq!(prefix_of(r"(?:er)(r)o+"), Some(3));
+        assert_eq!(prefix_of("warn$"), Some(13));
+        assert_eq!(prefix_of("(fatal|warn|error): "), Some(3));
+        assert_eq!(prefix_of("fatal"), None);
+        // Nothing is required at the start of these
+        assert!(Prefilter::new(&parse_regex(r"\d+:").unwrap()).is_none());
+        assert!(Prefilter::new(&parse_regex("e?rror").unwrap()).is_none());
+        assert!(Prefilter::new(&parse_regex("(err|\\w)").unwrap()).is_none());
+
+        let opts = ParseOptions {
+            case_insensitive: true,
+            ..ParseOptions::default()
+        };
+        assert!(Prefilter::new(&parse_regex_with("error", &opts).unwrap()).is_none());
+    }
+
+    #[test]
+    fn alternations_of_literals_are_exact() {
+        let prefixes = |pattern: &str| literal_prefixes(&parse_regex(pattern).unwrap());
+        let (mut found, exact) = prefixes("(?:ERROR|WARN|FATAL)!");
+        found.sort();
+        assert_eq!(found, ["ERROR!", "FATAL!", "WARN!"]);
+        assert!(exact);
+        // A group must report its span, and the tail isn't literal
+        assert!(!prefixes("(ERROR|WARN)").1);
+        let (found, exact) = prefixes("(?:a|b)c+");
+        assert_eq!((found.len(), exact), (2, false));
+        // Past 64 combinations the prefixes stop growing
+        let (found, exact) = prefixes("(?:a|b)(?:a|b)(?:a|b)(?:a|b)(?:a|b)(?:a|b)(?:a|b)");
+        assert_eq!((found.len(), found[0].len(), exact), (64, 6, false));
+    }
+}
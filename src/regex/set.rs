@@ -0,0 +1,333 @@
+use std::collections::HashSet;
+use std::ops::Range;
+
+use aho_corasick::{AhoCorasick, Anchored, Input, MatchKind, StartKind};
+
+use crate::regex::parser::{ParseOptions, RegexError};
+use crate::regex::pattern::Regex;
+use crate::regex::prefilter::Prefilter;
+
+// Several patterns searched together. Matches report which pattern produced
+// them so callers can attribute hits (--pattern-label, per-pattern stats).
+// Repeated patterns are compiled once, and patterns that are plain literals
+// (or alternations of them) share one Aho-Corasick automaton, so long -f
+// lists of indicators don't cost a backtracking attempt per pattern at every
+// position. When every other pattern starts with a literal too, a second
+// automaton over all those prefixes finds where a match can start.
+#[derive(Debug, Clone)]
+pub struct RegexSet {
+    regexes: Vec<(usize, Regex)>, // pattern index and compiled form
+    literals: Option<Literals>,
+    prefilter: Option<Prefilter>, // prefixes of every pattern, literals included
+    len: usize,
+    graphemes: bool, // matches may only start on grapheme cluster boundaries
+}
+
+#[derive(Debug, Clone)]
+struct Literals {
+    automaton: AhoCorasick,
+    patterns: Vec<usize>, // pattern index of each automaton pattern
+}
+
+#[derive(Debug, Clone)]
+pub struct SetMatch<'a> {
+    pub pattern: usize, // index into the patterns the set was built from
+    pub text: &'a str,
+    pub groups: Vec<Option<Range<usize>>>, // spans relative to the match start
+}
+
+impl RegexSet {
+    pub fn new(patterns: &[String], opts: &ParseOptions) -> Result<RegexSet, RegexError> {
+        RegexSet::build(patterns, opts, true)
+    }
+
+    // Every pattern on the backtracking matcher, literals included. The
+    // reference for --compare-engines.
+    pub fn backtracking(patterns: &[String], opts: &ParseOptions) -> Result<RegexSet, RegexError> {
+        RegexSet::build(patterns, opts, false)
+    }
+
+    // `fast` batches literals into the automaton and lets patterns run on
+    // the Pike VM
+    fn build(patterns: &[String], opts: &ParseOptions, fast: bool) -> Result<RegexSet, RegexError> {
+        let mut seen = HashSet::new();
+        let mut regexes = Vec::new();
+        let mut literals = Vec::new();
+        let mut literal_patterns = Vec::new();
+        for (index, pattern) in patterns.iter().enumerate() {
+            // A repeat can never beat its first occurrence on a tie
+            if !seen.insert(pattern.as_str()) {
+                continue;
+            }
+            let mut re = Regex::new(pattern, opts)?;
+            if !fast {
+                re = re.on_backtracker();
+            }
+            // With --graphemes a literal mustn't end inside a cluster, which
+            // only the matcher checks
+            match re.as_literals() {
+                Some(alternatives) if fast && !opts.graphemes => {
+                    for literal in alternatives {
+                        literals.push(literal);
+                        literal_patterns.push(index);
+                    }
+                }
+                _ => regexes.push((index, re)),
+            }
+        }
+        let prefilter = if fast && !regexes.is_empty() {
+            let prefixes: Option<Vec<&[String]>> = regexes
+                .iter()
+                .map(|(_, re)| Some(re.prefilter()?.prefixes()))
+                .collect();
+            prefixes.and_then(|prefixes| {
+                Prefilter::from_prefixes(
+                    prefixes
+                        .concat()
+                        .into_iter()
+                        .chain(literals.clone())
+                        .collect(),
+                )
+            })
+        } else {
+            None
+        };
+        let literals = (!literals.is_empty()).then(|| Literals {
+            automaton: AhoCorasick::builder()
+                .match_kind(MatchKind::LeftmostLongest)
+                .start_kind(StartKind::Both)
+                .build(&literals)
+                .expect("literal automaton fits in memory"),
+            patterns: literal_patterns,
+        });
+        Ok(RegexSet {
+            regexes,
+            literals,
+            prefilter,
+            len: patterns.len(),
+            graphemes: opts.graphemes && !opts.ascii,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Index of the group called `name` in pattern `pattern`, counting from 1
+    // as Regex::group_index does. Literal patterns have no groups.
+    pub fn group_index(&self, pattern: usize, name: &str) -> Option<usize> {
+        let (_, re) = self.regexes.iter().find(|(index, _)| *index == pattern)?;
+        re.group_index(name)
+    }
+
+    // Whether every pattern went into the literal automaton
+    pub fn is_literal_only(&self) -> bool {
+        self.regexes.is_empty() && self.literals.is_some()
+    }
+
+    // Whether find_from can jump straight to the next match: matches aren't
+    // held to grapheme cluster boundaries, which only stepping a cluster at
+    // a time keeps
+    pub fn can_find_from(&self) -> bool {
+        !self.graphemes
+    }
+
+    // The leftmost match starting at or after byte `pos`, with its start.
+    // Among matches there, the longest wins, then the earlier pattern, as in
+    // find_at. Only for sets where can_find_from holds.
+    pub fn find_from<'a>(&self, haystack: &'a str, pos: usize) -> Option<(usize, SetMatch<'a>)> {
+        let mut best = self.literals.as_ref().and_then(|literals| {
+            let m = literals.automaton.find(Input::new(haystack).range(pos..))?;
+            let found = SetMatch {
+                pattern: literals.patterns[m.pattern().as_usize()],
+                text: &haystack[m.range()],
+                groups: Vec::new(),
+            };
+            Some((m.start(), found))
+        });
+        for (pattern, re) in &self.regexes {
+            if let Some((start, m, groups)) = re.find_from(haystack, pos)
+                && best.as_ref().is_none_or(|(b_start, b)| {
+                    start < *b_start
+                        || (start == *b_start
+                            && (m.len() > b.text.len()
+                                || (m.len() == b.text.len() && *pattern < b.pattern)))
+                })
+            {
+                let found = SetMatch {
+                    pattern: *pattern,
+                    text: m,
+                    groups,
+                };
+                best = Some((start, found));
+            }
+        }
+        best
+    }
+
+    // The first byte at or after `pos` where a match could start: `pos`
+    // itself unless every pattern starts with a literal
+    pub fn next_candidate(&self, haystack: &str, pos: usize) -> Option<usize> {
+        match &self.prefilter {
+            Some(prefilter) => prefilter.find(haystack, pos),
+            None => Some(pos),
+        }
+    }
+
+    // The longest match among all patterns starting exactly at byte `pos` of
+    // `haystack`. On a tie the earlier pattern wins.
+    pub fn find_at<'a>(&self, haystack: &'a str, pos: usize) -> Option<SetMatch<'a>> {
+        let mut best = self
+            .literals
+            .as_ref()
+            .and_then(|l| l.find_at(haystack, pos));
+        for (pattern, re) in &self.regexes {
+            if let Some((m, groups)) = re.captures_at(haystack, pos)
+                && best.as_ref().is_none_or(|b| {
+                    m.len() > b.text.len() || (m.len() == b.text.len() && *pattern < b.pattern)
+                })
+            {
+                best = Some(SetMatch {
+                    pattern: *pattern,
+                    text: m,
+                    groups,
+                });
+            }
+        }
+        best
+    }
+}
+
+impl Literals {
+    fn find_at<'a>(&self, haystack: &'a str, pos: usize) -> Option<SetMatch<'a>> {
+        let input = Input::new(haystack).range(pos..).anchored(Anchored::Yes);
+        let m = self.automaton.find(input)?;
+        Some(SetMatch {
+            pattern: self.patterns[m.pattern().as_usize()],
+            text: &haystack[m.range()],
+            groups: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RegexSet;
+    use crate::regex::ParseOptions;
+
+    #[test]
+    fn reports_longest_match_and_its_pattern() {
+        let set = RegexSet::new(
+            &["ab".to_string(), "abc".to_string(), "x".to_string()],
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        let m = set.find_at("abcd", 0).unwrap();
+        assert_eq!((m.pattern, m.text), (1, "abc"));
+        assert!(set.find_at("zzz", 0).is_none());
+    }
+
+    #[test]
+    fn literal_only_sets_jump_to_the_next_match() {
+        let set = RegexSet::new(
+            &["needle".to_string(), "need".to_string()],
+            &ParseOptions::default(),
+        )
+        .unwrap();
+        assert!(set.is_literal_only());
+        let (start, m) = set.find_from("hay needle need", 1).unwrap();
+        assert_eq!((start, m.pattern, m.text), (4, 0, "needle"));
+        let (start, m) = set.find_from("hay needle need", 5).unwrap();
+        assert_eq!((start, m.pattern), (11, 1));
+        assert!(set.find_from("hay", 0).is_none());
+        assert!(
+            !RegexSet::new(&["ne+dle".to_string()], &ParseOptions::default())
+                .unwrap()
+                .is_literal_only()
+        );
+    }
+
+    #[test]
+    fn sets_jump_to_the_next_match() {
+        let patterns = [r"\d+", "ab", r"a\w"].map(String::from);
+        let set = RegexSet::new(&patterns, &ParseOptions::default()).unwrap();
+        assert!(set.can_find_from() && !set.is_literal_only());
+        let (start, m) = set.find_from("x abc 42", 0).unwrap();
+        // `ab` and `a\w` tie at the same start; the earlier pattern wins
+        assert_eq!((start, m.pattern, m.text), (2, 1, "ab"));
+        let (start, m) = set.find_from("x abc 42", 3).unwrap();
+        assert_eq!((start, m.pattern, m.text), (6, 0, "42"));
+        assert!(set.find_from("xyz", 0).is_none());
+
+        // The backtracker jumps ahead too; only grapheme mode steps through
+        let backref = RegexSet::new(&[r"(a)\1".to_string()], &ParseOptions::default()).unwrap();
+        assert!(backref.can_find_from());
+        let (start, m) = backref.find_from("xab aa", 0).unwrap();
+        assert_eq!((start, m.text), (4, "aa"));
+        let graphemes = ParseOptions {
+            graphemes: true,
+            ..Default::default()
+        };
+        let reference = RegexSet::backtracking(&patterns, &graphemes).unwrap();
+        assert!(!reference.can_find_from());
+    }
+
+    #[test]
+    fn alternations_of_literals_join_the_automaton() {
+        let patterns = ["(?:warn|fatal)", "error"].map(String::from);
+        let set = RegexSet::new(&patterns, &ParseOptions::default()).unwrap();
+        assert!(set.is_literal_only());
+        let (start, m) = set.find_from("ok fatal error", 0).unwrap();
+        assert_eq!((start, m.pattern, m.text), (3, 0, "fatal"));
+        // `err` is a prefix of `error`, and the pattern prefers it
+        let set = RegexSet::new(&["(?:err|error)".to_string()], &ParseOptions::default()).unwrap();
+        assert!(!set.is_literal_only());
+        assert_eq!(set.find_at("error", 0).unwrap().text, "err");
+    }
+
+    #[test]
+    fn prefixes_of_every_pattern_locate_candidates() {
+        let patterns = [r"(\w)\1", r"id=\d+", "(warn|fatal):"].map(String::from);
+        let set = RegexSet::new(&patterns, &ParseOptions::default()).unwrap();
+        assert_eq!(set.next_candidate("ok ok fatal: id=1", 0), Some(0));
+
+        let patterns = [r"x(\w)\1", r"id=\d+", "(warn|fatal):", "ok"].map(String::from);
+        let set = RegexSet::new(&patterns, &ParseOptions::default()).unwrap();
+        assert_eq!(set.next_candidate("a fatal: id=1", 0), Some(2));
+        assert_eq!(set.next_candidate("a fatal: id=1", 3), Some(9));
+        assert_eq!(set.next_candidate("a fatal: id=1", 10), None);
+        assert_eq!(set.next_candidate("a ok", 0), Some(2));
+    }
+
+    #[test]
+    fn whole_word_literals_keep_their_boundaries() {
+        let opts = ParseOptions {
+            whole_word: true,
+            ..ParseOptions::default()
+        };
+        let set = RegexSet::new(&["foo".to_string()], &opts).unwrap();
+        // The automaton can't check word edges, so this can't jump ahead
+        assert!(!set.is_literal_only());
+        assert!(set.find_at("foobar foo", 0).is_none());
+        assert!(set.find_at("xfoo", 1).is_none());
+        assert_eq!(set.find_at("foobar foo", 7).unwrap().text, "foo");
+    }
+
+    #[test]
+    fn literals_and_regexes_rank_together() {
+        let patterns = ["ab", "a.c", "ab", "abc", r"\w+"].map(String::from);
+        let set = RegexSet::new(&patterns, &ParseOptions::default()).unwrap();
+        assert_eq!(set.len(), 5);
+        let m = set.find_at("xabcd", 1).unwrap();
+        assert_eq!((m.pattern, m.text), (4, "abcd"));
+        // `a.c` and `abc` tie; the earlier pattern wins
+        let m = set.find_at("abc-", 0).unwrap();
+        assert_eq!((m.pattern, m.text), (1, "abc"));
+        let m = set.find_at("ab-", 0).unwrap();
+        assert_eq!((m.pattern, m.text), (0, "ab"));
+    }
+}
@@ -0,0 +1,232 @@
+use std::borrow::Cow;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::ops::Range;
+
+use crate::regex::Regex;
+
+// Rewrites matches of a pattern in a stream, e.g. to scrub secrets from logs.
+// Input is handled a line at a time, so memory is bounded by the longest line
+// rather than the size of the stream. The replacement is a Template.
+pub struct Replacer {
+    regex: Regex,
+    replacement: Template,
+}
+
+impl Replacer {
+    pub fn new(regex: Regex, replacement: &str) -> Replacer {
+        let replacement = Template::parse(replacement).resolve(|name| regex.group_index(name));
+        Replacer { regex, replacement }
+    }
+
+    // Copies `reader` to `writer` with every match replaced. Lines that aren't
+    // valid UTF-8 are passed through untouched. Returns the number of
+    // replacements made.
+    pub fn stream<R: Read, W: Write>(&self, reader: R, mut writer: W) -> io::Result<usize> {
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        let mut replaced = 0;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let (body, newline) = match line.strip_suffix(b"\n") {
+                Some(body) => (body, &b"\n"[..]),
+                None => (&line[..], &b""[..]),
+            };
+            match std::str::from_utf8(body) {
+                Ok(text) => {
+                    let (out, n) = self.replace_line(text);
+                    writer.write_all(out.as_bytes())?;
+                    replaced += n;
+                }
+                Err(_) => writer.write_all(body)?,
+            }
+            writer.write_all(newline)?;
+        }
+        writer.flush()?;
+        Ok(replaced)
+    }
+
+    // `line` with every non-overlapping match replaced, and how many there
+    // were. Each match is the leftmost one after the last, found the way
+    // Searcher::find_iter finds them.
+    pub fn replace_line<'a>(&self, line: &'a str) -> (Cow<'a, str>, usize) {
+        let mut out = String::new();
+        let mut copied = 0;
+        let mut count = 0;
+        let mut pos = 0;
+        while let Some((start, m, groups)) = self.regex.find_from(line, pos) {
+            // No empty match right where the previous match ended
+            if m.is_empty() && count > 0 && start == copied {
+                match line[start..].chars().next() {
+                    Some(c) => pos = start + c.len_utf8(),
+                    None => break,
+                }
+                continue;
+            }
+            out.push_str(&line[copied..start]);
+            self.replacement.expand(&mut out, m, &groups);
+            count += 1;
+            copied = start + m.len();
+            // An empty match still has to move past the next char
+            pos = match line[copied..].chars().next() {
+                Some(c) if m.is_empty() => copied + c.len_utf8(),
+                None if m.is_empty() => break,
+                _ => copied,
+            };
+        }
+        if count == 0 {
+            return (Cow::Borrowed(line), 0);
+        }
+        out.push_str(&line[copied..]);
+        (Cow::Owned(out), count)
+    }
+}
+
+// Text to put in place of a match, as in ripgrep: `$N` or `${N}` inserts
+// group N (`$0` is the whole match), `$name` or `${name}` a named group and
+// `$$` a literal `$`. A name runs as far as letters, digits and `_` go, so
+// `${1}a` is needed for group 1 then `a`. Groups that didn't take part, or
+// don't exist, insert nothing.
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Group(usize),
+    Name(String), // until resolved
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Template {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = template;
+        while let Some(dollar) = rest.find('$') {
+            literal.push_str(&rest[..dollar]);
+            let after = &rest[dollar + 1..];
+            match reference(after) {
+                Some((reference, len)) => {
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(match reference.parse() {
+                        Ok(n) => Part::Group(n),
+                        Err(_) => Part::Name(reference.to_string()),
+                    });
+                    rest = &after[len..];
+                }
+                // `$$`, or a `$` that starts no reference, is kept as text
+                None => {
+                    literal.push('$');
+                    rest = after.strip_prefix('$').unwrap_or(after);
+                }
+            }
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Template { parts }
+    }
+
+    // With each name replaced by the index `group_index` gives it, counting
+    // from 1; unknown names are dropped
+    pub fn resolve(&self, group_index: impl Fn(&str) -> Option<usize>) -> Template {
+        let parts = self
+            .parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Name(name) => group_index(name).map(Part::Group),
+                part => Some(part.clone()),
+            })
+            .collect();
+        Template { parts }
+    }
+
+    // Appends the expansion for the match `m`, with `groups` relative to it
+    pub fn expand(&self, out: &mut String, m: &str, groups: &[Option<Range<usize>>]) {
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => out.push_str(text),
+                Part::Group(0) => out.push_str(m),
+                Part::Group(n) => {
+                    if let Some(Some(span)) = groups.get(n - 1) {
+                        out.push_str(&m[span.clone()]);
+                    }
+                }
+                Part::Name(_) => {}
+            }
+        }
+    }
+}
+
+// The group number or name at the start of `s`, just after a `$`, and how
+// many bytes it takes up
+fn reference(s: &str) -> Option<(&str, usize)> {
+    if let Some(braced) = s.strip_prefix('{') {
+        let end = braced.find('}')?;
+        return Some((&braced[..end], end + 2));
+    }
+    let end = s
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(s.len());
+    (end > 0).then(|| (&s[..end], end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Replacer, Template};
+    use crate::regex::RegexBuilder;
+
+    #[test]
+    fn streams_replacements_line_by_line() {
+        let re = RegexBuilder::new(r"token=(\w+)").build().unwrap();
+        let replacer = Replacer::new(re, "token=<$1:redacted> $$");
+        let input = b"a token=abc b\nnone\n\xff token=x\ntoken=yz";
+        let mut out = Vec::new();
+        let n = replacer.stream(&input[..], &mut out).unwrap();
+        assert_eq!(n, 2);
+        assert_eq!(
+            out,
+            b"a token=<abc:redacted> $ b\nnone\n\xff token=x\ntoken=<yz:redacted> $"
+        );
+    }
+
+    #[test]
+    fn templates_insert_numbered_and_named_groups() {
+        let re = RegexBuilder::new(r"(?P<key>\w+)=(\d+)").build().unwrap();
+        let (m, groups) = re.captures_at("id=42", 0).unwrap();
+        let expand = |template: &str| {
+            let template = Template::parse(template).resolve(|name| re.group_index(name));
+            let mut out = String::new();
+            template.expand(&mut out, m, &groups);
+            out
+        };
+        assert_eq!(expand("$2"), "42");
+        assert_eq!(expand("${key}: ${2}0 $$1"), "id: 420 $1");
+        assert_eq!(expand("[$0] $key"), "[id=42] id");
+        // `$2x` names a group, which doesn't exist
+        assert_eq!(expand("$2x|$9|$ |${"), "||$ |${");
+    }
+
+    #[test]
+    fn empty_matches_advance() {
+        let replacer = Replacer::new(RegexBuilder::new("x*").build().unwrap(), "-");
+        assert_eq!(replacer.replace_line("abx").0, "-a-b-");
+    }
+
+    #[test]
+    fn long_lines_are_searched_once_not_from_every_position() {
+        let line = format!("{}helo", "x".repeat(200_000));
+        let re = RegexBuilder::new("hello").fuzzy(Some(1)).build().unwrap();
+        let started = std::time::Instant::now();
+        let (out, n) = Replacer::new(re, "<$0>").replace_line(&line);
+        assert_eq!((n, &out[200_000..]), (1, "<helo>"));
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+}
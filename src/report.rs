@@ -0,0 +1,182 @@
+use std::path::Path;
+
+use serde_json::{Value, json};
+
+use crate::cli::{Config, OutputFormat};
+use crate::output::display_path;
+use crate::printer::{MatchedLines, Printer};
+use crate::search::LineMatch;
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+// Machine-readable --format output. GitHub annotations are printed as they
+// are found; SARIF results are gathered and written in one go at the end.
+pub struct Report<'c> {
+    cfg: &'c Config,
+    uri: String,
+    results: Vec<Value>,
+}
+
+impl<'c> Report<'c> {
+    pub fn new(cfg: &'c Config) -> Report<'c> {
+        Report {
+            cfg,
+            uri: String::new(),
+            results: Vec::new(),
+        }
+    }
+}
+
+impl Printer for Report<'_> {
+    fn begin(&mut self, path: Option<&Path>) {
+        self.uri = path.map_or_else(|| "<stdin>".to_string(), |f| display_path(f).into_owned());
+    }
+
+    // Results point at a single line, so a -U match is reported on the line
+    // it starts on
+    fn matched(&mut self, event: &MatchedLines) {
+        for m in event.matches {
+            let before = &event.text[..m.span.start];
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            let line_len = event.text[line_start..]
+                .find('\n')
+                .unwrap_or(event.text.len() - line_start);
+            let line = &event.text[line_start..line_start + line_len];
+            let m = LineMatch {
+                line_number: event.line_number + before.matches('\n').count(),
+                line,
+                span: m.span.start - line_start..(m.span.end - line_start).min(line.len()),
+                pattern: m.pattern,
+            };
+            match self.cfg.format {
+                OutputFormat::Sarif => self.results.push(sarif_result(&self.uri, &m, self.cfg)),
+                OutputFormat::Github => println!("{}", github_annotation(&self.uri, &m, self.cfg)),
+                OutputFormat::Text | OutputFormat::Json => {
+                    unreachable!("not a report format")
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        if self.cfg.format == OutputFormat::Sarif {
+            let log = sarif_log(&self.results, self.cfg);
+            println!("{}", serde_json::to_string_pretty(&log).unwrap());
+        }
+    }
+}
+
+// One rule per pattern, named by its label
+fn sarif_log(results: &[Value], cfg: &Config) -> Value {
+    let rules: Vec<Value> = cfg
+        .labels
+        .iter()
+        .zip(&cfg.patterns)
+        .map(|(label, pattern)| {
+            json!({
+                "id": label,
+                "shortDescription": { "text": format!("matches /{pattern}/") },
+            })
+        })
+        .collect();
+    json!({
+        "$schema": SARIF_SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "rust-grep",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "columnKind": "utf16CodeUnits",
+            "results": results,
+        }]
+    })
+}
+
+fn sarif_result(uri: &str, m: &LineMatch, cfg: &Config) -> Value {
+    let matched = &m.line[m.span.clone()];
+    json!({
+        "ruleId": cfg.labels[m.pattern],
+        "ruleIndex": m.pattern,
+        "level": "warning",
+        "message": { "text": format!("Found `{matched}`") },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": { "uri": uri },
+                "region": {
+                    "startLine": m.line_number,
+                    "startColumn": utf16_column(m.line, m.span.start),
+                    "endColumn": utf16_column(m.line, m.span.end),
+                    "snippet": { "text": m.line },
+                }
+            }
+        }]
+    })
+}
+
+// `::warning file=F,line=N,col=C,endColumn=E,title=T::MESSAGE`, a GitHub
+// Actions workflow command that shows up inline on pull requests
+fn github_annotation(uri: &str, m: &LineMatch, cfg: &Config) -> String {
+    let matched = &m.line[m.span.clone()];
+    format!(
+        "::warning file={},line={},col={},endColumn={},title={}::{}",
+        escape_property(uri),
+        m.line_number,
+        m.line[..m.span.start].chars().count() + 1,
+        m.line[..m.span.end].chars().count() + 1,
+        escape_property(&format!("rust-grep pattern {}", cfg.labels[m.pattern])),
+        escape_data(matched),
+    )
+}
+
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+// Property values additionally can't contain the `:` and `,` delimiters
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+// 1-based column of a byte offset, counted in UTF-16 code units as SARIF
+// expects by default
+fn utf16_column(line: &str, byte: usize) -> usize {
+    line[..byte].encode_utf16().count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{github_annotation, utf16_column};
+    use crate::cli::Config;
+    use crate::search::LineMatch;
+
+    #[test]
+    fn github_annotation_escapes_delimiters() {
+        let cfg = Config {
+            labels: vec!["secrets".to_string()],
+            ..Default::default()
+        };
+        let m = LineMatch {
+            line_number: 3,
+            line: "é key=100%",
+            span: 3..11,
+            pattern: 0,
+        };
+        assert_eq!(
+            github_annotation("a,b:c.txt", &m, &cfg),
+            "::warning file=a%2Cb%3Ac.txt,line=3,col=3,endColumn=11,\
+             title=rust-grep pattern secrets::key=100%25"
+        );
+    }
+
+    #[test]
+    fn sarif_columns_count_utf16_units() {
+        assert_eq!(utf16_column("😀x", 4), 3);
+        assert_eq!(utf16_column("é x", 3), 3);
+    }
+}
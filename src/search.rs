@@ -1,73 +1,1499 @@
-use crate::output::maybe_colorize;
-use crate::regex::{Token, match_pattern};
-
-pub fn process_input(
-    content: &str,
-    tokens: &[Token],
-    filename: Option<&str>,
-    use_o: bool,
-    use_color: bool,
-    global_matched: &mut bool,
-    is_anchored: bool,
-    show_filename: bool,
-) {
-    let prefix = if show_filename {
-        filename.map(|f| format!("{f}:")).unwrap_or_default()
-    } else {
-        String::new()
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::cancel::CancelToken;
+use crate::cli::{Config, OutputFormat};
+use crate::encoding::decode_auto;
+use crate::mmap::Mmap;
+use crate::printer::{MatchSpan, MatchedLines, Printer};
+use crate::reader::{peek, read_all, read_bytes, read_lines_until};
+use crate::regex::{ParseOptions, Regex, RegexSet};
+use crate::stats::Stats;
+
+// Inputs with a NUL byte this near their start are taken to be binary, as in
+// grep
+const BINARY_SNIFF_LEN: usize = 8 * 1024;
+
+// Configures how inputs are walked for matches, e.g.
+// `SearcherBuilder::new().multiline(true).max_matches_per_line(Some(3)).build()`.
+// Patterns and output are supplied per search, so one Searcher can be reused
+// across inputs and queries.
+#[derive(Debug, Clone)]
+pub struct SearcherBuilder {
+    searcher: Searcher,
+}
+
+impl Default for SearcherBuilder {
+    fn default() -> SearcherBuilder {
+        SearcherBuilder::new()
+    }
+}
+
+impl SearcherBuilder {
+    pub fn new() -> SearcherBuilder {
+        SearcherBuilder {
+            searcher: Searcher {
+                multiline: false,
+                invert_match: false,
+                invert_segments: false,
+                line_terminator: b'\n',
+                max_matches_per_line: None,
+                max_count: None,
+                binary: false,
+                skip_binary: false,
+                auto_encoding: false,
+                mmap: true,
+                graphemes: false,
+                ascii: false,
+                join_continuations: None,
+                after_context: None,
+                before_context: None,
+                after_context_until: None,
+                before_context_until: None,
+                cancel: CancelToken::default(),
+            },
+        }
+    }
+
+    // -U: match across lines; printers get every line a match touches
+    pub fn multiline(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.searcher.multiline = yes;
+        self
+    }
+
+    // Report the lines that don't match instead
+    pub fn invert_match(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.searcher.invert_match = yes;
+        self
+    }
+
+    // With invert_match, report the parts of each line no match covers
+    // rather than whole lines without a match (-o -v). Not for multiline.
+    pub fn invert_segments(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.searcher.invert_segments = yes;
+        self
+    }
+
+    // The byte lines end with outside of multiline mode, e.g. `b'\0'` for
+    // NUL-separated file lists. Must be ASCII.
+    pub fn line_terminator(&mut self, byte: u8) -> &mut SearcherBuilder {
+        assert!(byte.is_ascii(), "line terminator must be ASCII");
+        self.searcher.line_terminator = byte;
+        self
+    }
+
+    pub fn max_matches_per_line(&mut self, max: Option<usize>) -> &mut SearcherBuilder {
+        self.searcher.max_matches_per_line = max;
+        self
+    }
+
+    // -m: stop reading an input after this many selected lines. Context
+    // after the last one is still printed.
+    pub fn max_count(&mut self, max: Option<usize>) -> &mut SearcherBuilder {
+        self.searcher.max_count = max;
+        self
+    }
+
+    // Search inputs that aren't valid UTF-8, reading invalid bytes as U+FFFD,
+    // and never treat one as binary (-a)
+    pub fn binary(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.searcher.binary = yes;
+        self
+    }
+
+    // Skip inputs that look binary instead of reporting whether they match
+    // (-I). Ignored in binary mode.
+    pub fn skip_binary(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.searcher.skip_binary = yes;
+        self
+    }
+
+    // Detect each input's encoding (BOM, UTF-16, UTF-8 or Latin-1) instead
+    // of requiring UTF-8
+    pub fn auto_encoding(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.searcher.auto_encoding = yes;
+        self
+    }
+
+    // Search large files through a memory map instead of reading them onto
+    // the heap (on by default; --no-mmap turns it off)
+    pub fn mmap(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.searcher.mmap = yes;
+        self
+    }
+
+    // Never start a match inside a grapheme cluster
+    pub fn graphemes(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.searcher.graphemes = yes;
+        self
+    }
+
+    // Step over ASCII bytes without decoding (--no-unicode)
+    pub fn ascii(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.searcher.ascii = yes;
+        self
+    }
+
+    // Merge continuation lines into the record before them, so a record is
+    // matched and printed as one unit. A line joins the previous one when the
+    // pattern matches at its start (`^[ \t]`); a match running to the end of
+    // a line (`\\$`) pulls the next line in. Not for multiline.
+    pub fn join_continuations(&mut self, pattern: Option<Regex>) -> &mut SearcherBuilder {
+        self.searcher.join_continuations = pattern;
+        self
+    }
+
+    // -A: follow each reported line with up to this many lines of context.
+    // With after_context_until too, context stops at whichever comes first.
+    // Not for multiline.
+    pub fn after_context(&mut self, lines: Option<usize>) -> &mut SearcherBuilder {
+        self.searcher.after_context = lines;
+        self
+    }
+
+    // -B: precede each reported line with up to this many lines of context
+    // not already printed. Not for multiline.
+    pub fn before_context(&mut self, lines: Option<usize>) -> &mut SearcherBuilder {
+        self.searcher.before_context = lines;
+        self
+    }
+
+    // Follow each reported line with context up to and including the next
+    // line this matches, or to the end of the input. Not for multiline.
+    pub fn after_context_until(&mut self, delimiter: Option<Regex>) -> &mut SearcherBuilder {
+        self.searcher.after_context_until = delimiter;
+        self
+    }
+
+    // Precede each reported line with context back to and including the
+    // nearest line this matches, or to the start of the input. A reported
+    // line that matches it gets no context. Not for multiline.
+    pub fn before_context_until(&mut self, delimiter: Option<Regex>) -> &mut SearcherBuilder {
+        self.searcher.before_context_until = delimiter;
+        self
+    }
+
+    pub fn cancel(&mut self, token: CancelToken) -> &mut SearcherBuilder {
+        self.searcher.cancel = token;
+        self
+    }
+
+    pub fn build(&self) -> Searcher {
+        self.searcher.clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Searcher {
+    multiline: bool,
+    invert_match: bool,
+    invert_segments: bool,
+    line_terminator: u8,
+    max_matches_per_line: Option<usize>,
+    max_count: Option<usize>,
+    binary: bool,
+    skip_binary: bool,
+    auto_encoding: bool,
+    mmap: bool,
+    graphemes: bool,
+    ascii: bool,
+    join_continuations: Option<Regex>,
+    after_context: Option<usize>,
+    before_context: Option<usize>,
+    after_context_until: Option<Regex>,
+    before_context_until: Option<Regex>,
+    cancel: CancelToken,
+}
+
+// The searcher for the options in `cfg`
+pub fn for_config(cfg: &Config) -> Searcher {
+    SearcherBuilder::new()
+        .multiline(cfg.multiline)
+        .invert_match(cfg.invert_match)
+        .invert_segments(cfg.use_o)
+        .max_matches_per_line(if counts_lines_only(cfg) {
+            Some(1)
+        } else {
+            cfg.max_matches_per_line
+        })
+        .max_count(if lists_inputs(cfg) {
+            Some(cfg.max_count.unwrap_or(1).min(1))
+        } else {
+            cfg.max_count
+        })
+        .binary(cfg.text)
+        .skip_binary(cfg.skip_binary)
+        .auto_encoding(cfg.auto_encoding)
+        .mmap(!cfg.no_mmap)
+        .graphemes(cfg.graphemes)
+        .ascii(cfg.no_unicode)
+        .join_continuations(line_regex(cfg, &cfg.join_continuations))
+        .after_context(cfg.after_context)
+        .before_context(cfg.before_context)
+        .after_context_until(line_regex(cfg, &cfg.after_context_until))
+        .before_context_until(line_regex(cfg, &cfg.before_context_until))
+        .cancel(cfg.cancel.clone())
+        .build()
+}
+
+// -c prints how many lines matched, so a line's first match settles it and
+// the rest needn't be found, unless something else counts every match or a
+// -U match past the first could reach more lines
+fn counts_lines_only(cfg: &Config) -> bool {
+    cfg.count
+        && cfg.format == OutputFormat::Text
+        && cfg.stats.is_none()
+        && cfg.group_by.is_none()
+        && cfg.dir_summary.is_none()
+        && !cfg.multiline
+}
+
+// -l, -L and -q only say whether an input matches, so reading it stops at
+// the first selected line
+fn lists_inputs(cfg: &Config) -> bool {
+    cfg.quiet || cfg.files_with_matches || cfg.files_without_match
+}
+
+// A pattern matched against single lines rather than searched for, e.g.
+// --join-continuations. The CLI reports malformed ones before searching.
+fn line_regex(cfg: &Config, pattern: &Option<String>) -> Option<Regex> {
+    let opts = ParseOptions {
+        ascii: cfg.no_unicode,
+        nesting_limit: cfg.max_nesting,
+        ..Default::default()
     };
+    pattern.as_deref().and_then(|p| Regex::new(p, &opts).ok())
+}
 
-    for line in content.lines() {
-        let mut current_search_text = line;
-        let mut line_buffer = String::new();
-        let mut line_has_match = false;
-        let mut last_match_end_in_line = 0;
-
-        loop {
-            if let Some(matched_slice) = match_pattern(current_search_text, tokens) {
-                *global_matched = true;
-                line_has_match = true;
-
-                let match_text = maybe_colorize(matched_slice, use_color);
-
-                if use_o {
-                    println!("{prefix}{match_text}");
-                } else {
-                    let offset_in_line = line.len() - current_search_text.len();
-                    line_buffer.push_str(&line[last_match_end_in_line..offset_in_line]);
-                    line_buffer.push_str(&match_text);
-                    last_match_end_in_line = offset_in_line + matched_slice.len();
-                }
+// How an input is searched, going by its first block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Input {
+    Text,
+    Binary, // read lossily; printers showing lines just say that it matched
+    Skip,   // -I
+}
+
+// Where a piece of an input starts within the whole of it, for inputs
+// searched a piece at a time (--watch): the lines and bytes before it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Position {
+    pub lines: usize,
+    pub offset: usize,
+}
+
+// A match found by find_matches, for callers that format results themselves
+#[derive(Debug, Clone)]
+pub struct LineMatch<'a> {
+    pub line_number: usize,
+    pub line: &'a str,
+    pub span: Range<usize>, // relative to the line
+    pub pattern: usize,
+}
+
+impl Searcher {
+    // All of `reader` as text, decoded the way this searcher reads inputs
+    pub fn read<R: Read>(&self, reader: R) -> io::Result<String> {
+        self.read_as(reader, Input::Text)
+    }
+
+    fn read_as<R: Read>(&self, reader: R, input: Input) -> io::Result<String> {
+        if self.auto_encoding {
+            return Ok(decode_auto(&read_bytes(reader)?));
+        }
+        read_all(reader, self.lossy(input))
+    }
 
-                if is_anchored {
+    // A NUL byte near the start makes an input binary, unless -a reads
+    // everything as text. UTF-16 is full of them, so --encoding=auto
+    // doesn't sniff either.
+    pub(crate) fn sniff(&self, block: &[u8]) -> Input {
+        let block = &block[..block.len().min(BINARY_SNIFF_LEN)];
+        if self.binary || self.auto_encoding || !block.contains(&0) {
+            Input::Text
+        } else if self.skip_binary {
+            Input::Skip
+        } else {
+            Input::Binary
+        }
+    }
+
+    // Whether invalid UTF-8 in the input reads as U+FFFD rather than failing
+    fn lossy(&self, input: Input) -> bool {
+        self.binary || input == Input::Binary
+    }
+
+    // A multi-line match can span any number of lines, so -U needs the whole
+    // input before searching, as do sniffing the encoding, joining records
+    // and splitting at another line terminator
+    fn needs_whole_input(&self) -> bool {
+        self.multiline
+            || self.line_terminator != b'\n'
+            || self.auto_encoding
+            || self.join_continuations.is_some()
+    }
+
+    // Searches everything `reader` produces. Lines are read and reported a
+    // batch at a time, so memory stays bounded however long the input is and
+    // output keeps up with one that's still growing (`tail -f | rust-grep`).
+    // An input that can't be decoded fails at the batch where that happens,
+    // after the lines before it were reported.
+    pub fn search_reader<R: Read>(
+        &self,
+        reader: R,
+        regexes: &RegexSet,
+        filename: Option<&Path>,
+        printer: &mut dyn Printer,
+        stats: &mut Stats,
+    ) -> io::Result<()> {
+        let (input, reader) = peek(reader, BINARY_SNIFF_LEN, |block| self.sniff(block))?;
+        if input == Input::Skip {
+            stats.files_skipped += 1;
+            return Ok(());
+        }
+        if self.needs_whole_input() {
+            let content = self.read_as(reader, input)?;
+            self.search_input(&content, input, regexes, filename, printer, stats);
+            return Ok(());
+        }
+        stats.files_searched += 1;
+        let lines_before = stats.matched_lines;
+        printer.begin(filename);
+        if input == Input::Binary {
+            printer.binary(filename);
+        }
+        let mut state = LineState::new(self);
+        let read = read_lines_until(reader, self.lossy(input), |batch| {
+            stats.bytes_searched += batch.len();
+            self.search_batch(batch, &mut state, regexes, printer, stats)
+        });
+        printer.end();
+        if stats.matched_lines > lines_before {
+            stats.files_matched += 1;
+        }
+        read.map(|_| ())
+    }
+
+    // Searches the file at `path`. A large regular file is mapped rather
+    // than read, unless mmap is off; others are streamed.
+    pub fn search_path(
+        &self,
+        path: &Path,
+        regexes: &RegexSet,
+        printer: &mut dyn Printer,
+        stats: &mut Stats,
+    ) -> io::Result<()> {
+        let file = File::open(path)?;
+        if self.mmap
+            && let Some(map) = Mmap::map(&file)
+        {
+            return self.search_bytes(&map, regexes, Some(path), printer, stats);
+        }
+        self.search_reader(file, regexes, Some(path), printer, stats)
+    }
+
+    // Like `search` for raw bytes, which must be UTF-8 unless the searcher is
+    // in binary mode or they look binary. Lets callers reuse one read buffer
+    // across files.
+    pub fn search_bytes(
+        &self,
+        data: &[u8],
+        regexes: &RegexSet,
+        filename: Option<&Path>,
+        printer: &mut dyn Printer,
+        stats: &mut Stats,
+    ) -> io::Result<()> {
+        let input = self.sniff(data);
+        self.search_bytes_as(data, input, regexes, filename, printer, stats)
+    }
+
+    // search_bytes for the piece of an input that starts `at`, so line
+    // numbers and offsets count from there rather than from 1 and 0
+    pub fn search_bytes_at(
+        &self,
+        data: &[u8],
+        at: Position,
+        regexes: &RegexSet,
+        filename: Option<&Path>,
+        printer: &mut dyn Printer,
+        stats: &mut Stats,
+    ) -> io::Result<()> {
+        let mut shifted = Shifted { inner: printer, at };
+        self.search_bytes(data, regexes, filename, &mut shifted, stats)
+    }
+
+    // search_bytes for an input already sniffed, e.g. a chunk of one
+    pub(crate) fn search_bytes_as(
+        &self,
+        data: &[u8],
+        input: Input,
+        regexes: &RegexSet,
+        filename: Option<&Path>,
+        printer: &mut dyn Printer,
+        stats: &mut Stats,
+    ) -> io::Result<()> {
+        if input == Input::Skip {
+            stats.files_skipped += 1;
+            return Ok(());
+        }
+        if self.auto_encoding {
+            let content = decode_auto(data);
+            self.search(&content, regexes, filename, printer, stats);
+            return Ok(());
+        }
+        let content = match std::str::from_utf8(data) {
+            Ok(text) => Cow::Borrowed(text),
+            Err(_) if self.lossy(input) => String::from_utf8_lossy(data),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        self.search_input(&content, input, regexes, filename, printer, stats);
+        Ok(())
+    }
+
+    // Searches `content`, handing results to `printer`. `filename` is None
+    // for standard input.
+    pub fn search(
+        &self,
+        content: &str,
+        regexes: &RegexSet,
+        filename: Option<&Path>,
+        printer: &mut dyn Printer,
+        stats: &mut Stats,
+    ) {
+        self.search_input(content, Input::Text, regexes, filename, printer, stats);
+    }
+
+    fn search_input(
+        &self,
+        content: &str,
+        input: Input,
+        regexes: &RegexSet,
+        filename: Option<&Path>,
+        printer: &mut dyn Printer,
+        stats: &mut Stats,
+    ) {
+        stats.files_searched += 1;
+        stats.bytes_searched += content.len();
+        let lines_before = stats.matched_lines;
+
+        printer.begin(filename);
+        if input == Input::Binary {
+            printer.binary(filename);
+        }
+        if self.multiline {
+            self.search_multiline(content, regexes, printer, stats);
+        } else {
+            self.search_lines(content, regexes, printer, stats);
+        }
+        printer.end();
+
+        if stats.matched_lines > lines_before {
+            stats.files_matched += 1;
+        }
+    }
+
+    // -l and -q only need to know whether the input matches, so reading
+    // stops at the first matching line instead of running to EOF. Nothing is
+    // printed.
+    pub fn search_first<R: Read>(
+        &self,
+        reader: R,
+        regexes: &RegexSet,
+        stats: &mut Stats,
+    ) -> io::Result<bool> {
+        if self.max_count == Some(0) {
+            stats.files_searched += 1;
+            return Ok(false);
+        }
+        let (input, reader) = peek(reader, BINARY_SNIFF_LEN, |block| self.sniff(block))?;
+        if input == Input::Skip {
+            stats.files_skipped += 1;
+            return Ok(false);
+        }
+        stats.files_searched += 1;
+        // Some(pattern) for a match, Some(None) for a selected inverted line
+        let mut found: Option<Option<usize>> = None;
+        let mut check = |batch: &str| {
+            if self.cancel.is_cancelled() {
+                return true;
+            }
+            stats.bytes_searched += batch.len();
+            found = if self.multiline && !self.invert_match {
+                self.first_match(batch, regexes).map(Some)
+            } else if self.multiline {
+                self.unmatched_lines(batch, regexes).first().map(|_| None)
+            } else {
+                self.records(batch)
+                    .take_while(|_| !self.cancel.is_cancelled())
+                    .find_map(|(_, line)| match self.first_match(line, regexes) {
+                        Some(_) if self.invert_segments && self.invert_match => {
+                            (!self.unmatched_segments(line, regexes).is_empty()).then_some(None)
+                        }
+                        Some(_) if self.invert_match => None,
+                        None if self.invert_match => Some(None),
+                        found => found.map(Some),
+                    })
+            };
+            found.is_some()
+        };
+
+        if self.needs_whole_input() {
+            check(&self.read_as(reader, input)?);
+        } else {
+            read_lines_until(reader, self.lossy(input), check)?;
+        }
+
+        if let Some(pattern) = found {
+            if let Some(pattern) = pattern {
+                stats.matches += 1;
+                stats.per_pattern[pattern] += 1;
+            }
+            stats.matched_lines += 1;
+            stats.files_matched += 1;
+        }
+        Ok(found.is_some())
+    }
+
+    // Every match in `content` line by line, the way `search` walks it, but
+    // collected instead of printed. Used by `serve`.
+    pub fn find_matches<'a>(&self, content: &'a str, regexes: &RegexSet) -> Vec<LineMatch<'a>> {
+        let mut out = Vec::new();
+        for (line_number, line) in self.records(content) {
+            if self.cancel.is_cancelled() {
+                break;
+            }
+            for m in self.line_matches(line, regexes) {
+                out.push(LineMatch {
+                    line_number,
+                    line,
+                    span: m.span,
+                    pattern: m.pattern,
+                });
+            }
+        }
+        out
+    }
+
+    // `content` split at the line terminator. A final terminator doesn't
+    // start another line, and with `\n` a `\r` before it is dropped too.
+    fn lines<'a>(&self, content: &'a str) -> Box<dyn Iterator<Item = &'a str> + 'a> {
+        if self.line_terminator == b'\n' {
+            return Box::new(content.lines());
+        }
+        let terminator = self.line_terminator as char;
+        let content = content.strip_suffix(terminator).unwrap_or(content);
+        if content.is_empty() {
+            return Box::new(std::iter::empty());
+        }
+        Box::new(content.split(terminator))
+    }
+
+    // (line number, record) for each record in `content`: its lines, or
+    // with join_continuations runs of lines merged into one slice
+    fn records<'a>(&self, content: &'a str) -> Box<dyn Iterator<Item = (usize, &'a str)> + 'a> {
+        let numbered = self
+            .lines(content)
+            .enumerate()
+            .map(|(i, line)| (i + 1, line));
+        let Some(join) = self.join_continuations.clone() else {
+            return Box::new(numbered);
+        };
+        let mut lines = numbered.peekable();
+        Box::new(std::iter::from_fn(move || {
+            let (line_number, first) = lines.next()?;
+            let start = offset_in(content, first);
+            let mut end = start + first.len();
+            let mut pull_next = continues_onto_next(&join, first);
+            while let Some(&(_, next)) = lines.peek() {
+                if !pull_next && join.captures_at(next, 0).is_none() {
                     break;
                 }
+                pull_next = continues_onto_next(&join, next);
+                end = offset_in(content, next) + next.len();
+                lines.next();
+            }
+            Some((line_number, &content[start..end]))
+        }))
+    }
 
-                let advance_by = if matched_slice.is_empty() {
-                    1
-                } else {
-                    matched_slice.len()
-                };
-                if advance_by > current_search_text.len() {
-                    break;
+    // Leftmost, non-overlapping matches in `text`. Each search resumes where
+    // the previous match ended, and an empty match right at the end of the
+    // previous one is skipped, as in POSIX: `a*` over "baac" finds "" at 0,
+    // "aa" at 1 and "" at 4, but nothing at 3. Every kind of output (-o,
+    // counts, highlighting) walks matches through here.
+    pub fn find_iter<'s, 't>(&'s self, text: &'t str, regexes: &'s RegexSet) -> FindIter<'s, 't> {
+        FindIter {
+            searcher: self,
+            regexes,
+            text,
+            pos: 0,
+            last_end: None,
+        }
+    }
+
+    // Matches in `line`, up to --max-matches-per-line
+    fn line_matches(&self, line: &str, regexes: &RegexSet) -> Vec<MatchSpan> {
+        let matches = self.find_iter(line, regexes);
+        match self.max_matches_per_line {
+            Some(max) => matches.take(max).collect(),
+            None => matches.collect(),
+        }
+    }
+
+    // -o -v: the non-empty stretches of `line` between matches, as spans
+    // attributed to the first pattern
+    fn unmatched_segments(&self, line: &str, regexes: &RegexSet) -> Vec<MatchSpan> {
+        let mut segments = Vec::new();
+        let mut covered_to = 0;
+        let ends = self.find_iter(line, regexes).map(|m| m.span);
+        for span in ends.chain(std::iter::once(line.len()..line.len())) {
+            if span.start > covered_to {
+                segments.push(MatchSpan {
+                    span: covered_to..span.start,
+                    pattern: 0,
+                    groups: Vec::new(),
+                });
+            }
+            covered_to = covered_to.max(span.end);
+        }
+        segments
+    }
+
+    // Pattern index of the first match anywhere in `text`
+    fn first_match(&self, text: &str, regexes: &RegexSet) -> Option<usize> {
+        self.find_iter(text, regexes).next().map(|m| m.pattern)
+    }
+
+    // What `line` is reported with if it's selected: its matches, the
+    // stretches between them for -o -v, or none for an inverted line
+    fn selected(&self, line: &str, regexes: &RegexSet) -> Option<Vec<MatchSpan>> {
+        if self.invert_match && self.invert_segments {
+            let segments = self.unmatched_segments(line, regexes);
+            return (!segments.is_empty()).then_some(segments);
+        }
+        let matches = self.line_matches(line, regexes);
+        if matches.is_empty() != self.invert_match {
+            return None;
+        }
+        // An inverted line is reported without the matches it lacks
+        Some(if self.invert_match {
+            Vec::new()
+        } else {
+            matches
+        })
+    }
+
+    fn search_lines(
+        &self,
+        content: &str,
+        regexes: &RegexSet,
+        printer: &mut dyn Printer,
+        stats: &mut Stats,
+    ) {
+        let mut state = LineState::new(self);
+        self.search_batch(content, &mut state, regexes, printer, stats);
+    }
+
+    // Reports the selected lines of `batch`, which holds whole lines and
+    // follows the batches `state` has seen. Returns true once nothing more
+    // would be reported, so the rest of the input needn't be read.
+    fn search_batch(
+        &self,
+        batch: &str,
+        state: &mut LineState,
+        regexes: &RegexSet,
+        printer: &mut dyn Printer,
+        stats: &mut Stats,
+    ) -> bool {
+        let keep_before = self.before_context.is_some() || self.before_context_until.is_some();
+        let max_before = self.before_context.unwrap_or(usize::MAX);
+        let mut batch_lines = 0;
+        for (n, line) in self.records(batch) {
+            if self.cancel.is_cancelled() {
+                return true;
+            }
+            batch_lines = n;
+            let line_number = state.lines + n;
+            let offset = state.offset + offset_in(batch, line);
+            let event = |matches| MatchedLines {
+                line_number,
+                offset,
+                text: line,
+                matches,
+            };
+            // Past -m only the last line's after-context is left to print
+            if state.selected_left == 0 {
+                if state.after_left == 0 {
+                    return true;
+                }
+                printer.context(&event(&[]));
+                state.after_left -= 1;
+                if is_delimiter(&self.after_context_until, line) {
+                    state.after_left = 0;
                 }
-                current_search_text = &current_search_text[advance_by..];
+                continue;
+            }
+            let Some(matches) = self.selected(line, regexes) else {
+                if state.after_left > 0 {
+                    printer.context(&event(&[]));
+                    state.after_left -= 1;
+                    if is_delimiter(&self.after_context_until, line) {
+                        state.after_left = 0;
+                    }
+                } else if keep_before {
+                    if is_delimiter(&self.before_context_until, line) {
+                        state.pending.clear();
+                    }
+                    state
+                        .pending
+                        .push_back((line_number, offset, line.to_string()));
+                    if state.pending.len() > max_before {
+                        state.pending.pop_front();
+                    }
+                }
+                continue;
+            };
+            // A selected line that is itself a delimiter starts its own stanza
+            if !is_delimiter(&self.before_context_until, line) {
+                for (line_number, offset, text) in state.pending.drain(..) {
+                    printer.context(&MatchedLines {
+                        line_number,
+                        offset,
+                        text: &text,
+                        matches: &[],
+                    });
+                }
+            }
+            state.pending.clear();
+            if self.invert_segments && self.invert_match {
+                stats.matches += matches.len();
             } else {
-                if is_anchored {
-                    break;
+                count_matches(&matches, stats);
+            }
+            stats.matched_lines += 1;
+            state.selected_left -= 1;
+            printer.matched(&event(&matches));
+            state.after_left = match (self.after_context, &self.after_context_until) {
+                (Some(lines), _) => lines,
+                (None, Some(_)) => usize::MAX,
+                (None, None) => 0,
+            };
+        }
+        state.lines += batch_lines;
+        state.offset += batch.len();
+        state.selected_left == 0 && state.after_left == 0
+    }
+
+    // In grapheme mode never start a match in the middle of a cluster. With
+    // --no-unicode an ASCII byte is stepped over without decoding.
+    fn next_step(&self, text: &str) -> Option<usize> {
+        let &first = text.as_bytes().first()?;
+        if self.ascii && first.is_ascii() {
+            Some(1)
+        } else if self.graphemes && !self.ascii {
+            text.graphemes(true).next().map(str::len)
+        } else {
+            text.chars().next().map(char::len_utf8)
+        }
+    }
+
+    // Every match in the buffer, with spans relative to `content`
+    fn multiline_matches(&self, content: &str, regexes: &RegexSet) -> Vec<MatchSpan> {
+        let mut current_line_start = 0;
+        let mut line_matches = 0;
+        self.find_iter(content, regexes)
+            .filter(|m| {
+                if line_start(content, m.span.start) != current_line_start {
+                    current_line_start = line_start(content, m.span.start);
+                    line_matches = 0;
                 }
-                let mut chars = current_search_text.chars();
-                if chars.next().is_some() {
-                    current_search_text = chars.as_str();
-                } else {
+                // --max-matches-per-line counts matches starting on the same line
+                line_matches += 1;
+                self.max_matches_per_line
+                    .is_none_or(|max| line_matches <= max)
+            })
+            .collect()
+    }
+
+    // Runs of matches whose lines overlap, as (block start, block end, the
+    // range of `matches` in it)
+    fn blocks(content: &str, matches: &[MatchSpan]) -> Vec<(usize, usize, Range<usize>)> {
+        let mut blocks = Vec::new();
+        let mut i = 0;
+        while i < matches.len() {
+            let first = &matches[i].span;
+            let block_start = line_start(content, first.start);
+            let mut block_end = line_end(content, first.start, first.end);
+            let mut j = i + 1;
+            while j < matches.len() && matches[j].span.start <= block_end {
+                let span = &matches[j].span;
+                block_end = block_end.max(line_end(content, span.start, span.end));
+                j += 1;
+            }
+            blocks.push((block_start, block_end, i..j));
+            i = j;
+        }
+        blocks
+    }
+
+    // -U with invert: (line number, line) of every line no match touches
+    fn unmatched_lines<'a>(&self, content: &'a str, regexes: &RegexSet) -> Vec<(usize, &'a str)> {
+        let matches = self.multiline_matches(content, regexes);
+        let blocks = Searcher::blocks(content, &matches);
+        content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| {
+                let start = offset_in(content, line);
+                !blocks
+                    .iter()
+                    .any(|&(block_start, block_end, _)| start >= block_start && start <= block_end)
+            })
+            .map(|(idx, line)| (idx + 1, line))
+            .collect()
+    }
+
+    // -U: run the pattern over the whole buffer so matches can span lines,
+    // then report every line a match touches. Matches whose lines overlap
+    // are handed to the printer as one block.
+    fn search_multiline(
+        &self,
+        content: &str,
+        regexes: &RegexSet,
+        printer: &mut dyn Printer,
+        stats: &mut Stats,
+    ) {
+        let max_count = self.max_count.unwrap_or(usize::MAX);
+        if self.invert_match {
+            let unmatched = self.unmatched_lines(content, regexes);
+            for (line_number, line) in unmatched.into_iter().take(max_count) {
+                stats.matched_lines += 1;
+                printer.matched(&MatchedLines {
+                    line_number,
+                    offset: offset_in(content, line),
+                    text: line,
+                    matches: &[],
+                });
+            }
+            return;
+        }
+
+        let matches = self.multiline_matches(content, regexes);
+
+        let mut line_number = 1;
+        let mut counted_to = 0;
+        let mut lines_reported = 0;
+        for (block_start, block_end, range) in Searcher::blocks(content, &matches) {
+            // -m counts the lines of whole blocks, so the last block may
+            // run past it
+            if lines_reported >= max_count {
+                break;
+            }
+            count_matches(&matches[range.clone()], stats);
+            line_number += content[counted_to..block_start].matches('\n').count();
+            counted_to = block_start;
+            let text = &content[block_start..block_end];
+            let block: Vec<MatchSpan> = matches[range]
+                .iter()
+                .map(|m| MatchSpan {
+                    span: m.span.start - block_start..m.span.end - block_start,
+                    ..m.clone()
+                })
+                .collect();
+            // -o and --offsets print a line per match rather than per line
+            stats.matched_lines += if printer.per_match() {
+                block.len()
+            } else {
+                text.split('\n').count()
+            };
+            lines_reported += text.split('\n').count();
+            printer.matched(&MatchedLines {
+                line_number,
+                offset: block_start,
+                text,
+                matches: &block,
+            });
+        }
+    }
+}
+
+// Where a line-by-line search stands between batches of input
+struct LineState {
+    // Lines since the last before-context delimiter or reported line, at
+    // most before_context of them: line number, offset and text
+    pending: VecDeque<(usize, usize, String)>,
+    after_left: usize,    // lines of after-context still to print
+    selected_left: usize, // lines -m still allows
+    lines: usize,         // lines in earlier batches
+    offset: usize,        // bytes in earlier batches
+}
+
+impl LineState {
+    fn new(searcher: &Searcher) -> LineState {
+        LineState {
+            pending: VecDeque::new(),
+            after_left: 0,
+            selected_left: searcher.max_count.unwrap_or(usize::MAX),
+            lines: 0,
+            offset: 0,
+        }
+    }
+}
+
+// Hands `inner` events with line numbers and offsets moved past `at`
+struct Shifted<'p> {
+    inner: &'p mut dyn Printer,
+    at: Position,
+}
+
+impl Shifted<'_> {
+    fn shift<'a>(&self, event: &MatchedLines<'a>) -> MatchedLines<'a> {
+        MatchedLines {
+            line_number: self.at.lines + event.line_number,
+            offset: self.at.offset + event.offset,
+            ..event.clone()
+        }
+    }
+}
+
+impl Printer for Shifted<'_> {
+    fn begin(&mut self, path: Option<&Path>) {
+        self.inner.begin(path);
+    }
+
+    fn binary(&mut self, path: Option<&Path>) {
+        self.inner.binary(path);
+    }
+
+    fn matched(&mut self, event: &MatchedLines) {
+        let event = self.shift(event);
+        self.inner.matched(&event);
+    }
+
+    fn context(&mut self, event: &MatchedLines) {
+        let event = self.shift(event);
+        self.inner.context(&event);
+    }
+
+    fn end(&mut self) {
+        self.inner.end();
+    }
+
+    fn finish(&mut self) {
+        self.inner.finish();
+    }
+
+    fn per_match(&self) -> bool {
+        self.inner.per_match()
+    }
+}
+
+// Iterator returned by Searcher::find_iter
+pub struct FindIter<'s, 't> {
+    searcher: &'s Searcher,
+    regexes: &'s RegexSet,
+    text: &'t str,
+    pos: usize,
+    last_end: Option<usize>,
+}
+
+impl FindIter<'_, '_> {
+    fn step(&mut self) {
+        match self.searcher.next_step(&self.text[self.pos..]) {
+            Some(len) => self.pos += len,
+            None => self.pos = self.text.len() + 1,
+        }
+    }
+
+    // Moves up to byte `candidate`, stepping over whole clusters in grapheme
+    // mode so a match never starts inside one
+    fn skip_to(&mut self, candidate: usize) {
+        if self.searcher.graphemes && !self.searcher.ascii {
+            while self.pos < candidate {
+                self.step();
+            }
+        } else {
+            self.pos = candidate;
+        }
+    }
+}
+
+impl Iterator for FindIter<'_, '_> {
+    type Item = MatchSpan;
+
+    fn next(&mut self) -> Option<MatchSpan> {
+        while self.pos <= self.text.len() && !self.searcher.cancel.is_cancelled() {
+            // Unless matches must start on cluster boundaries, skip straight
+            // to the next match instead of trying every position
+            let (start, m) = if self.regexes.can_find_from() {
+                match self.regexes.find_from(self.text, self.pos) {
+                    Some(found) => found,
+                    None => break,
+                }
+            } else {
+                // Nothing can match before the next place the pattern's
+                // required prefix occurs
+                let Some(candidate) = self.regexes.next_candidate(self.text, self.pos) else {
                     break;
+                };
+                self.skip_to(candidate);
+                match self.regexes.find_at(self.text, self.pos) {
+                    Some(m) => (self.pos, m),
+                    None => {
+                        self.step();
+                        continue;
+                    }
                 }
+            };
+            self.pos = start;
+            let end = start + m.text.len();
+            if start == end && self.last_end == Some(start) {
+                self.step();
+                continue;
             }
+            self.last_end = Some(end);
+            if end > start {
+                self.pos = end;
+            } else {
+                self.step();
+            }
+            return Some(MatchSpan {
+                span: start..end,
+                pattern: m.pattern,
+                groups: m.groups,
+            });
         }
+        None
+    }
+}
+
+fn is_delimiter(until: &Option<Regex>, line: &str) -> bool {
+    until.as_ref().is_some_and(|re| re.is_match(line))
+}
+
+// Whether a match of `join` ends `line` without starting it, as `\\$` does
+// on a line ending in a backslash
+fn continues_onto_next(join: &Regex, line: &str) -> bool {
+    join.find_overlapping_iter(line)
+        .any(|span| span.start > 0 && span.end == line.len())
+}
+
+fn count_matches(matches: &[MatchSpan], stats: &mut Stats) {
+    stats.matches += matches.len();
+    for m in matches {
+        stats.per_pattern[m.pattern] += 1;
+    }
+}
+
+// Byte offset of `part` within `whole`; `part` must be a subslice of it
+fn offset_in(whole: &str, part: &str) -> usize {
+    part.as_ptr() as usize - whole.as_ptr() as usize
+}
+
+fn line_start(content: &str, pos: usize) -> usize {
+    content[..pos].rfind('\n').map_or(0, |i| i + 1)
+}
+
+// End of the last line a match touches, excluding its newline. A match that
+// ends by consuming a newline does not drag in the following line.
+fn line_end(content: &str, start: usize, end: usize) -> usize {
+    let last_char = if end > start && content.as_bytes()[end - 1] == b'\n' {
+        end - 1
+    } else {
+        end
+    };
+    content[last_char..]
+        .find('\n')
+        .map_or(content.len(), |i| last_char + i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SearcherBuilder;
+    use crate::regex::{ParseOptions, Regex, RegexSet};
+
+    #[test]
+    fn builder_settings_change_how_lines_are_walked() {
+        let regexes = RegexSet::new(&["a".to_string()], &ParseOptions::default()).unwrap();
+        let searcher = SearcherBuilder::new().line_terminator(b'\0').build();
+        let found = searcher.find_matches("xa\0b\0a a\0", &regexes);
+        let lines: Vec<_> = found
+            .iter()
+            .map(|m| (m.line_number, m.span.start))
+            .collect();
+        assert_eq!(lines, [(1, 1), (3, 0), (3, 2)]);
+
+        let searcher = SearcherBuilder::new().max_matches_per_line(Some(1)).build();
+        assert_eq!(searcher.find_matches("aaa\n", &regexes).len(), 1);
+
+        let searcher = SearcherBuilder::new().invert_match(true).build();
+        let mut stats = crate::stats::Stats::new(1);
+        assert!(
+            searcher
+                .search_first(&b"a\nb\n"[..], &regexes, &mut stats)
+                .unwrap()
+        );
+        assert!(
+            !searcher
+                .search_first(&b"a\na\n"[..], &regexes, &mut stats)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn count_mode_stops_at_each_lines_first_match() {
+        let regexes = RegexSet::new(&["o".to_string()], &ParseOptions::default()).unwrap();
+        let cfg = crate::cli::Config {
+            count: true,
+            ..Default::default()
+        };
+        let searcher = super::for_config(&cfg);
+        assert_eq!(searcher.find_matches("foo\nbar\nboo\n", &regexes).len(), 2);
+
+        // --stats reports every match, so they all have to be found
+        let cfg = crate::cli::Config {
+            stats: Some(crate::cli::StatsFormat::Text),
+            ..cfg
+        };
+        let searcher = super::for_config(&cfg);
+        assert_eq!(searcher.find_matches("foo\nbar\nboo\n", &regexes).len(), 4);
+    }
+
+    #[test]
+    fn find_iter_skips_empty_matches_touching_the_previous_match() {
+        let searcher = SearcherBuilder::new().build();
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            let regexes = RegexSet::new(&[pattern.to_string()], &ParseOptions::default()).unwrap();
+            searcher
+                .find_iter(text, &regexes)
+                .map(|m| (m.span.start, m.span.end))
+                .collect()
+        };
+        assert_eq!(spans("a*", "baac"), [(0, 0), (1, 3), (4, 4)]);
+        assert_eq!(spans("a*", "aa"), [(0, 2)]);
+        assert_eq!(spans("x?", ""), [(0, 0)]);
+        assert_eq!(spans("ab", "abab"), [(0, 2), (2, 4)]);
+    }
+
+    #[test]
+    fn invert_match_reports_unmatched_lines_without_spans() {
+        let regexes = RegexSet::new(&["foo".to_string()], &ParseOptions::default()).unwrap();
+        let searcher = SearcherBuilder::new().invert_match(true).build();
+        let mut collect = crate::printer::Collector::default();
+        let mut stats = crate::stats::Stats::new(1);
+        searcher.search(
+            "foo\nbar\nfoo baz\nqux\n",
+            &regexes,
+            None,
+            &mut collect,
+            &mut stats,
+        );
+        let lines: Vec<_> = collect
+            .0
+            .iter()
+            .map(|m| (m.line_number, &*m.text))
+            .collect();
+        assert_eq!(lines, [(2, "bar"), (4, "qux")]);
+        assert!(collect.0.iter().all(|m| m.matches.is_empty()));
+        // The exit status comes from whether any line was selected
+        assert!(stats.matched() && stats.matches == 0);
+
+        let mut stats = crate::stats::Stats::new(1);
+        let mut collect = crate::printer::Collector::default();
+        searcher.search("foo\n", &regexes, None, &mut collect, &mut stats);
+        assert!(!stats.matched());
+    }
 
-        if !use_o && line_has_match {
-            line_buffer.push_str(&line[last_match_end_in_line..]);
-            println!("{prefix}{line_buffer}");
+    #[test]
+    fn invert_segments_reports_what_matches_leave_out() {
+        let regexes = RegexSet::new(&[r"\d+".to_string()], &ParseOptions::default()).unwrap();
+        let searcher = SearcherBuilder::new()
+            .invert_match(true)
+            .invert_segments(true)
+            .build();
+        let mut collect = crate::printer::Collector::default();
+        let mut stats = crate::stats::Stats::new(1);
+        searcher.search(
+            "a1b22c\n123\nxyz\n",
+            &regexes,
+            None,
+            &mut collect,
+            &mut stats,
+        );
+        let segments: Vec<(usize, &str)> = collect
+            .0
+            .iter()
+            .flat_map(|m| {
+                m.matches
+                    .iter()
+                    .map(|s| (m.line_number, &m.text[s.span.clone()]))
+            })
+            .collect();
+        assert_eq!(segments, [(1, "a"), (1, "b"), (1, "c"), (3, "xyz")]);
+    }
+
+    #[test]
+    fn join_continuations_matches_whole_records() {
+        let regexes = RegexSet::new(&["frame".to_string()], &ParseOptions::default()).unwrap();
+        let records = |join: &str, text: &str| -> Vec<(usize, String)> {
+            let join = Regex::new(join, &ParseOptions::default()).unwrap();
+            let searcher = SearcherBuilder::new()
+                .join_continuations(Some(join))
+                .build();
+            searcher
+                .find_matches(text, &regexes)
+                .iter()
+                .map(|m| (m.line_number, m.line.to_string()))
+                .collect()
+        };
+        let log = "ERROR x\n  at frame\nINFO ok\nWARN frame\n";
+        assert_eq!(
+            records("^[ \t]", log),
+            [
+                (1, "ERROR x\n  at frame".to_string()),
+                (4, "WARN frame".to_string())
+            ]
+        );
+        let config = "a = frame \\\n  b\nc = frame\n";
+        assert_eq!(
+            records(r"\\$", config),
+            [
+                (1, "a = frame \\\n  b".to_string()),
+                (3, "c = frame".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn multiline_matches_span_lines() {
+        let pattern = r"fn main\(\)\s*\{\n\s*println";
+        let regexes = RegexSet::new(&[pattern.to_string()], &ParseOptions::default()).unwrap();
+        let searcher = SearcherBuilder::new().multiline(true).build();
+        let mut collect = crate::printer::Collector::default();
+        let mut stats = crate::stats::Stats::new(1);
+        let text = "use x;\nfn main() {\n    println!();\n}\n";
+        searcher.search(text, &regexes, None, &mut collect, &mut stats);
+        let found: Vec<_> = collect
+            .0
+            .iter()
+            .map(|m| (m.line_number, &*m.text))
+            .collect();
+        assert_eq!(found, [(2, "fn main() {\n    println!();")]);
+        assert_eq!(stats.matches, 1);
+    }
+
+    // (line number, whether it was a match) for each line printed
+    struct Lines(Vec<(usize, bool)>);
+
+    impl crate::printer::Printer for Lines {
+        fn matched(&mut self, event: &crate::printer::MatchedLines) {
+            self.0.push((event.line_number, true));
         }
+        fn context(&mut self, event: &crate::printer::MatchedLines) {
+            self.0.push((event.line_number, false));
+        }
+    }
+
+    #[test]
+    fn context_counts_lines_around_matches() {
+        let regexes = RegexSet::new(&["x".to_string()], &ParseOptions::default()).unwrap();
+        let searcher = SearcherBuilder::new()
+            .before_context(Some(2))
+            .after_context(Some(1))
+            .build();
+        let mut lines = Lines(Vec::new());
+        let mut stats = crate::stats::Stats::new(1);
+        let text = "a\nb\nc\nx\nd\nx\ne\nf\ng\n";
+        searcher.search(text, &regexes, None, &mut lines, &mut stats);
+        assert_eq!(
+            lines.0,
+            [
+                (2, false),
+                (3, false),
+                (4, true),
+                (5, false),
+                (6, true),
+                (7, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn context_runs_to_delimiter_lines() {
+        let regexes = RegexSet::new(&["ERROR".to_string()], &ParseOptions::default()).unwrap();
+        let delimiter = || Some(Regex::new("^2024", &ParseOptions::default()).unwrap());
+        let log = "2024 INFO\n  a\n2024 x\n  ERROR b\n  c\n2024 d\n  e\n2024 ERROR f\n";
+        let searcher = SearcherBuilder::new()
+            .before_context_until(delimiter())
+            .after_context_until(delimiter())
+            .build();
+        let mut lines = Lines(Vec::new());
+        let mut stats = crate::stats::Stats::new(1);
+        searcher.search(log, &regexes, None, &mut lines, &mut stats);
+        assert_eq!(
+            lines.0,
+            [(3, false), (4, true), (5, false), (6, false), (8, true)]
+        );
+    }
+
+    #[test]
+    fn max_count_stops_after_the_last_lines_context() {
+        let regexes = RegexSet::new(&["x".to_string()], &ParseOptions::default()).unwrap();
+        let searcher = SearcherBuilder::new()
+            .max_count(Some(2))
+            .after_context(Some(2))
+            .build();
+        let mut lines = Lines(Vec::new());
+        let mut stats = crate::stats::Stats::new(1);
+        let text = "x\nx\na\nx\nb\nx\n";
+        searcher.search(text, &regexes, None, &mut lines, &mut stats);
+        // The third x is only context
+        assert_eq!(lines.0, [(1, true), (2, true), (3, false), (4, false)]);
+        assert_eq!((stats.matched_lines, stats.matches), (2, 2));
+
+        let none = SearcherBuilder::new().max_count(Some(0)).build();
+        let mut stats = crate::stats::Stats::new(1);
+        assert!(
+            !none
+                .search_first("x\n".as_bytes(), &regexes, &mut stats)
+                .unwrap()
+        );
+    }
+
+    // Whether the input was flagged binary, and how many lines matched
+    #[derive(Default)]
+    struct Sniffed(bool, usize);
+
+    impl crate::printer::Printer for Sniffed {
+        fn binary(&mut self, _path: Option<&std::path::Path>) {
+            self.0 = true;
+        }
+        fn matched(&mut self, _event: &crate::printer::MatchedLines) {
+            self.1 += 1;
+        }
+    }
+
+    #[test]
+    fn inputs_with_a_nul_byte_are_binary() {
+        let regexes = RegexSet::new(&["x".to_string()], &ParseOptions::default()).unwrap();
+        let data = b"x\0\xff\nx\n";
+        let search = |searcher: &super::Searcher| {
+            let mut sniffed = Sniffed::default();
+            let mut stats = crate::stats::Stats::new(1);
+            searcher
+                .search_reader(&data[..], &regexes, None, &mut sniffed, &mut stats)
+                .unwrap();
+            (sniffed.0, sniffed.1, stats.files_skipped)
+        };
+        // Invalid UTF-8 in a binary input is read lossily
+        assert_eq!(search(&SearcherBuilder::new().build()), (true, 2, 0));
+        assert_eq!(
+            search(&SearcherBuilder::new().binary(true).build()),
+            (false, 2, 0)
+        );
+        let skip = SearcherBuilder::new().skip_binary(true).build();
+        assert_eq!(search(&skip), (false, 0, 1));
+        let mut stats = crate::stats::Stats::new(1);
+        assert!(!skip.search_first(&data[..], &regexes, &mut stats).unwrap());
+        assert!(
+            skip.search_first(&b"x\n"[..], &regexes, &mut stats)
+                .unwrap()
+        );
+    }
+
+    // Hands out a few bytes per read, then fails if told to
+    struct Trickle<'a>(&'a [u8], bool);
+
+    impl std::io::Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() && self.1 {
+                return Err(std::io::Error::other("connection reset"));
+            }
+            let n = self.0.len().min(buf.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn search_reader_reports_each_batch_as_it_arrives() {
+        let regexes = RegexSet::new(&["x".to_string()], &ParseOptions::default()).unwrap();
+        let searcher = SearcherBuilder::new()
+            .before_context(Some(1))
+            .after_context(Some(1))
+            .build();
+        let text = "a\nb\nx1\nc\n\nd\nx2\nx3\ne";
+        let mut whole = Lines(Vec::new());
+        let mut stats = crate::stats::Stats::new(1);
+        searcher.search(text, &regexes, None, &mut whole, &mut stats);
+        let mut streamed = Lines(Vec::new());
+        let mut stats = crate::stats::Stats::new(1);
+        let reader = Trickle(text.as_bytes(), false);
+        searcher
+            .search_reader(reader, &regexes, None, &mut streamed, &mut stats)
+            .unwrap();
+        assert_eq!(streamed.0, whole.0);
+        assert_eq!((stats.matched_lines, stats.files_matched), (3, 1));
+
+        // What was read before a failure has already been reported
+        let mut collect = crate::printer::Collector::default();
+        let mut stats = crate::stats::Stats::new(1);
+        let reader = Trickle(b"a\nx here\n", true);
+        let read = searcher.search_reader(reader, &regexes, None, &mut collect, &mut stats);
+        assert!(read.is_err());
+        let found: Vec<_> = collect
+            .0
+            .iter()
+            .map(|m| (m.line_number, m.offset, &*m.text))
+            .collect();
+        assert_eq!(found, [(2, 2, "x here")]);
+    }
+
+    #[test]
+    fn search_bytes_at_numbers_lines_from_where_the_piece_starts() {
+        let regexes = RegexSet::new(&["x".to_string()], &ParseOptions::default()).unwrap();
+        let at = super::Position {
+            lines: 2,
+            offset: 10,
+        };
+        for searcher in [
+            SearcherBuilder::new().build(),
+            SearcherBuilder::new().multiline(true).build(),
+        ] {
+            let mut collect = crate::printer::Collector::default();
+            let mut stats = crate::stats::Stats::new(1);
+            searcher
+                .search_bytes_at(b"y\nx 3\n", at, &regexes, None, &mut collect, &mut stats)
+                .unwrap();
+            let found: Vec<_> = collect
+                .0
+                .iter()
+                .map(|m| (m.line_number, m.offset, &*m.text))
+                .collect();
+            assert_eq!(found, [(4, 12, "x 3")]);
+        }
+    }
+
+    #[test]
+    fn list_modes_stop_reading_at_the_first_selected_line() {
+        let regexes = RegexSet::new(&["x".to_string()], &ParseOptions::default()).unwrap();
+        let cfg = crate::cli::Config {
+            files_with_matches: true,
+            ..Default::default()
+        };
+        let searcher = super::for_config(&cfg);
+        let mut collect = crate::printer::Collector::default();
+        let mut stats = crate::stats::Stats::new(1);
+        let reader = Trickle(b"a\nx here\nx again\n", true);
+        searcher
+            .search_reader(reader, &regexes, None, &mut collect, &mut stats)
+            .unwrap();
+        assert_eq!((collect.0.len(), stats.files_matched), (1, 1));
     }
 }
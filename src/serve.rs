@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cli::Config;
+use crate::file_types;
+use crate::fs_walk::collect_files;
+use crate::glob::FileGlobs;
+use crate::index::{INDEX_FILE, Index, required_trigrams};
+use crate::output::display_path;
+use crate::reader::read_file;
+use crate::regex::parser::parse_regex_with;
+use crate::regex::{ParseOptions, Regex, RegexSet};
+use crate::search;
+
+// One search per line of JSON. Only `patterns` and `paths` are required.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Request {
+    patterns: Vec<String>,
+    paths: Vec<PathBuf>,
+    recursive: bool,
+    use_index: bool,
+    path_regexp: Option<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    #[serde(rename = "type")]
+    types: Vec<String>,
+    type_not: Vec<String>,
+    no_unicode: bool,
+    text: bool,
+    max_matches_per_line: Option<usize>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Response {
+    matches: Vec<MatchRecord>,
+    files_searched: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct MatchRecord {
+    path: String,
+    line: usize,
+    text: String,
+    start: usize,
+    end: usize,
+    pattern: usize,
+}
+
+// How many compiled pattern sets are kept; the least recently used is
+// dropped to make room
+const MAX_REGEX_SETS: usize = 64;
+
+// Patterns and whether they're ASCII-only (`no_unicode`)
+type RegexKey = (Vec<String>, bool);
+
+// Compiled patterns and loaded indexes survive between requests. An index is
+// reloaded when its file's mtime changes. Both are built without holding the
+// lock, so a slow one doesn't hold up other connections.
+#[derive(Default)]
+struct Cache {
+    regexes: HashMap<RegexKey, (u64, Arc<RegexSet>)>, // with when each was last used
+    uses: u64,                                        // lookups so far, to order them by
+    indexes: HashMap<PathBuf, (SystemTime, Arc<Index>)>,
+}
+
+impl Cache {
+    // The set compiled for `key`, if it's kept, marked as just used
+    fn regexes(&mut self, key: &RegexKey) -> Option<Arc<RegexSet>> {
+        self.uses += 1;
+        let (last_used, regexes) = self.regexes.get_mut(key)?;
+        *last_used = self.uses;
+        Some(Arc::clone(regexes))
+    }
+
+    fn keep_regexes(&mut self, key: RegexKey, regexes: Arc<RegexSet>) {
+        self.uses += 1;
+        self.regexes.insert(key, (self.uses, regexes));
+        if self.regexes.len() > MAX_REGEX_SETS
+            && let Some(oldest) = self
+                .regexes
+                .iter()
+                .min_by_key(|(_, (last_used, _))| *last_used)
+                .map(|(key, _)| key.clone())
+        {
+            self.regexes.remove(&oldest);
+        }
+    }
+}
+
+// `rust-grep serve --socket PATH` or `rust-grep serve --tcp ADDR`
+pub fn subcommand(args: Vec<OsString>) -> ! {
+    let args: Vec<String> = args
+        .iter()
+        .skip(2)
+        .map(|a| a.to_string_lossy().into_owned())
+        .collect();
+    let cache = Arc::new(Mutex::new(Cache::default()));
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["--socket", path] => serve_unix(path, cache),
+        ["--tcp", addr] => serve_tcp(addr, cache),
+        _ => {
+            eprintln!("rust-grep: usage: serve --socket PATH | serve --tcp ADDR");
+            process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("rust-grep: serve: {e}");
+    }
+    process::exit(2);
+}
+
+#[cfg(unix)]
+fn serve_unix(path: &str, cache: Arc<Mutex<Cache>>) -> io::Result<()> {
+    use std::os::unix::net::UnixListener;
+
+    // A socket left behind by a previous server would make bind fail
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let cache = Arc::clone(&cache);
+        thread::spawn(move || {
+            if let Ok(reader) = stream.try_clone() {
+                let _ = handle(BufReader::new(reader), stream, &cache);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn serve_unix(_path: &str, _cache: Arc<Mutex<Cache>>) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix sockets are not available",
+    ))
+}
+
+fn serve_tcp(addr: &str, cache: Arc<Mutex<Cache>>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let cache = Arc::clone(&cache);
+        thread::spawn(move || {
+            if let Ok(reader) = stream.try_clone() {
+                let _ = handle(BufReader::new(reader), stream, &cache);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle(reader: impl BufRead, mut writer: impl Write, cache: &Mutex<Cache>) -> io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => search(&request, cache),
+            Err(e) => Response {
+                error: Some(e.to_string()),
+                ..Default::default()
+            },
+        };
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+fn search(request: &Request, cache: &Mutex<Cache>) -> Response {
+    if request.patterns.is_empty() {
+        return Response {
+            error: Some("no patterns given".to_string()),
+            ..Default::default()
+        };
+    }
+    let parse_opts = ParseOptions {
+        ascii: request.no_unicode,
+        ..Default::default()
+    };
+    let invalid = request
+        .patterns
+        .iter()
+        .chain(&request.path_regexp)
+        .find_map(|p| parse_regex_with(p, &parse_opts).err());
+    if let Some(e) = invalid {
+        return Response {
+            error: Some(e.to_string()),
+            ..Default::default()
+        };
+    }
+    let globs = match file_globs(request) {
+        Ok(globs) => globs,
+        Err(e) => {
+            return Response {
+                error: Some(e),
+                ..Default::default()
+            };
+        }
+    };
+    let cfg = Config {
+        patterns: request.patterns.clone(),
+        paths: request.paths.clone(),
+        recursive: request.recursive,
+        no_unicode: request.no_unicode,
+        text: request.text,
+        max_matches_per_line: request.max_matches_per_line,
+        globs,
+        ..Default::default()
+    };
+    let key = (cfg.patterns.clone(), cfg.no_unicode);
+    let cached = cache.lock().unwrap().regexes(&key);
+    let regexes = cached.unwrap_or_else(|| {
+        let regexes =
+            Arc::new(RegexSet::new(&cfg.patterns, &parse_opts).expect("patterns were checked"));
+        cache
+            .lock()
+            .unwrap()
+            .keep_regexes(key, Arc::clone(&regexes));
+        regexes
+    });
+    let path_filter = request
+        .path_regexp
+        .as_deref()
+        .map(|p| Regex::new(p, &parse_opts).expect("patterns were checked"));
+    let required: Vec<_> = cfg
+        .patterns
+        .iter()
+        .map(|pattern| required_trigrams(pattern, &parse_opts))
+        .collect();
+
+    let searcher = search::for_config(&cfg);
+    let mut response = Response::default();
+    for root in &cfg.paths {
+        let index = if request.use_index {
+            load_index(cache, root)
+        } else {
+            None
+        };
+        for path in collect_files(
+            root,
+            cfg.recursive,
+            cfg.follow_links,
+            cfg.devices,
+            &cfg.globs,
+            &cfg.cancel,
+        ) {
+            if path_filter
+                .as_ref()
+                .is_some_and(|re| !re.is_match(&display_path(&path)))
+                || index
+                    .as_ref()
+                    .is_some_and(|ix| !ix.is_candidate(root, &path, &required))
+            {
+                continue;
+            }
+            let Ok(content) = read_file(&path, cfg.text) else {
+                continue;
+            };
+            response.files_searched += 1;
+            for m in searcher.find_matches(&content, &regexes) {
+                response.matches.push(MatchRecord {
+                    path: display_path(&path).into_owned(),
+                    line: m.line_number,
+                    text: m.line.to_string(),
+                    start: m.span.start,
+                    end: m.span.end,
+                    pattern: m.pattern,
+                });
+            }
+        }
+    }
+    response
+}
+
+fn load_index(cache: &Mutex<Cache>, root: &PathBuf) -> Option<Arc<Index>> {
+    let modified = fs::metadata(root.join(INDEX_FILE))
+        .and_then(|m| m.modified())
+        .ok()?;
+    if let Some((loaded_at, index)) = cache.lock().unwrap().indexes.get(root)
+        && *loaded_at == modified
+    {
+        return Some(Arc::clone(index));
+    }
+    let index = Arc::new(Index::load(root)?);
+    cache
+        .lock()
+        .unwrap()
+        .indexes
+        .insert(root.clone(), (modified, Arc::clone(&index)));
+    Some(index)
+}
+
+// The request's `include`, `exclude`, `type` and `type_not`, as the
+// options of the same names combine them
+fn file_globs(request: &Request) -> Result<FileGlobs, String> {
+    let type_globs = |names: &[String]| -> Result<Vec<String>, String> {
+        let mut globs = Vec::new();
+        for name in names {
+            let file_type =
+                file_types::lookup(name).ok_or_else(|| format!("unknown file type '{name}'"))?;
+            globs.extend(file_type.globs.iter().map(|g| g.to_string()));
+        }
+        Ok(globs)
+    };
+    let mut globs = FileGlobs {
+        include: request.include.clone(),
+        exclude: request.exclude.clone(),
+        types: type_globs(&request.types)?,
+    };
+    globs.exclude.extend(type_globs(&request.type_not)?);
+    Ok(globs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, MAX_REGEX_SETS, handle};
+    use crate::regex::{ParseOptions, RegexSet};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn answers_each_request_line_with_json() {
+        let dir = std::env::temp_dir().join(format!("rust-grep-serve-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "one\nfound it\n").unwrap();
+
+        let request = format!(
+            "{{\"patterns\": [\"it\"], \"paths\": [{:?}], \"recursive\": true}}\nnot json\n",
+            dir.to_str().unwrap()
+        );
+        let mut out = Vec::new();
+        handle(request.as_bytes(), &mut out, &Mutex::new(Cache::default())).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["files_searched"], 1);
+        assert_eq!(lines[0]["matches"][0]["line"], 2);
+        assert_eq!(lines[0]["matches"][0]["text"], "found it");
+        assert_eq!(lines[0]["matches"][0]["start"], 6);
+        assert!(lines[1]["error"].is_string());
+    }
+
+    #[test]
+    fn requests_filter_files_by_glob_and_type() {
+        let dir =
+            std::env::temp_dir().join(format!("rust-grep-serve-globs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["a.rs", "b.txt", "c.log"] {
+            std::fs::write(dir.join(name), "needle\n").unwrap();
+        }
+        let search = |filters: &str| -> serde_json::Value {
+            let request = format!(
+                "{{\"patterns\": [\"needle\"], \"paths\": [{:?}], \"recursive\": true, {filters}}}\n",
+                dir.to_str().unwrap()
+            );
+            let mut out = Vec::new();
+            handle(request.as_bytes(), &mut out, &Mutex::new(Cache::default())).unwrap();
+            serde_json::from_slice(&out).unwrap()
+        };
+        assert_eq!(
+            search(r#""include": ["*.rs", "*.txt"]"#)["files_searched"],
+            2
+        );
+        assert_eq!(search(r#""exclude": ["*.rs"]"#)["files_searched"], 2);
+        assert_eq!(search(r#""type": ["log"]"#)["files_searched"], 1);
+        assert_eq!(
+            search(r#""type_not": ["rust", "txt"]"#)["files_searched"],
+            1
+        );
+        assert!(search(r#""type": ["nope"]"#)["error"].is_string());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn least_recently_used_pattern_sets_are_dropped() {
+        let mut cache = Cache::default();
+        let key = |i: usize| (vec![format!("p{i}")], false);
+        let set = Arc::new(RegexSet::new(&["p".to_string()], &ParseOptions::default()).unwrap());
+        for i in 0..MAX_REGEX_SETS {
+            cache.keep_regexes(key(i), Arc::clone(&set));
+        }
+        // Using the oldest set saves it; the next oldest goes instead
+        assert!(cache.regexes(&key(0)).is_some());
+        cache.keep_regexes(key(MAX_REGEX_SETS), Arc::clone(&set));
+        assert_eq!(cache.regexes.len(), MAX_REGEX_SETS);
+        assert!(cache.regexes(&key(0)).is_some());
+        assert!(cache.regexes(&key(1)).is_none());
+    }
+}
@@ -0,0 +1,96 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+use serde_json::{Value, json};
+
+// Counters collected while searching, reported by --stats
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub matches: usize,
+    pub matched_lines: usize,
+    pub files_searched: usize,
+    pub files_matched: usize,
+    pub files_skipped: usize, // unreadable, or ruled out by --cache
+    pub bytes_searched: usize,
+    pub per_pattern: Vec<usize>, // matches attributed to each pattern
+}
+
+impl Stats {
+    pub fn new(pattern_count: usize) -> Stats {
+        Stats {
+            per_pattern: vec![0; pattern_count],
+            ..Default::default()
+        }
+    }
+
+    pub fn matched(&self) -> bool {
+        self.matched_lines > 0
+    }
+
+    // Human-readable summary, on stderr unless --stats-file says otherwise
+    // so it never mixes with results
+    pub fn write_text(
+        &self,
+        out: &mut dyn Write,
+        labels: &[String],
+        elapsed: Duration,
+    ) -> io::Result<()> {
+        writeln!(out, "{} matches", self.matches)?;
+        writeln!(out, "{} matched lines", self.matched_lines)?;
+        writeln!(out, "{} files contained matches", self.files_matched)?;
+        writeln!(out, "{} files searched", self.files_searched)?;
+        if self.files_skipped > 0 {
+            writeln!(out, "{} files skipped", self.files_skipped)?;
+        }
+        writeln!(out, "{} bytes searched", self.bytes_searched)?;
+        writeln!(out, "{:.6} seconds", elapsed.as_secs_f64())?;
+        if labels.len() > 1 {
+            for (label, count) in labels.iter().zip(&self.per_pattern) {
+                writeln!(out, "{count} matches for pattern {label}")?;
+            }
+        }
+        Ok(())
+    }
+
+    // --stats=json: the same counters as one object. Per-pattern counts are
+    // always included, in pattern order.
+    pub fn to_json(&self, labels: &[String], elapsed: Duration) -> Value {
+        let per_pattern: Vec<Value> = labels
+            .iter()
+            .zip(&self.per_pattern)
+            .map(|(label, count)| json!({ "pattern": label, "matches": count }))
+            .collect();
+        json!({
+            "matches": self.matches,
+            "matched_lines": self.matched_lines,
+            "files_matched": self.files_matched,
+            "files_searched": self.files_searched,
+            "files_skipped": self.files_skipped,
+            "bytes_searched": self.bytes_searched,
+            "elapsed_seconds": elapsed.as_secs_f64(),
+            "per_pattern": per_pattern,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stats;
+    use std::time::Duration;
+
+    #[test]
+    fn json_has_every_counter() {
+        let mut stats = Stats::new(2);
+        stats.matches = 3;
+        stats.files_searched = 2;
+        stats.files_skipped = 1;
+        stats.per_pattern = vec![2, 1];
+        let labels = ["error".to_string(), "warn".to_string()];
+        let json = stats.to_json(&labels, Duration::from_millis(1500));
+        assert_eq!(json["matches"], 3);
+        assert_eq!(json["files_skipped"], 1);
+        assert_eq!(json["elapsed_seconds"], 1.5);
+        assert_eq!(json["per_pattern"][1]["pattern"], "warn");
+        assert_eq!(json["per_pattern"][1]["matches"], 1);
+    }
+}
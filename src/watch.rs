@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::app::{path_matches, report};
+use crate::cli::Config;
+use crate::fs_walk::collect_files;
+use crate::printer::{self, Printer};
+use crate::reader::read_bytes;
+use crate::regex::{Regex, RegexSet};
+use crate::search::{self, Position, Searcher};
+use crate::stats::Stats;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// --watch: search everything once, then poll the paths and only search what
+// was appended since (tail -F semantics). A file that shrinks was truncated or
+// rotated and is searched again from the start.
+pub fn watch(
+    cfg: &Config,
+    regexes: &RegexSet,
+    path_filter: Option<&Regex>,
+    use_color: bool,
+    show_filename: bool,
+) -> ! {
+    let mut watcher = Watcher {
+        regexes,
+        searcher: search::for_config(cfg),
+        printer: printer::for_config(cfg, regexes, use_color, show_filename),
+        offsets: HashMap::new(),
+        failed: HashMap::new(),
+        stats: Stats::new(regexes.len()),
+    };
+    let mut first_pass = true;
+
+    loop {
+        for p in &cfg.paths {
+            for path in collect_files(
+                p,
+                cfg.recursive,
+                cfg.follow_links,
+                cfg.devices,
+                &cfg.globs,
+                &cfg.cancel,
+            ) {
+                if path_matches(path_filter, &path) {
+                    watcher.poll(path, first_pass);
+                }
+            }
+        }
+        first_pass = false;
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+struct Watcher<'a> {
+    regexes: &'a RegexSet,
+    searcher: Searcher,
+    printer: Box<dyn Printer + 'a>,
+    offsets: HashMap<PathBuf, Position>, // how far each file has been searched
+    failed: HashMap<PathBuf, u64>,       // length of files whose error was reported
+    stats: Stats,
+}
+
+impl Watcher<'_> {
+    fn poll(&mut self, path: PathBuf, first_pass: bool) {
+        let Ok(len) = fs::metadata(&path).map(|m| m.len()) else {
+            return;
+        };
+        // A file that couldn't be read or decoded is tried again once it
+        // changes, rather than every poll
+        if self.failed.get(&path) == Some(&len) {
+            return;
+        }
+        let start = match self.offsets.get(&path) {
+            Some(at) if at.offset as u64 == len => return,
+            Some(&at) if (at.offset as u64) < len => at,
+            _ => Position::default(),
+        };
+
+        // Files present at startup are searched in full like a normal run;
+        // afterwards only complete lines are taken so a line still being
+        // written is reported once it is finished
+        match self.search_from(&path, start, !first_pass) {
+            Ok(end) => {
+                self.failed.remove(&path);
+                self.offsets.insert(path, end);
+            }
+            Err(e) => {
+                if self.failed.insert(path.clone(), len).is_none() {
+                    report(&path, &e);
+                }
+            }
+        }
+    }
+
+    // Searches `path` from `start` on, numbering lines and offsets from
+    // there, and returns where the search got to
+    fn search_from(
+        &mut self,
+        path: &Path,
+        start: Position,
+        complete_lines_only: bool,
+    ) -> io::Result<Position> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(start.offset as u64))?;
+        let mut data = read_bytes(file)?;
+
+        if complete_lines_only {
+            match data.iter().rposition(|&b| b == b'\n') {
+                Some(idx) => data.truncate(idx + 1),
+                None => return Ok(start),
+            }
+        }
+        self.searcher.search_bytes_at(
+            &data,
+            start,
+            self.regexes,
+            Some(path),
+            self.printer.as_mut(),
+            &mut self.stats,
+        )?;
+        // Counted in the file's own bytes, which lossy decoding doesn't keep
+        Ok(Position {
+            lines: start.lines + data.iter().filter(|&&b| b == b'\n').count(),
+            offset: start.offset + data.len(),
+        })
+    }
+}